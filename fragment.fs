@@ -1,8 +1,8 @@
 #version 330 core
 out vec4 FragColor;
-uniform float our_color;
 in vec2 TexCoord;
 in vec3 Normal;
+in vec4 Tangent;
 
 struct Material {
 	vec3 ambient;
@@ -11,61 +11,60 @@ struct Material {
 	float shininess;
 };
 
-struct PointLight {
-	vec3 position;
-
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-	
-    float constant;
-    float linear;
-    float quadratic;
-};
+#include "lights.glsl"
+#include "normal_map.glsl"
 
 uniform Material material;
-uniform PointLight point_light;
 
 uniform sampler2D our_texture;
+// Each map is only sampled when its flag is set.
+// Bump maps are read as tangent space normal maps, like most exporters write them.
+uniform sampler2D specular_map;
+uniform sampler2D bump_map;
+uniform int has_specular_map;
+uniform int has_bump_map;
 uniform vec3 camera_pos;
 
 in vec3 FragPos;
 
+vec3 blinn_phong(Light light, vec3 norm, vec3 view_dir, vec3 specular_color) {
+	// FragPos (position of hit) to light
+	vec3 light_dir = light_direction(light, FragPos);
+	float attenuation = light_attenuation(light, FragPos, light_dir);
+
+	vec3 ambient = light.ambient * material.ambient;
+
+	// Check how 'close' the normal and the light is
+	// the closer, the more light it has
+	float diff = max(dot(norm, light_dir), 0.0);
+	vec3 diffuse = diff * light.diffuse * material.diffuse;
+
+	vec3 halfway_dir = normalize(light_dir + view_dir);
+	float spec = pow(max(dot(norm, halfway_dir), 0.0), material.shininess);
+	// No highlights on faces pointing away from the light
+	if (diff == 0.0) {
+		spec = 0.0;
+	}
+	vec3 specular = (spec * specular_color) * light.specular;
+
+	// Shadows leave the ambient light alone
+	float shadow = shadow_factor(light, FragPos, norm, light_dir, length(camera_pos - FragPos));
+	return (ambient + (1.0 - shadow) * (diffuse + specular)) * attenuation;
+}
 
 void main() {
-	// float dist = length(point_light.position - FragPos);
-	// float attenuation = 
-	// 	1.0 / (point_light.constant + point_light.linear * dist + 
-	// 		point_light.quadratic * (dist * dist));
-
-	// // Ensure normal is actually a normal, lol
-	// vec3 norm = normalize(Normal);
-
-	// // FragPos (position of hit) to light
-	// vec3 light_dir = normalize(point_light.position - FragPos);
-
-	// // Check how 'close' the normal and the light is
-	// // the closer, the more light it has
-	// vec3 ambient = point_light.ambient * material.ambient;
-
-	// float diff = max(dot(norm, light_dir), 0.0);
-	// vec3 diffuse = diff * point_light.diffuse * material.diffuse;
-
-	// vec3 view_dir = normalize(camera_pos - FragPos);
-	// vec3 reflect_dir = reflect(-light_dir, norm);
-
-	// float spec = pow(max(dot(view_dir, reflect_dir), 0.0), material.shininess);
-
-	// vec3 specular = (spec * material.specular) * point_light.specular;
-
-	// // ambient  *= attenuation;
-	// // diffuse  *= attenuation;
-	// // specular *= attenuation;
-
-	// vec3 eqn = ambient + diffuse + specular;
-	vec3 eqn = vec3(1.0);
+	vec3 norm = surface_normal(Normal, Tangent, TexCoord, has_bump_map, bump_map, 1.0);
+	vec3 view_dir = normalize(camera_pos - FragPos);
+	vec3 specular_color = material.specular;
+	if (has_specular_map != 0) {
+		specular_color *= texture(specular_map, TexCoord).rgb;
+	}
+
+	vec3 eqn = vec3(0.0);
+	for (int i = 0; i < light_count; i++) {
+		eqn += blinn_phong(lights[i], norm, view_dir, specular_color);
+	}
 
 	vec3 result = (eqn) * vec3(texture(our_texture, TexCoord));
 	FragColor = vec4(result, 1.0);
-	// FragColor = vec4(1.0);
 }
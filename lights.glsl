@@ -0,0 +1,128 @@
+// Lights and their shadows, shared by the lit shaders.
+// MAX_LIGHTS, MAX_SHADOW_MAPS and MAX_SHADOW_CUBE_MAPS are defined by the engine.
+
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+struct Light {
+	int kind;
+
+	vec3 position;
+	vec3 direction;
+
+	vec3 ambient;
+	vec3 diffuse;
+	vec3 specular;
+
+	float constant;
+	float linear;
+	float quadratic;
+
+	// Cosines of the spot light's inner and outer cone
+	float cutoff;
+	float outer_cutoff;
+
+	// Index into the shadow maps of the light's kind, -1 when it casts none
+	int shadow;
+	mat4 light_space;
+	float far_plane;
+	float shadow_bias;
+};
+
+uniform Light lights[MAX_LIGHTS];
+uniform int light_count;
+
+// Point lights use the cube maps, other lights the regular ones
+uniform sampler2D shadow_maps[MAX_SHADOW_MAPS];
+uniform samplerCube shadow_cube_maps[MAX_SHADOW_CUBE_MAPS];
+
+// From the fragment towards the light
+vec3 light_direction(Light light, vec3 frag_pos) {
+	if (light.kind == DIRECTIONAL_LIGHT) {
+		return normalize(-light.direction);
+	}
+	return normalize(light.position - frag_pos);
+}
+
+float light_attenuation(Light light, vec3 frag_pos, vec3 light_dir) {
+	if (light.kind == DIRECTIONAL_LIGHT) {
+		return 1.0;
+	}
+	float dist = length(light.position - frag_pos);
+	float attenuation = 1.0 / (light.constant + light.linear * dist +
+		light.quadratic * (dist * dist));
+
+	// Fade out towards the edge of a spot light's cone
+	if (light.kind == SPOT_LIGHT) {
+		float theta = dot(light_dir, normalize(-light.direction));
+		float epsilon = max(light.cutoff - light.outer_cutoff, 0.0001);
+		attenuation *= clamp((theta - light.outer_cutoff) / epsilon, 0.0, 1.0);
+	}
+	return attenuation;
+}
+
+// Average of a 3x3 block of depth comparisons, for softer edges
+float pcf_2d(sampler2D map, vec4 light_space_pos, float bias) {
+	vec3 coords = light_space_pos.xyz / light_space_pos.w * 0.5 + 0.5;
+	// Anything the shadow map doesn't cover is lit
+	if (coords.z > 1.0 || any(lessThan(coords.xy, vec2(0.0))) ||
+		any(greaterThan(coords.xy, vec2(1.0)))) {
+		return 0.0;
+	}
+	vec2 texel = 1.0 / vec2(textureSize(map, 0));
+	float shadow = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			float closest = texture(map, coords.xy + vec2(x, y) * texel).r;
+			shadow += coords.z - bias > closest ? 1.0 : 0.0;
+		}
+	}
+	return shadow / 9.0;
+}
+
+const vec3 cube_offsets[20] = vec3[](
+	vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+	vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+	vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+	vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+	vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+// Cube maps hold the distance to the light divided by the far plane
+float pcf_cube(samplerCube map, vec3 light_to_frag, float far_plane, float bias, float view_distance) {
+	float depth = length(light_to_frag) / far_plane;
+	if (depth > 1.0) {
+		return 0.0;
+	}
+	// Spread the samples further apart the further away we are
+	float radius = (1.0 + view_distance / far_plane) / 25.0;
+	float shadow = 0.0;
+	for (int i = 0; i < 20; i++) {
+		float closest = texture(map, light_to_frag + cube_offsets[i] * radius).r;
+		shadow += depth - bias > closest ? 1.0 : 0.0;
+	}
+	return shadow / 20.0;
+}
+
+// Samplers can only be indexed by constants, so the engine replaces the next
+// line with functions picking a map through one branch per shadow map:
+//   float sample_shadow_map(int slot, vec4 light_space_pos, float bias)
+//   float sample_shadow_cube_map(int slot, vec3 light_to_frag, float far_plane, float bias, float view_distance)
+#shadow_lookups
+
+// How much of the light is blocked, from 0 to 1
+float shadow_factor(Light light, vec3 frag_pos, vec3 norm, vec3 light_dir, float view_distance) {
+	if (light.shadow < 0) {
+		return 0.0;
+	}
+	// Surfaces at a steep angle to the light need more bias
+	float bias = max(light.shadow_bias * (1.0 - dot(norm, light_dir)), light.shadow_bias * 0.1);
+
+	if (light.kind == POINT_LIGHT) {
+		vec3 light_to_frag = frag_pos - light.position;
+		return sample_shadow_cube_map(light.shadow, light_to_frag, light.far_plane, bias, view_distance);
+	}
+	vec4 light_space_pos = light.light_space * vec4(frag_pos, 1.0);
+	return sample_shadow_map(light.shadow, light_space_pos, bias);
+}
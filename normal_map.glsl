@@ -0,0 +1,16 @@
+// Normal mapping, shared by the lit shaders
+
+// The interpolated normal, perturbed by a tangent space normal map if `has_map` is set.
+// `scale` scales the map's x and y, like the normal scale of glTF materials.
+// Meshes without tangents have them all set to zero, and keep their normal.
+vec3 surface_normal(vec3 normal, vec4 tangent, vec2 uv, int has_map, sampler2D map, float scale) {
+	vec3 norm = normalize(normal);
+	if (has_map == 0 || dot(tangent.xyz, tangent.xyz) == 0.0) {
+		return norm;
+	}
+	vec3 t = normalize(tangent.xyz - norm * dot(norm, tangent.xyz));
+	vec3 bitangent = cross(norm, t) * tangent.w;
+	vec3 sampled = texture(map, uv).xyz * 2.0 - 1.0;
+	sampled.xy *= scale;
+	return normalize(mat3(t, bitangent, norm) * sampled);
+}
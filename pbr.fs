@@ -0,0 +1,121 @@
+#version 330 core
+out vec4 FragColor;
+in vec2 TexCoord;
+in vec3 Normal;
+in vec4 Tangent;
+in vec3 FragPos;
+
+#include "lights.glsl"
+#include "normal_map.glsl"
+
+// Metallic-roughness material, factors are multiplied with their maps like in glTF
+struct PbrMaterial {
+	vec4 base_color;
+	float metallic;
+	float roughness;
+	float normal_scale;
+	float occlusion_strength;
+	vec3 emissive;
+};
+
+uniform PbrMaterial material;
+
+// Each map is only sampled when its flag is set
+uniform sampler2D base_color_map;
+uniform sampler2D metallic_map;
+uniform sampler2D roughness_map;
+uniform sampler2D normal_map;
+uniform sampler2D occlusion_map;
+uniform sampler2D emissive_map;
+uniform int has_base_color_map;
+uniform int has_metallic_map;
+uniform int has_roughness_map;
+uniform int has_normal_map;
+uniform int has_occlusion_map;
+uniform int has_emissive_map;
+
+uniform vec3 camera_pos;
+
+const float PI = 3.14159265359;
+
+// How many microfacets line up with the halfway vector (Trowbridge-Reitz GGX)
+float distribution_ggx(float n_dot_h, float roughness) {
+	float a = roughness * roughness;
+	float a2 = a * a;
+	float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
+
+// How much of the microfacets shadow each other (Schlick-GGX)
+float geometry_schlick_ggx(float n_dot_x, float roughness) {
+	float r = roughness + 1.0;
+	float k = (r * r) / 8.0;
+	return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+	return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Light reflected towards the viewer, leaving out the ambient part
+vec3 cook_torrance(Light light, vec3 norm, vec3 view_dir, vec3 albedo, float metallic, float roughness) {
+	vec3 light_dir = light_direction(light, FragPos);
+	vec3 halfway_dir = normalize(light_dir + view_dir);
+	float n_dot_l = max(dot(norm, light_dir), 0.0);
+	float n_dot_v = max(dot(norm, view_dir), 0.0001);
+
+	// Dielectrics all reflect about 4% head on, metals reflect their own color
+	vec3 f0 = mix(vec3(0.04), albedo, metallic);
+	vec3 fresnel = fresnel_schlick(max(dot(halfway_dir, view_dir), 0.0), f0);
+	float ndf = distribution_ggx(max(dot(norm, halfway_dir), 0.0), roughness);
+	float geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+	vec3 specular = (ndf * geometry * fresnel) / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+
+	// Whatever isn't reflected is refracted, and metals absorb all of that
+	vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - metallic) * albedo / PI;
+
+	float attenuation = light_attenuation(light, FragPos, light_dir);
+	float shadow = shadow_factor(light, FragPos, norm, light_dir, length(camera_pos - FragPos));
+	return (1.0 - shadow) * (diffuse + specular) * light.diffuse * attenuation * n_dot_l;
+}
+
+void main() {
+	vec4 base_color = material.base_color;
+	if (has_base_color_map == 1) {
+		base_color *= texture(base_color_map, TexCoord);
+	}
+	// Metalness is in the blue channel and roughness in the green one,
+	// so a single glTF metallic-roughness texture can serve as both maps.
+	float metallic = material.metallic;
+	if (has_metallic_map == 1) {
+		metallic *= texture(metallic_map, TexCoord).b;
+	}
+	float roughness = material.roughness;
+	if (has_roughness_map == 1) {
+		roughness *= texture(roughness_map, TexCoord).g;
+	}
+	roughness = clamp(roughness, 0.04, 1.0);
+	float occlusion = 1.0;
+	if (has_occlusion_map == 1) {
+		occlusion = mix(1.0, texture(occlusion_map, TexCoord).r, material.occlusion_strength);
+	}
+	vec3 emissive = material.emissive;
+	if (has_emissive_map == 1) {
+		emissive *= texture(emissive_map, TexCoord).rgb;
+	}
+
+	vec3 albedo = base_color.rgb;
+	vec3 norm = surface_normal(Normal, Tangent, TexCoord, has_normal_map, normal_map, material.normal_scale);
+	vec3 view_dir = normalize(camera_pos - FragPos);
+
+	vec3 direct = vec3(0.0);
+	vec3 ambient = vec3(0.0);
+	for (int i = 0; i < light_count; i++) {
+		direct += cook_torrance(lights[i], norm, view_dir, albedo, metallic, roughness);
+		vec3 light_dir = light_direction(lights[i], FragPos);
+		ambient += lights[i].ambient * light_attenuation(lights[i], FragPos, light_dir);
+	}
+
+	vec3 result = ambient * albedo * occlusion + direct + emissive;
+	FragColor = vec4(result, base_color.a);
+}
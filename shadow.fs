@@ -0,0 +1,16 @@
+#version 330 core
+in vec3 FragPos;
+
+// Point lights store the distance to the light instead,
+// since a cube map's faces each have their own projection.
+uniform int linear_depth;
+uniform vec3 light_pos;
+uniform float far_plane;
+
+void main() {
+	if (linear_depth == 1) {
+		gl_FragDepth = length(FragPos - light_pos) / far_plane;
+	} else {
+		gl_FragDepth = gl_FragCoord.z;
+	}
+}
@@ -0,0 +1,11 @@
+#version 330 core
+layout (location = 0) in vec3 position;
+out vec3 FragPos;
+
+uniform mat4 light_space;
+uniform mat4 model;
+
+void main() {
+	FragPos = vec3(model * vec4(position, 1.0));
+	gl_Position = light_space * vec4(FragPos, 1.0);
+}
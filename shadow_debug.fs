@@ -0,0 +1,29 @@
+#version 330 core
+out vec4 FragColor;
+in vec2 TexCoord;
+
+uniform sampler2D depth_map;
+uniform samplerCube depth_cube;
+// Cube maps are unfolded into a strip of their six faces
+uniform int cube;
+
+vec3 cube_direction(vec2 coord) {
+	int face = int(min(coord.x * 6.0, 5.0));
+	vec2 uv = vec2(fract(coord.x * 6.0), coord.y) * 2.0 - 1.0;
+	if (face == 0) return vec3(1.0, -uv.y, -uv.x);
+	if (face == 1) return vec3(-1.0, -uv.y, uv.x);
+	if (face == 2) return vec3(uv.x, 1.0, uv.y);
+	if (face == 3) return vec3(uv.x, -1.0, -uv.y);
+	if (face == 4) return vec3(uv.x, -uv.y, 1.0);
+	return vec3(-uv.x, -uv.y, -1.0);
+}
+
+void main() {
+	float depth;
+	if (cube == 1) {
+		depth = texture(depth_cube, cube_direction(TexCoord)).r;
+	} else {
+		depth = texture(depth_map, TexCoord).r;
+	}
+	FragColor = vec4(vec3(depth), 1.0);
+}
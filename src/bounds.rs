@@ -0,0 +1,311 @@
+// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+	pub min: glm::Vec3,
+	pub max: glm::Vec3,
+}
+
+impl Aabb {
+	// The smallest box around all of the points, which is an empty one
+	// at the origin when there are none.
+	pub fn from_points<I: IntoIterator<Item = glm::Vec3>>(points: I) -> Self {
+		let mut points = points.into_iter();
+		let first = match points.next() {
+			Some(first) => first,
+			None => glm::Vec3::zeros(),
+		};
+		points.fold(
+			Self {
+				min: first,
+				max: first,
+			},
+			|aabb, point| Self {
+				min: glm::min2(&aabb.min, &point),
+				max: glm::max2(&aabb.max, &point),
+			},
+		)
+	}
+
+	pub fn center(&self) -> glm::Vec3 {
+		(self.min + self.max) * 0.5
+	}
+
+	pub fn half_extents(&self) -> glm::Vec3 {
+		(self.max - self.min) * 0.5
+	}
+
+	// The smallest box around both boxes
+	pub fn union(&self, other: &Aabb) -> Self {
+		Self {
+			min: glm::min2(&self.min, &other.min),
+			max: glm::max2(&self.max, &other.max),
+		}
+	}
+
+	pub fn grown(&self, amount: &glm::Vec3) -> Self {
+		Self {
+			min: self.min - amount,
+			max: self.max + amount,
+		}
+	}
+
+	pub fn contains(&self, other: &Aabb) -> bool {
+		glm::all(&glm::less_than_equal(&self.min, &other.min))
+			&& glm::all(&glm::greater_than_equal(&self.max, &other.max))
+	}
+
+	pub fn intersects(&self, other: &Aabb) -> bool {
+		glm::all(&glm::less_than_equal(&self.min, &other.max))
+			&& glm::all(&glm::greater_than_equal(&self.max, &other.min))
+	}
+
+	pub fn surface_area(&self) -> f32 {
+		let size = self.max - self.min;
+		2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+	}
+
+	// Squared distance from the point to the box, zero when it's inside
+	pub fn distance_squared(&self, point: &glm::Vec3) -> f32 {
+		let closest = glm::clamp_vec(point, &self.min, &self.max);
+		glm::distance2(&closest, point)
+	}
+
+	// How far along the ray it enters the box, if it does at all.
+	// Rays starting inside the box hit it right away.
+	pub fn ray_distance(&self, ray: &Ray) -> Option<f32> {
+		let mut near = 0.0f32;
+		let mut far = f32::INFINITY;
+		for axis in 0..3 {
+			let inverse = 1.0 / ray.direction[axis];
+			let a = (self.min[axis] - ray.origin[axis]) * inverse;
+			let b = (self.max[axis] - ray.origin[axis]) * inverse;
+			// NaN comes from parallel rays right on a side, which graze it
+			if a.is_nan() || b.is_nan() {
+				continue;
+			}
+			near = near.max(a.min(b));
+			far = far.min(a.max(b));
+		}
+		if near <= far {
+			Some(near)
+		} else {
+			None
+		}
+	}
+
+	// The box around this box after transforming it
+	pub fn transformed(&self, matrix: &glm::Mat4) -> Self {
+		let center = self.center();
+		let center = glm::vec4_to_vec3(&(matrix * glm::vec4(center.x, center.y, center.z, 1.0)));
+		let extents = glm::abs(&glm::mat4_to_mat3(matrix)) * self.half_extents();
+		Self {
+			min: center - extents,
+			max: center + extents,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+	pub origin: glm::Vec3,
+	// Distances along the ray are in multiples of this
+	pub direction: glm::Vec3,
+}
+
+// The planes bounding what a camera sees, facing inwards
+pub struct Frustum {
+	planes: Vec<glm::Vec4>,
+}
+
+impl Frustum {
+	// Pull the planes out of a projection * view matrix
+	pub fn from_matrix(matrix: &glm::Mat4) -> Self {
+		let row = |i| glm::row(matrix, i);
+		let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+		let planes = [w + x, w - x, w + y, w - y, w + z, w - z]
+			.iter()
+			.filter_map(|plane| {
+				let length = glm::vec4_to_vec3(plane).norm();
+				// An infinite far plane has no normal, and bounds nothing
+				if length > f32::EPSILON {
+					Some(plane / length)
+				} else {
+					None
+				}
+			})
+			.collect();
+		Self { planes }
+	}
+
+	// Conservative, boxes near the corners may pass without being visible
+	pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+		let (center, extents) = (aabb.center(), aabb.half_extents());
+		self.planes.iter().all(|plane| {
+			let reach = glm::dot(&glm::abs(&glm::vec4_to_vec3(plane)), &extents);
+			distance(plane, &center) >= -reach
+		})
+	}
+}
+
+fn distance(plane: &glm::Vec4, point: &glm::Vec3) -> f32 {
+	glm::dot(&glm::vec4_to_vec3(plane), point) + plane.w
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::resources::projection::Projection;
+
+	fn cube(center: glm::Vec3, half: f32) -> Aabb {
+		Aabb {
+			min: center - glm::vec3(half, half, half),
+			max: center + glm::vec3(half, half, half),
+		}
+	}
+
+	fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+		assert!(glm::distance(&a, &b) < 1e-5, "{:?} != {:?}", a, b);
+	}
+
+	// Looking down -z from the origin, with a field of view of 90 degrees,
+	// so the view is as wide as it is far away.
+	fn frustum() -> Frustum {
+		Frustum::from_matrix(&glm::perspective(1.0, 90f32.to_radians(), 1.0, 100.0))
+	}
+
+	#[test]
+	fn boxes_inside_the_frustum_are_seen() {
+		let frustum = frustum();
+		assert!(frustum.intersects_aabb(&cube(glm::vec3(0.0, 0.0, -10.0), 1.0)));
+		assert!(frustum.intersects_aabb(&cube(glm::vec3(-8.0, 8.0, -10.0), 1.0)));
+		assert!(frustum.intersects_aabb(&cube(glm::vec3(0.0, 0.0, -10.0), 500.0)));
+	}
+
+	#[test]
+	fn boxes_outside_the_frustum_are_not() {
+		let frustum = frustum();
+		// Behind, beside, below, too close and too far
+		for center in &[
+			glm::vec3(0.0, 0.0, 10.0),
+			glm::vec3(15.0, 0.0, -10.0),
+			glm::vec3(0.0, -15.0, -10.0),
+			glm::vec3(0.0, 0.0, -0.5),
+			glm::vec3(0.0, 0.0, -150.0),
+		] {
+			assert!(
+				!frustum.intersects_aabb(&cube(*center, 0.25)),
+				"{:?}",
+				center
+			);
+		}
+	}
+
+	#[test]
+	fn boxes_straddling_a_plane_are_seen() {
+		let frustum = frustum();
+		for center in &[
+			glm::vec3(10.0, 0.0, -10.0),
+			glm::vec3(0.0, -10.0, -10.0),
+			glm::vec3(0.0, 0.0, -1.0),
+			glm::vec3(0.0, 0.0, -100.0),
+		] {
+			assert!(frustum.intersects_aabb(&cube(*center, 0.5)), "{:?}", center);
+		}
+	}
+
+	#[test]
+	fn infinite_frustums_have_no_far_plane() {
+		let projection = Projection::Perspective {
+			fov: 90f32.to_radians(),
+			near: 1.0,
+			far: None,
+			reverse_z: false,
+		};
+		let frustum = Frustum::from_matrix(&projection.matrix(1.0));
+		assert_eq!(frustum.planes.len(), 5);
+		assert!(frustum.intersects_aabb(&cube(glm::vec3(0.0, 0.0, -1.0e6), 1.0)));
+		assert!(!frustum.intersects_aabb(&cube(glm::vec3(0.0, 0.0, 10.0), 1.0)));
+	}
+
+	#[test]
+	fn reversed_frustums_still_cull() {
+		// Depth going from 0 to 1 makes the far plane a little further away,
+		// which only means a few more boxes are drawn and then clipped.
+		for &far in &[Some(100.0), None] {
+			let projection = Projection::Perspective {
+				fov: 90f32.to_radians(),
+				near: 1.0,
+				far,
+				reverse_z: true,
+			};
+			let frustum = Frustum::from_matrix(&projection.matrix(1.0));
+			assert!(frustum.intersects_aabb(&cube(glm::vec3(0.0, 0.0, -10.0), 1.0)));
+			for center in &[
+				glm::vec3(0.0, 0.0, 10.0),
+				glm::vec3(15.0, 0.0, -10.0),
+				glm::vec3(0.0, 0.0, -0.5),
+			] {
+				assert!(
+					!frustum.intersects_aabb(&cube(*center, 0.25)),
+					"{:?}",
+					center
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn transformed_boxes_contain_the_transformed_corners() {
+		let aabb = Aabb {
+			min: glm::vec3(-1.0, -2.0, -3.0),
+			max: glm::vec3(1.0, 2.0, 3.0),
+		};
+		// A quarter turn swaps the extents along x and y
+		let quarter = glm::translation(&glm::vec3(10.0, 0.0, 0.0))
+			* glm::rotation(90f32.to_radians(), &glm::vec3(0.0, 0.0, 1.0));
+		let turned = aabb.transformed(&quarter);
+		assert_close(turned.min, glm::vec3(8.0, -1.0, -3.0));
+		assert_close(turned.max, glm::vec3(12.0, 1.0, 3.0));
+
+		// Half of that puts the corners of a cube on the axes
+		let eighth = glm::rotation(45f32.to_radians(), &glm::vec3(0.0, 0.0, 1.0));
+		let turned = cube(glm::Vec3::zeros(), 1.0).transformed(&eighth);
+		let diagonal = 2f32.sqrt();
+		assert_close(turned.max, glm::vec3(diagonal, diagonal, 1.0));
+		assert_close(turned.min, -turned.max);
+	}
+
+	fn ray(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+		Ray { origin, direction }
+	}
+
+	#[test]
+	fn rays_from_inside_hit_right_away() {
+		let aabb = cube(glm::Vec3::zeros(), 1.0);
+		let from_center = ray(glm::Vec3::zeros(), glm::vec3(0.0, 1.0, 0.0));
+		assert_eq!(aabb.ray_distance(&from_center), Some(0.0));
+	}
+
+	#[test]
+	fn rays_from_outside_hit_where_they_enter() {
+		let aabb = cube(glm::Vec3::zeros(), 1.0);
+		let along_x = ray(glm::vec3(-5.0, 0.5, 0.5), glm::vec3(1.0, 0.0, 0.0));
+		assert_eq!(aabb.ray_distance(&along_x), Some(4.0));
+		// In multiples of the direction
+		let fast = ray(glm::vec3(-5.0, 0.5, 0.5), glm::vec3(2.0, 0.0, 0.0));
+		assert_eq!(aabb.ray_distance(&fast), Some(2.0));
+		let diagonal = ray(glm::vec3(3.0, 3.0, 3.0), glm::vec3(-1.0, -1.0, -1.0));
+		assert_eq!(aabb.ray_distance(&diagonal), Some(2.0));
+	}
+
+	#[test]
+	fn rays_beside_or_away_from_the_box_miss() {
+		let aabb = cube(glm::Vec3::zeros(), 1.0);
+		let beside = ray(glm::vec3(-5.0, 2.0, 0.0), glm::vec3(1.0, 0.0, 0.0));
+		assert_eq!(aabb.ray_distance(&beside), None);
+		let away = ray(glm::vec3(-5.0, 0.0, 0.0), glm::vec3(-1.0, 0.0, 0.0));
+		assert_eq!(aabb.ray_distance(&away), None);
+		let past = ray(glm::vec3(-5.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 0.0));
+		assert_eq!(aabb.ray_distance(&past), None);
+	}
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use glium::backend::Facade;
+use glium::texture::Texture2d;
+use specs::prelude::*;
+
+use crate::components::{
+	name::NameComponent,
+	scene_camera::{CameraComponent, CameraTarget},
+};
+use crate::render_target::Offscreen;
+use crate::resources::camera::Camera;
+use crate::texture_views::TextureViews;
+
+// The textures of cameras that draw into one, kept between frames
+#[derive(Default)]
+pub struct CameraTargets {
+	targets: HashMap<Entity, Offscreen>,
+}
+
+impl CameraTargets {
+	// The camera's texture, made anew whenever its size changes
+	pub fn get<F: Facade>(
+		&mut self,
+		facade: &F,
+		entity: Entity,
+		width: u32,
+		height: u32,
+	) -> Result<&Offscreen, String> {
+		let stale = self
+			.targets
+			.get(&entity)
+			.is_none_or(|target| target.dimensions() != (width, height));
+		if stale {
+			self.targets
+				.insert(entity, Offscreen::new(facade, width, height)?);
+		}
+		Ok(&self.targets[&entity])
+	}
+
+	// Drop the textures of cameras that don't draw into one anymore
+	pub fn retain(&mut self, cameras: &[Entity]) {
+		self.targets.retain(|entity, _| cameras.contains(entity));
+	}
+}
+
+// Lets cameras be switched on and off, and shows what those drawing into a texture see
+pub fn draw_ui(
+	ui: &imgui::Ui,
+	world: &World,
+	views: &mut TextureViews,
+	textures: &mut imgui::Textures<Rc<Texture2d>>,
+) {
+	use imgui::Condition;
+
+	views.register(textures);
+
+	let entities = world.entities();
+	let names = world.read_storage::<NameComponent>();
+	let mut cameras = world.write_storage::<CameraComponent>();
+	let mut main_camera = world.write_resource::<Camera>();
+	imgui::Window::new(ui, im_str!("Cameras"))
+		.size([300.0, 300.0], Condition::FirstUseEver)
+		.position([850.0, 50.0], Condition::FirstUseEver)
+		.build(|| {
+			ui.checkbox(im_str!("Main camera"), &mut main_camera.active);
+			for (entity, camera, name) in (&entities, &mut cameras, names.maybe()).join() {
+				ui.push_id(entity.id() as i32);
+				ui.separator();
+				match name {
+					Some(name) => ui.text(&name.0),
+					None => ui.text(format!("Camera {}", entity.id())),
+				}
+				ui.checkbox(im_str!("Active"), &mut camera.active);
+				ui.slider_int(im_str!("Priority"), &mut camera.priority, -10, 10)
+					.build();
+				camera.projection.edit(ui);
+				if let CameraTarget::Texture { .. } = camera.target {
+					views.image(ui, entity, 0.5);
+				}
+				ui.pop_id();
+			}
+		});
+}
@@ -0,0 +1,66 @@
+use std::os::raw::c_void;
+use std::rc::Rc;
+
+use glium::{Api, Version};
+
+// OpenGL maps depth from -1..1 in clip space onto the depth buffer, which puts
+// the precision reversed depth is for right where it gets rounded away.
+// glClipControl lets depth go from 0 to 1 instead, but glium doesn't know
+// about it, so it's called directly.
+type ClipControlFn = extern "system" fn(origin: u32, depth: u32);
+
+const LOWER_LEFT: u32 = 0x8CA1;
+const NEGATIVE_ONE_TO_ONE: u32 = 0x935E;
+const ZERO_TO_ONE: u32 = 0x935F;
+
+// glClipControl, if the context has it
+#[derive(Clone, Copy, Default)]
+pub struct ClipControl(Option<ClipControlFn>);
+
+impl ClipControl {
+	// Look the function up through the context glium was made with. Drivers
+	// hand out addresses for functions they don't have, so it's only
+	// looked up when the OpenGL version says it's there.
+	pub fn load<F: FnOnce(&str) -> *const c_void>(
+		context: &glium::backend::Context,
+		get_proc_address: F,
+	) -> Self {
+		if *context.get_opengl_version() < Version(Api::Gl, 4, 5) {
+			return Self(None);
+		}
+		let address = get_proc_address("glClipControl");
+		if address.is_null() {
+			return Self(None);
+		}
+		// Safety: this is glClipControl, as declared by the OpenGL headers
+		Self(Some(unsafe {
+			std::mem::transmute::<*const c_void, ClipControlFn>(address)
+		}))
+	}
+
+	pub fn is_supported(&self) -> bool {
+		self.0.is_some()
+	}
+
+	// Run `draw` with depth going from 0 to 1 in clip space,
+	// and put things back the way glium expects them afterwards.
+	pub fn zero_to_one<T, F: FnOnce() -> T>(
+		&self,
+		context: &Rc<glium::backend::Context>,
+		draw: F,
+	) -> T {
+		let clip_control = match self.0 {
+			Some(clip_control) => clip_control,
+			None => return draw(),
+		};
+		// Safety: the context is made current first, and the state is restored
+		unsafe {
+			context.exec_in_context(|| clip_control(LOWER_LEFT, ZERO_TO_ONE));
+		}
+		let result = draw();
+		unsafe {
+			context.exec_in_context(|| clip_control(LOWER_LEFT, NEGATIVE_ONE_TO_ONE));
+		}
+		result
+	}
+}
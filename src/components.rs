@@ -2,6 +2,10 @@ pub mod light;
 pub mod material;
 pub mod model;
 pub mod name;
+pub mod parent;
+pub mod pbr_material;
+pub mod scene_camera;
+pub mod shadow;
 //pub mod shader;
 pub mod texture;
 pub mod transformation;
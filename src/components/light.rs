@@ -1,12 +1,40 @@
 use specs::prelude::*;
 
+// How many lights the shaders take into account at once.
+// Passed on to the shaders as `MAX_LIGHTS`, any beyond it are ignored.
+pub const MAX_LIGHTS: usize = 16;
+
+// Positional lights take their position from the entity's transformation.
+// Directions are in world space.
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Light {
+	DirectionalLight {
+		direction: glm::Vec3,
+
+		ambient: glm::Vec3,
+		diffuse: glm::Vec3,
+		specular: glm::Vec3,
+	},
 	PointLight {
 		ambient: glm::Vec3,
 		diffuse: glm::Vec3,
 		specular: glm::Vec3,
 
+		constant: f32,
+		linear: f32,
+		quadratic: f32,
+	},
+	SpotLight {
+		direction: glm::Vec3,
+		// Cosines of the angles where the cone starts and finishes fading out
+		cutoff: f32,
+		outer_cutoff: f32,
+
+		ambient: glm::Vec3,
+		diffuse: glm::Vec3,
+		specular: glm::Vec3,
+
 		constant: f32,
 		linear: f32,
 		quadratic: f32,
@@ -1,10 +1,8 @@
 use specs::prelude::*;
 
+use crate::resources::material_store::MaterialHandle;
+
+// Refers to a material in the MaterialStore
 #[derive(Component, Debug)]
 #[storage(VecStorage)]
-pub struct MaterialComponent {
-	pub ambient: glm::Vec3,
-	pub diffuse: glm::Vec3,
-	pub specular: glm::Vec3,
-	pub shininess: f32,
-}
+pub struct MaterialComponent(pub MaterialHandle);
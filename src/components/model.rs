@@ -1,11 +1,8 @@
 use specs::{Component, VecStorage};
 
-use crate::object::VertexArray;
+use crate::resources::mesh_store::MeshHandle;
 
-// Stores the VAO
+// Refers to geometry in the MeshStore
 #[derive(Component, Debug)]
 #[storage(VecStorage)]
-pub struct ModelComponent {
-	pub vertices: Vec<VertexArray>,
-	pub indices: glium::index::NoIndices,
-}
+pub struct ModelComponent(pub MeshHandle);
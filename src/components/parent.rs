@@ -0,0 +1,7 @@
+use specs::prelude::*;
+
+// Makes the entity's transformation relative to that of another entity.
+// Children whose parent gets deleted are detached, staying where they were.
+#[derive(Component, Debug, Clone, Copy)]
+#[storage(VecStorage)]
+pub struct ParentComponent(pub Entity);
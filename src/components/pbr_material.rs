@@ -0,0 +1,9 @@
+use specs::prelude::*;
+
+use crate::resources::material_store::PbrMaterialHandle;
+
+// Refers to a material in the PbrMaterialStore, which gets
+// the entity drawn with the Cook-Torrance shader
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct PbrMaterialComponent(pub PbrMaterialHandle);
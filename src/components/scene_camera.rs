@@ -0,0 +1,41 @@
+use specs::{Component, VecStorage};
+
+use crate::resources::projection::Projection;
+
+// Where a camera draws to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraTarget {
+	// Part of the window, given by `viewport`
+	Screen,
+	// A texture of its own, shown in the "Cameras" overlay
+	Texture { width: u32, height: u32 },
+}
+
+// Looks down the negative Z axis of the entity's transformation.
+// The main camera is drawn first, camera entities are drawn on top of it.
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct CameraComponent {
+	pub projection: Projection,
+	// Left, bottom, width and height as fractions of the target
+	pub viewport: glm::Vec4,
+	// Higher priorities are drawn later, on top of lower ones
+	pub priority: i32,
+	// Only the depth is cleared without one, leaving what was drawn before visible
+	pub clear_color: Option<glm::Vec4>,
+	pub active: bool,
+	pub target: CameraTarget,
+}
+
+impl Default for CameraComponent {
+	fn default() -> Self {
+		Self {
+			projection: Projection::default(),
+			viewport: glm::vec4(0.0, 0.0, 1.0, 1.0),
+			priority: 0,
+			clear_color: Some(glm::vec4(0.0, 0.0, 0.0, 1.0)),
+			active: true,
+			target: CameraTarget::Screen,
+		}
+	}
+}
@@ -0,0 +1,32 @@
+use specs::{Component, VecStorage};
+
+// How many lights can cast shadows at once, passed on to the shaders.
+// Directional and spot lights share one pool, point lights another.
+pub const MAX_SHADOW_MAPS: usize = 4;
+pub const MAX_SHADOW_CUBE_MAPS: usize = 2;
+
+// Lets the light on the same entity cast shadows
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
+pub struct ShadowComponent {
+	// Width and height of the shadow map in texels
+	pub resolution: u32,
+	// Depth offset against shadow acne
+	pub bias: f32,
+	// Half the size of the area around the camera a directional light covers
+	pub extent: f32,
+	pub near: f32,
+	pub far: f32,
+}
+
+impl Default for ShadowComponent {
+	fn default() -> Self {
+		Self {
+			resolution: 1024,
+			bias: 0.005,
+			extent: 100.0,
+			near: 1.0,
+			far: 500.0,
+		}
+	}
+}
@@ -1,10 +1,10 @@
 use specs::{Component, VecStorage};
 
-use crate::resources::texture_map::GLTextureHandle;
+use crate::resources::texture_map::TextureHandle;
 
-// Store OpenGL Texture handles only
+// The texture an entity is drawn with.
 // Actual textures are stored in TextureMap,
-// As well as references to the handles
+// the renderer uploads them the first time they're used.
 #[derive(Component)]
 #[storage(VecStorage)]
-pub struct GLTextureComponent(pub GLTextureHandle);
+pub struct GLTextureComponent(pub TextureHandle);
@@ -1,27 +1,274 @@
 use glm::Mat4;
+use nalgebra::{Matrix3, Rotation3, UnitQuaternion};
 use specs::{Component, VecStorage};
 
-#[derive(Component, Debug)]
+// Relative to the parent, if the entity has one, and to the world otherwise.
+// Entities face down their negative Z axis, like OpenGL cameras do.
+#[derive(Component, Debug, Clone)]
 #[storage(VecStorage)]
-pub struct TransformationComponent(pub Mat4);
+pub struct TransformationComponent {
+	position: glm::Vec3,
+	rotation: UnitQuaternion<f32>,
+	scale: glm::Vec3,
+	// Only recomputed when any of the above changed
+	matrix: Mat4,
+	dirty: bool,
+}
+
+impl Default for TransformationComponent {
+	fn default() -> Self {
+		Self::new(
+			glm::Vec3::zeros(),
+			UnitQuaternion::identity(),
+			glm::vec3(1.0, 1.0, 1.0),
+		)
+	}
+}
 
 impl TransformationComponent {
+	pub fn new(position: glm::Vec3, rotation: UnitQuaternion<f32>, scale: glm::Vec3) -> Self {
+		Self {
+			position,
+			rotation,
+			scale,
+			matrix: Mat4::identity(),
+			dirty: true,
+		}
+	}
+
 	pub fn from_pos(pos: glm::Vec3) -> Self {
-		Self(glm::mat4(
-			1.0, 0.0, 0.0, pos[0], //
-			0.0, 1.0, 0.0, pos[1], //
-			0.0, 0.0, 1.0, pos[2], //
-			0.0, 0.0, 0.0, 1.0, //
-		))
+		Self {
+			position: pos,
+			..Default::default()
+		}
+	}
+
+	pub fn get_pos(&self) -> glm::Vec3 {
+		self.position
 	}
 
 	pub fn set_pos(&mut self, pos: glm::Vec3) {
-		self.0[12] = pos[0];
-		self.0[13] = pos[1];
-		self.0[14] = pos[2];
+		self.position = pos;
+		self.dirty = true;
+	}
+
+	pub fn rotation(&self) -> &UnitQuaternion<f32> {
+		&self.rotation
+	}
+
+	pub fn set_rotation(&mut self, rotation: UnitQuaternion<f32>) {
+		self.rotation = rotation;
+		self.dirty = true;
+	}
+
+	pub fn scale(&self) -> glm::Vec3 {
+		self.scale
+	}
+
+	pub fn set_scale(&mut self, scale: glm::Vec3) {
+		self.scale = scale;
+		self.dirty = true;
+	}
+
+	// Split a matrix into translation, rotation and scale.
+	// Shearing can't be expressed and gets lost.
+	pub fn set_matrix(&mut self, matrix: &Mat4) {
+		let columns: Vec<glm::Vec3> = (0..3)
+			.map(|i| glm::vec4_to_vec3(&glm::column(matrix, i)))
+			.collect();
+		let scale = glm::vec3(columns[0].norm(), columns[1].norm(), columns[2].norm());
+		// A mirrored matrix gets one negative axis instead of a rotation
+		let sign = glm::mat4_to_mat3(matrix).determinant().signum();
+		let scale = glm::vec3(scale.x * sign, scale.y, scale.z);
+		// An axis scaled to nothing leaves no rotation to recover,
+		// so the current one is kept rather than dividing by zero
+		if scale.iter().all(|axis| axis.abs() > f32::EPSILON) {
+			let rotation = Matrix3::from_columns(&[
+				columns[0] / scale.x,
+				columns[1] / scale.y,
+				columns[2] / scale.z,
+			]);
+			self.rotation =
+				UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation));
+		}
+
+		self.position = glm::vec4_to_vec3(&glm::column(matrix, 3));
+		self.scale = scale;
+		self.dirty = true;
+	}
+
+	// The direction the entity faces
+	pub fn forward(&self) -> glm::Vec3 {
+		self.rotation * glm::vec3(0.0, 0.0, -1.0)
+	}
+
+	pub fn translate(&mut self, offset: &glm::Vec3) {
+		self.position += offset;
+		self.dirty = true;
+	}
+
+	// Move along the entity's own axes rather than those of its parent
+	pub fn translate_local(&mut self, offset: &glm::Vec3) {
+		self.translate(&(self.rotation * offset));
+	}
+
+	// Rotate about the entity's own position
+	pub fn rotate(&mut self, rotation: &UnitQuaternion<f32>) {
+		self.rotation = rotation * self.rotation;
+		self.dirty = true;
 	}
 
+	// Swing the entity around `point`, turning it along with it
+	pub fn rotate_around(&mut self, point: &glm::Vec3, rotation: &UnitQuaternion<f32>) {
+		self.position = point + rotation * (self.position - point);
+		self.rotate(rotation);
+	}
+
+	// Turn to face `target`, keeping `up` as close to up as possible
+	pub fn look_at(&mut self, target: &glm::Vec3, up: &glm::Vec3) {
+		let direction = target - self.position;
+		if direction.norm_squared() == 0.0 {
+			return;
+		}
+		// `face_towards` points the positive Z axis, and entities face down the negative one
+		self.set_rotation(UnitQuaternion::face_towards(&-direction, up));
+	}
+
+	// Translation * rotation * scale
+	pub fn matrix(&mut self) -> &Mat4 {
+		if self.dirty {
+			self.matrix = glm::translation(&self.position)
+				* self.rotation.to_homogeneous()
+				* glm::scaling(&self.scale);
+			self.dirty = false;
+		}
+		&self.matrix
+	}
+
+	// The widgets for moving, turning and scaling the entity, to go in a window
+	pub fn edit(&mut self, ui: &imgui::Ui) {
+		let mut position: [f32; 3] = self.get_pos().into();
+		if ui.drag_float3(im_str!("Position"), &mut position).build() {
+			self.set_pos(position.into());
+		}
+		// Roll, pitch and yaw in degrees
+		let (roll, pitch, yaw) = self.rotation().euler_angles();
+		let mut angles = [roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees()];
+		if ui.drag_float3(im_str!("Rotation"), &mut angles).build() {
+			self.set_rotation(UnitQuaternion::from_euler_angles(
+				angles[0].to_radians(),
+				angles[1].to_radians(),
+				angles[2].to_radians(),
+			));
+		}
+		let mut scale: [f32; 3] = self.scale().into();
+		if ui
+			.drag_float3(im_str!("Scale"), &mut scale)
+			.speed(0.01)
+			.build()
+		{
+			self.set_scale(scale.into());
+		}
+
+		let forward = self.forward();
+		ui.text(format!(
+			"Facing: {:.2}, {:.2}, {:.2}",
+			forward.x, forward.y, forward.z
+		));
+		if ui.button(im_str!("Step forward"), [0.0, 0.0]) {
+			self.translate_local(&glm::vec3(0.0, 0.0, -1.0));
+		}
+		ui.same_line(0.0);
+		if ui.button(im_str!("Step back"), [0.0, 0.0]) {
+			self.translate_local(&glm::vec3(0.0, 0.0, 1.0));
+		}
+	}
+}
+
+// The transformation relative to the world, worked out every frame by the
+// transform system. Whatever is drawn or lit goes by this one.
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct GlobalTransformationComponent(pub Mat4);
+
+impl GlobalTransformationComponent {
 	pub fn get_pos(&self) -> glm::Vec3 {
 		glm::vec4_to_vec3(&glm::column(&self.0, 3))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(a: &Mat4, b: &Mat4) {
+		assert!((a - b).abs().max() < 1e-4, "{} differs from {}", a, b);
+	}
+
+	fn assert_close_vec(a: &glm::Vec3, b: &glm::Vec3) {
+		assert!((a - b).abs().max() < 1e-4, "{} differs from {}", a, b);
+	}
+
+	#[test]
+	fn matrices_round_trip() {
+		let rotation = UnitQuaternion::from_euler_angles(0.3, -1.2, 2.0);
+		for scale in &[
+			glm::vec3(1.0, 2.0, 3.0),
+			// Mirrored along one axis, and along all three
+			glm::vec3(-1.0, 2.0, 3.0),
+			glm::vec3(1.0, 2.0, -3.0),
+			glm::vec3(-0.5, -2.0, -3.0),
+		] {
+			let mut original =
+				TransformationComponent::new(glm::vec3(4.0, -5.0, 6.0), rotation, *scale);
+			let matrix = *original.matrix();
+			let mut decomposed = TransformationComponent::default();
+			decomposed.set_matrix(&matrix);
+			assert_close(decomposed.matrix(), &matrix);
+			assert_close_vec(&decomposed.get_pos(), &glm::vec3(4.0, -5.0, 6.0));
+		}
+	}
+
+	#[test]
+	fn zero_scale_keeps_the_rotation() {
+		let rotation = UnitQuaternion::from_euler_angles(0.0, 0.5, 0.0);
+		let mut flat = TransformationComponent::new(
+			glm::vec3(1.0, 2.0, 3.0),
+			rotation,
+			glm::vec3(1.0, 0.0, 1.0),
+		);
+		let matrix = *flat.matrix();
+		let mut decomposed =
+			TransformationComponent::new(glm::Vec3::zeros(), rotation, glm::vec3(1.0, 1.0, 1.0));
+		decomposed.set_matrix(&matrix);
+		assert!(decomposed.matrix().iter().all(|value| value.is_finite()));
+		assert_close(decomposed.matrix(), &matrix);
+		assert_eq!(decomposed.rotation(), &rotation);
+	}
+
+	#[test]
+	fn rotating_around_a_point() {
+		let mut trans = TransformationComponent::from_pos(glm::vec3(2.0, 0.0, 0.0));
+		let quarter =
+			UnitQuaternion::from_axis_angle(&glm::Vec3::y_axis(), std::f32::consts::FRAC_PI_2);
+		trans.rotate_around(&glm::vec3(1.0, 0.0, 0.0), &quarter);
+		assert_close_vec(&trans.get_pos(), &glm::vec3(1.0, 0.0, -1.0));
+		// It turns along with its position
+		assert_close_vec(&trans.forward(), &glm::vec3(-1.0, 0.0, 0.0));
+	}
+
+	#[test]
+	fn looking_at_a_target() {
+		let mut trans = TransformationComponent::from_pos(glm::vec3(0.0, 0.0, 5.0));
+		trans.look_at(&glm::vec3(5.0, 0.0, 5.0), &glm::vec3(0.0, 1.0, 0.0));
+		assert_close_vec(&trans.forward(), &glm::vec3(1.0, 0.0, 0.0));
+		// Moving forward in its own space heads for the target
+		trans.translate_local(&glm::vec3(0.0, 0.0, -2.0));
+		assert_close_vec(&trans.get_pos(), &glm::vec3(2.0, 0.0, 5.0));
+
+		// Looking at its own position changes nothing
+		let before = *trans.rotation();
+		trans.look_at(&glm::vec3(2.0, 0.0, 5.0), &glm::vec3(0.0, 1.0, 0.0));
+		assert_eq!(trans.rotation(), &before);
+	}
+}
@@ -0,0 +1,446 @@
+use std::fs;
+use std::path::Path;
+
+use nalgebra::{Quaternion, UnitQuaternion};
+use specs::prelude::*;
+
+use crate::components::*;
+use crate::mesh_processing;
+use crate::object::{Object, Vertex, VertexIndex};
+use crate::resources::asset_store::HandleId;
+use crate::resources::material_store::{PbrMaterial, PbrMaterialHandle, PbrMaterialStore};
+use crate::resources::mesh_store::MeshStore;
+use crate::resources::texture_map::{TextureHandle, TextureMap};
+
+// Resolve a glTF URI, which is either a base64 data URI
+// or a path relative to the file that references it.
+fn read_uri(dir: &Path, uri: &str) -> Result<Vec<u8>, String> {
+	if uri.starts_with("data:") {
+		let (_, data) = uri
+			.split_once(";base64,")
+			.ok_or_else(|| "Only base64 data URIs are supported".to_string())?;
+		base64::decode(data).map_err(|e| format!("Invalid data URI: {}", e))
+	} else {
+		let path = dir.join(uri);
+		fs::read(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))
+	}
+}
+
+// Every buffer, checked to be as long as the file says and to hold every view
+// into it, so that reading through views can't go out of bounds.
+fn load_buffers(gltf: &gltf::Gltf, dir: &Path) -> Result<Vec<Vec<u8>>, String> {
+	let buffers = gltf
+		.buffers()
+		.map(|buffer| {
+			let data = match buffer.source() {
+				gltf::buffer::Source::Bin => gltf
+					.blob
+					.clone()
+					.ok_or_else(|| "Missing binary chunk".to_string())?,
+				gltf::buffer::Source::Uri(uri) => read_uri(dir, uri)?,
+			};
+			if data.len() < buffer.length() {
+				return Err(format!(
+					"Buffer {} has {} bytes but should have {}",
+					buffer.index(),
+					data.len(),
+					buffer.length()
+				));
+			}
+			Ok(data)
+		})
+		.collect::<Result<Vec<_>, String>>()?;
+	for view in gltf.views() {
+		if view.offset() + view.length() > view.buffer().length() {
+			return Err(format!(
+				"Buffer view {} reaches past the end of buffer {}",
+				view.index(),
+				view.buffer().index()
+			));
+		}
+	}
+	Ok(buffers)
+}
+
+fn load_image(
+	image: gltf::Image,
+	buffers: &[Vec<u8>],
+	path: &str,
+	dir: &Path,
+	texture_map: &mut TextureMap,
+) -> Result<TextureHandle, String> {
+	let key = format!("{}#image{}", path, image.index());
+	match image.source() {
+		gltf::image::Source::View { view, .. } => {
+			let data = buffers
+				.get(view.buffer().index())
+				.and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()))
+				.ok_or_else(|| format!("Image {} lies outside its buffer", image.index()))?;
+			texture_map.load_from_memory(&key, data)
+		}
+		gltf::image::Source::Uri { uri, .. } => {
+			texture_map.load_from_memory(&key, &read_uri(dir, uri)?)
+		}
+	}
+}
+
+// Primitives sharing a material share the loaded one as well
+fn load_material(
+	material: gltf::Material,
+	buffers: &[Vec<u8>],
+	path: &str,
+	dir: &Path,
+	world: &World,
+) -> Result<PbrMaterialHandle, String> {
+	// The default material has no index
+	let key = match material.index() {
+		Some(index) => format!("{}#material{}", path, index),
+		None => format!("{}#material", path),
+	};
+	let mut texture_map = world.write_resource::<TextureMap>();
+	world.write_resource::<PbrMaterialStore>().load(&key, |_| {
+		read_material(material, buffers, path, dir, &mut texture_map)
+	})
+}
+
+fn read_material(
+	material: gltf::Material,
+	buffers: &[Vec<u8>],
+	path: &str,
+	dir: &Path,
+	texture_map: &mut TextureMap,
+) -> Result<PbrMaterial, String> {
+	let pbr = material.pbr_metallic_roughness();
+
+	let mut load = |texture: Option<gltf::Texture>| -> Result<_, String> {
+		match texture {
+			Some(texture) => Ok(Some(load_image(
+				texture.source(),
+				buffers,
+				path,
+				dir,
+				texture_map,
+			)?)),
+			None => Ok(None),
+		}
+	};
+
+	// glTF packs metalness and roughness into one texture
+	let metallic_roughness_map = load(pbr.metallic_roughness_texture().map(|info| info.texture()))?;
+	Ok(PbrMaterial {
+		base_color: pbr.base_color_factor().into(),
+		base_color_map: load(pbr.base_color_texture().map(|info| info.texture()))?,
+		metallic: pbr.metallic_factor(),
+		metallic_map: metallic_roughness_map.clone(),
+		roughness: pbr.roughness_factor(),
+		roughness_map: metallic_roughness_map,
+		normal_scale: material
+			.normal_texture()
+			.map_or(1.0, |normal| normal.scale()),
+		normal_map: load(material.normal_texture().map(|normal| normal.texture()))?,
+		occlusion_strength: material
+			.occlusion_texture()
+			.map_or(1.0, |occlusion| occlusion.strength()),
+		occlusion_map: load(
+			material
+				.occlusion_texture()
+				.map(|occlusion| occlusion.texture()),
+		)?,
+		emissive: material.emissive_factor().into(),
+		emissive_map: load(material.emissive_texture().map(|info| info.texture()))?,
+	})
+}
+
+fn load_primitive(primitive: &gltf::Primitive, buffers: &[Vec<u8>]) -> Result<Object, String> {
+	if primitive.mode() != gltf::mesh::Mode::Triangles {
+		return Err(format!("Unsupported primitive mode {:?}", primitive.mode()));
+	}
+	let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+
+	let positions: Vec<[f32; 3]> = reader
+		.read_positions()
+		.ok_or_else(|| "Primitive has no positions".to_string())?
+		.collect();
+	let normals: Vec<[f32; 3]> = reader
+		.read_normals()
+		.map(|n| n.collect())
+		.unwrap_or_default();
+	let uvs: Vec<[f32; 2]> = reader
+		.read_tex_coords(0)
+		.map(|uv| uv.into_f32().collect())
+		.unwrap_or_default();
+	let tangents: Vec<[f32; 4]> = reader
+		.read_tangents()
+		.map(|t| t.collect())
+		.unwrap_or_default();
+
+	let mut obj = Object::default();
+	for (i, position) in positions.iter().enumerate() {
+		let normal = normals.get(i).copied().unwrap_or([0.0; 3]);
+		let [u, v] = uvs.get(i).copied().unwrap_or([0.0; 2]);
+		obj.vertexes.push(Vertex {
+			position: (*position).into(),
+			normal: normal.into(),
+			// glTF puts the origin of texture space at the top left, OBJ at the bottom left
+			uv: glm::vec2(u, 1.0 - v),
+			tangent: tangents.get(i).map(|&t| t.into()),
+		});
+	}
+
+	let indices: Vec<u32> = match reader.read_indices() {
+		Some(indices) => indices.into_u32().collect(),
+		None => (0..positions.len() as u32).collect(),
+	};
+	if indices.iter().any(|&i| i as usize >= positions.len()) {
+		return Err("Primitive index out of bounds".to_string());
+	}
+	for triangle in indices.chunks_exact(3) {
+		obj.triangle_indices
+			.push(VertexIndex(triangle[0], triangle[1], triangle[2]));
+	}
+
+	// glTF asks for flat normals when they're missing
+	mesh_processing::generate_normals(&mut obj);
+	obj.deduplicate();
+	if obj.vertexes.iter().any(|v| v.tangent.is_none()) {
+		mesh_processing::generate_tangents(&mut obj);
+	}
+	Ok(obj)
+}
+
+fn primitive_key(path: &str, mesh: &gltf::Mesh, index: usize) -> String {
+	format!("{}#mesh{}/{}", path, mesh.index(), index)
+}
+
+struct Importer<'a> {
+	buffers: Vec<Vec<u8>>,
+	path: &'a str,
+	dir: &'a Path,
+	entities: Vec<Entity>,
+}
+
+impl<'a> Importer<'a> {
+	fn import_node(
+		&mut self,
+		world: &mut World,
+		node: gltf::Node,
+		parent: Option<Entity>,
+	) -> Result<(), String> {
+		let (translation, rotation, scale) = node.transform().decomposed();
+		let local = transformation::TransformationComponent::new(
+			translation.into(),
+			// glTF stores quaternions as x, y, z, w
+			UnitQuaternion::from_quaternion(Quaternion::new(
+				rotation[3],
+				rotation[0],
+				rotation[1],
+				rotation[2],
+			)),
+			scale.into(),
+		);
+		let name = node
+			.name()
+			.map(str::to_string)
+			.unwrap_or_else(|| format!("Node {}", node.index()));
+
+		let mut builder = world
+			.create_entity()
+			.with(local)
+			.with(name::NameComponent(name.clone()));
+		if let Some(parent) = parent {
+			builder = builder.with(parent::ParentComponent(parent));
+		}
+		let entity = builder.build();
+		self.entities.push(entity);
+
+		// Every primitive gets an entity of its own below the node's
+		if let Some(mesh) = node.mesh() {
+			let count = mesh.primitives().len();
+			for (i, primitive) in mesh.primitives().enumerate() {
+				let key = primitive_key(self.path, &mesh, i);
+				let mesh = world.write_resource::<MeshStore>().load(&key, |_| {
+					load_primitive(&primitive, &self.buffers).map(|object| object.get_mesh())
+				})?;
+				let material = load_material(
+					primitive.material(),
+					&self.buffers,
+					self.path,
+					self.dir,
+					world,
+				)?;
+
+				let entity_name = if count > 1 {
+					format!("{} ({})", name, i)
+				} else {
+					name.clone()
+				};
+				self.entities.push(
+					world
+						.create_entity()
+						.with(transformation::TransformationComponent::default())
+						.with(parent::ParentComponent(entity))
+						.with(model::ModelComponent(mesh))
+						.with(pbr_material::PbrMaterialComponent(material))
+						.with(name::NameComponent(entity_name))
+						.build(),
+				);
+			}
+		}
+
+		for child in node.children() {
+			self.import_node(world, child, Some(entity))?;
+		}
+		Ok(())
+	}
+}
+
+fn open(path: &str) -> Result<(gltf::Gltf, &Path), String> {
+	let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+	let gltf = gltf::Gltf::from_slice(&bytes).map_err(|e| format!("glTF error: {}", e))?;
+	let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+	Ok((gltf, dir))
+}
+
+// Import the default scene of a .gltf or .glb file,
+// creating an entity for every node and mesh primitive,
+// each a child of the node it belongs to.
+// Their materials are drawn with the PBR shader.
+pub fn import(world: &mut World, path: &str) -> Result<Vec<Entity>, String> {
+	let (gltf, dir) = open(path)?;
+	let scene = gltf
+		.default_scene()
+		.or_else(|| gltf.scenes().next())
+		.ok_or_else(|| format!("{} has no scenes", path))?;
+
+	let mut importer = Importer {
+		buffers: load_buffers(&gltf, dir)?,
+		path,
+		dir,
+		entities: Vec::new(),
+	};
+	for node in scene.nodes() {
+		importer.import_node(world, node, None)?;
+	}
+	Ok(importer.entities)
+}
+
+// Load the meshes of a glTF file again and swap the new geometry in
+// behind the existing mesh handles, returning the meshes that changed.
+pub fn reload_meshes(mesh_store: &mut MeshStore, path: &str) -> Result<Vec<HandleId>, String> {
+	let (gltf, dir) = open(path)?;
+	let buffers = load_buffers(&gltf, dir)?;
+	let mut changed = Vec::new();
+	for mesh in gltf.meshes() {
+		for (i, primitive) in mesh.primitives().enumerate() {
+			if let Some(handle) = mesh_store.find(&primitive_key(path, &mesh, i)) {
+				let object = load_primitive(&primitive, &buffers)?;
+				mesh_store.replace(&handle, object.get_mesh());
+				changed.push(handle.id());
+			}
+		}
+	}
+	Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::TempDir;
+
+	// A quad, with `buffer` standing in for the first buffer's fields
+	// and `view` for those of the first view
+	fn write_gltf(dir: &TempDir, buffer: &str, view: &str) -> String {
+		let json = format!(
+			r#"{{
+				"asset": {{"version": "2.0"}},
+				"scene": 0,
+				"scenes": [{{"nodes": [0]}}],
+				"nodes": [{{"mesh": 0}}],
+				"meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1}}]}}],
+				"buffers": [{}],
+				"bufferViews": [{}, {{"buffer": 0, "byteOffset": 48, "byteLength": 12}}],
+				"accessors": [
+					{{"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+					{{"bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR"}}
+				]
+			}}"#,
+			buffer, view
+		);
+		dir.write("scene.gltf", json).to_string_lossy().into_owned()
+	}
+
+	const DATA: &str = "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAACAPwAAgD8AAAAAAAABAAIAAQADAAIA";
+	const VIEW: &str = r#"{"buffer": 0, "byteOffset": 0, "byteLength": 48}"#;
+
+	#[test]
+	fn intact_buffers_load() {
+		let temp = TempDir::new("gltf-intact");
+		let path = write_gltf(
+			&temp,
+			&format!(r#"{{"byteLength": 60, "uri": "{}"}}"#, DATA),
+			VIEW,
+		);
+		let (gltf, dir) = open(&path).unwrap();
+		let buffers = load_buffers(&gltf, dir).unwrap();
+		let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+		assert_eq!(
+			load_primitive(&primitive, &buffers).unwrap().vertexes.len(),
+			4
+		);
+	}
+
+	#[test]
+	fn primitives_share_their_material() {
+		let temp = TempDir::new("gltf-material");
+		let path = write_gltf(
+			&temp,
+			&format!(r#"{{"byteLength": 60, "uri": "{}"}}"#, DATA),
+			VIEW,
+		);
+		let (gltf, dir) = open(&path).unwrap();
+		let buffers = load_buffers(&gltf, dir).unwrap();
+		let mut world = World::new();
+		world.insert(TextureMap::new());
+		world.insert(PbrMaterialStore::new());
+		let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+		let first = load_material(primitive.material(), &buffers, &path, dir, &world).unwrap();
+		let second = load_material(primitive.material(), &buffers, &path, dir, &world).unwrap();
+		assert_eq!(first, second);
+		assert!(world
+			.read_resource::<PbrMaterialStore>()
+			.get(&first)
+			.is_some());
+	}
+
+	#[test]
+	fn truncated_buffers_are_an_error() {
+		// The last twelve bytes, the indices, are cut off
+		let truncated = &DATA[..DATA.len() - 16];
+		let temp = TempDir::new("gltf-truncated");
+		let path = write_gltf(
+			&temp,
+			&format!(r#"{{"byteLength": 60, "uri": "{}"}}"#, truncated),
+			VIEW,
+		);
+		let (gltf, dir) = open(&path).unwrap();
+		let error = load_buffers(&gltf, dir).unwrap_err();
+		assert!(
+			error.contains("has 48 bytes but should have 60"),
+			"{}",
+			error
+		);
+	}
+
+	#[test]
+	fn views_past_the_buffer_are_an_error() {
+		let temp = TempDir::new("gltf-long_view");
+		let path = write_gltf(
+			&temp,
+			&format!(r#"{{"byteLength": 60, "uri": "{}"}}"#, DATA),
+			r#"{"buffer": 0, "byteOffset": 24, "byteLength": 48}"#,
+		);
+		let (gltf, dir) = open(&path).unwrap();
+		let error = load_buffers(&gltf, dir).unwrap_err();
+		assert!(error.contains("past the end"), "{}", error);
+	}
+}
@@ -0,0 +1,261 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
+
+use glium::backend::Facade;
+use glium::index::{IndexBufferAny, PrimitiveType};
+use specs::prelude::*;
+
+use crate::components::{light, shadow};
+use crate::object::VertexArray;
+use crate::resources::asset_store::{AssetStore, Handle, HandleId};
+use crate::resources::mesh_store::{Indices, Mesh};
+use crate::resources::shader_store::ShaderSource;
+use crate::resources::texture_map::RawTexture;
+use crate::resources::*;
+
+pub struct GpuMesh {
+	pub vertices: glium::VertexBuffer<VertexArray>,
+	pub indices: IndexBufferAny,
+}
+
+pub fn upload_mesh<F: Facade>(facade: &F, mesh: &Mesh) -> Result<GpuMesh, String> {
+	let vertices = glium::VertexBuffer::new(facade, &mesh.vertices)
+		.map_err(|e| format!("Could not upload vertices: {:?}", e))?;
+	let indices = match &mesh.indices {
+		Indices::U16(indices) => {
+			glium::IndexBuffer::new(facade, PrimitiveType::TrianglesList, indices)
+				.map(IndexBufferAny::from)
+		}
+		Indices::U32(indices) => {
+			glium::IndexBuffer::new(facade, PrimitiveType::TrianglesList, indices)
+				.map(IndexBufferAny::from)
+		}
+	}
+	.map_err(|e| format!("Could not upload indices: {:?}", e))?;
+	Ok(GpuMesh { vertices, indices })
+}
+
+pub fn upload_texture<F: Facade>(
+	facade: &F,
+	texture: &RawTexture,
+) -> Result<glium::texture::Texture2d, String> {
+	let image = glium::texture::RawImage2d::from_raw_rgba_reversed(
+		&texture.pixels,
+		(texture.width, texture.height),
+	);
+	glium::texture::Texture2d::new(facade, image)
+		.map_err(|e| format!("Could not upload texture: {:?}", e))
+}
+
+// Colors in textures are usually stored in sRGB, which sampling
+// this converts to the linear values the lighting works with
+pub fn upload_srgb_texture<F: Facade>(
+	facade: &F,
+	texture: &RawTexture,
+) -> Result<glium::texture::SrgbTexture2d, String> {
+	let image = glium::texture::RawImage2d::from_raw_rgba_reversed(
+		&texture.pixels,
+		(texture.width, texture.height),
+	);
+	glium::texture::SrgbTexture2d::new(facade, image)
+		.map_err(|e| format!("Could not upload texture: {:?}", e))
+}
+
+// The functions replacing `#shadow_lookups`, with a branch for every shadow map
+fn shadow_lookups() -> String {
+	let mut lookups =
+		String::from("float sample_shadow_map(int slot, vec4 light_space_pos, float bias) {\n");
+	for slot in 0..shadow::MAX_SHADOW_MAPS {
+		lookups.push_str(&format!(
+			"\tif (slot == {0}) return pcf_2d(shadow_maps[{0}], light_space_pos, bias);\n",
+			slot
+		));
+	}
+	lookups.push_str("\treturn 0.0;\n}\n");
+	lookups.push_str("float sample_shadow_cube_map(int slot, vec3 light_to_frag, float far_plane, float bias, float view_distance) {\n");
+	for slot in 0..shadow::MAX_SHADOW_CUBE_MAPS {
+		lookups.push_str(&format!(
+			"\tif (slot == {0}) return pcf_cube(shadow_cube_maps[{0}], light_to_frag, far_plane, bias, view_distance);\n",
+			slot
+		));
+	}
+	lookups.push_str("\treturn 0.0;\n}\n");
+	lookups
+}
+
+// Share constants with the shaders by defining them right after `#version`,
+// and generate the code that depends on them
+fn preprocess(source: &str) -> String {
+	let defines = format!(
+		"#define MAX_LIGHTS {}\n#define MAX_SHADOW_MAPS {}\n#define MAX_SHADOW_CUBE_MAPS {}\n",
+		light::MAX_LIGHTS,
+		shadow::MAX_SHADOW_MAPS,
+		shadow::MAX_SHADOW_CUBE_MAPS,
+	);
+	let source = source
+		.lines()
+		.map(|line| {
+			if line.trim() == "#shadow_lookups" {
+				shadow_lookups()
+			} else {
+				format!("{}\n", line)
+			}
+		})
+		.collect::<String>();
+	match source.find('\n') {
+		Some(end) if source.starts_with("#version") => {
+			format!("{}{}{}", &source[..=end], defines, &source[end + 1..])
+		}
+		_ => format!("{}{}", defines, source),
+	}
+}
+
+pub fn compile_program<F: Facade>(
+	facade: &F,
+	source: &ShaderSource,
+) -> Result<glium::Program, String> {
+	let vertex = preprocess(&source.vertex);
+	let fragment = preprocess(&source.fragment);
+	glium::Program::from_source(facade, &vertex, &fragment, None)
+		.map_err(|e| format!("Could not compile shader: {}", e))
+}
+
+// GPU copies of the assets in an AssetStore, created the first time they are used.
+// Lives outside the World because glium objects can't be shared between threads.
+pub struct GpuStore<G> {
+	pub uploaded: HashMap<HandleId, G>,
+	// Assets that failed to upload, left alone until they're freed
+	failed: HashSet<HandleId>,
+}
+
+impl<G> Default for GpuStore<G> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<G> GpuStore<G> {
+	pub fn new() -> Self {
+		Self {
+			uploaded: HashMap::new(),
+			failed: HashSet::new(),
+		}
+	}
+
+	pub fn get_or_upload<T, U>(
+		&mut self,
+		handle: &Handle<T>,
+		store: &AssetStore<T>,
+		upload: U,
+	) -> Result<&G, String>
+	where
+		U: FnOnce(&T) -> Result<G, String>,
+	{
+		match self.uploaded.entry(handle.id()) {
+			Entry::Occupied(entry) => Ok(entry.into_mut()),
+			Entry::Vacant(entry) => {
+				let asset = store
+					.get(handle)
+					.ok_or_else(|| "Could not find that asset.".to_string())?;
+				Ok(entry.insert(upload(asset)?))
+			}
+		}
+	}
+
+	// Upload an asset if it isn't yet. Failures are reported once, after which
+	// the asset is skipped until it's freed, for instance by reloading it.
+	pub fn upload_or_report<T, U>(&mut self, handle: &Handle<T>, store: &AssetStore<T>, upload: U)
+	where
+		U: FnOnce(&T) -> Result<G, String>,
+	{
+		if self.failed.contains(&handle.id()) && !self.uploaded.contains_key(&handle.id()) {
+			return;
+		}
+		if let Err(e) = self.get_or_upload(handle, store, upload) {
+			eprintln!("Could not prepare {:?}: {}", handle, e);
+			self.failed.insert(handle.id());
+		}
+	}
+
+	// The GPU copy of an asset, if it has been uploaded
+	pub fn get<T>(&self, handle: &Handle<T>) -> Option<&G> {
+		self.uploaded.get(&handle.id())
+	}
+
+	pub fn free(&mut self, ids: &[HandleId]) {
+		for id in ids {
+			self.uploaded.remove(id);
+			self.failed.remove(id);
+		}
+	}
+}
+
+// Everything the renderer keeps on the GPU
+#[derive(Default)]
+pub struct GpuAssets {
+	pub meshes: GpuStore<GpuMesh>,
+	pub textures: GpuStore<glium::texture::Texture2d>,
+	// Color maps, which the same texture assets are uploaded differently for
+	pub srgb_textures: GpuStore<glium::texture::SrgbTexture2d>,
+	pub programs: GpuStore<glium::Program>,
+}
+
+impl GpuAssets {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	// Drop both kinds of GPU copies of textures
+	pub fn free_textures(&mut self, ids: &[HandleId]) {
+		self.textures.free(ids);
+		self.srgb_textures.free(ids);
+	}
+
+	// Release assets that are no longer referenced along with their GPU copies.
+	// Materials go before textures since materials hold on to their texture maps.
+	pub fn maintain(&mut self, world: &World) {
+		world
+			.write_resource::<material_store::MaterialStore>()
+			.maintain();
+		world
+			.write_resource::<material_store::PbrMaterialStore>()
+			.maintain();
+		let freed = world.write_resource::<texture_map::TextureMap>().maintain();
+		self.free_textures(&freed);
+		let freed = world.write_resource::<mesh_store::MeshStore>().maintain();
+		self.meshes.free(&freed);
+		let freed = world
+			.write_resource::<shader_store::ShaderStore>()
+			.maintain();
+		self.programs.free(&freed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn defines_go_after_the_version() {
+		let source = preprocess("#version 330 core\nvoid main() {}\n");
+		assert!(source.starts_with("#version 330 core\n#define MAX_LIGHTS "));
+		assert!(source.ends_with("void main() {}\n"));
+	}
+
+	#[test]
+	fn every_shadow_map_gets_a_lookup() {
+		let source = preprocess("#version 330 core\n#shadow_lookups\n");
+		assert!(!source.contains("#shadow_lookups"));
+		for slot in 0..shadow::MAX_SHADOW_MAPS {
+			assert!(source.contains(&format!("shadow_maps[{}]", slot)));
+		}
+		for slot in 0..shadow::MAX_SHADOW_CUBE_MAPS {
+			assert!(source.contains(&format!("shadow_cube_maps[{}]", slot)));
+		}
+		assert!(!source.contains(&format!("shadow_maps[{}]", shadow::MAX_SHADOW_MAPS)));
+		assert!(!source.contains(&format!(
+			"shadow_cube_maps[{}]",
+			shadow::MAX_SHADOW_CUBE_MAPS
+		)));
+	}
+}
@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use glium::backend::Facade;
+use glutin::dpi::PhysicalSize;
+use specs::prelude::*;
+
+use crate::clip_control::ClipControl;
+use crate::gpu_store::GpuAssets;
+use crate::obj_parser::ParseMode;
+use crate::render_target::{Offscreen, RenderTarget};
+use crate::resources::*;
+use crate::screenshot;
+use crate::shadows::ShadowDebug;
+use crate::systems::{build_dispatcher, render_sys::RenderSystem};
+use crate::texture_views::TextureViews;
+use crate::world;
+
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+// Frames are a fixed time apart, so every run renders the same images
+const TIMESTEP: f32 = 1.0 / 60.0;
+// How far a channel may be off before a pixel counts as different,
+// drivers don't all rasterize exactly alike.
+const TOLERANCE: u8 = 8;
+
+// A context without a window, kept alive along with whatever it needs
+struct HeadlessContext {
+	renderer: glium::HeadlessRenderer,
+	clip_control: ClipControl,
+	_events_loop: Option<glutin::EventsLoop>,
+}
+
+// glClipControl can only be looked up while the context is current, which
+// glium would make it anyway, and before glium takes the context over.
+fn use_context(
+	context: glutin::Context<glutin::NotCurrent>,
+	name: &str,
+	events_loop: Option<glutin::EventsLoop>,
+) -> Result<HeadlessContext, String> {
+	let context = unsafe { context.make_current() }
+		.map_err(|(_, e)| format!("Could not use {} context: {}", name, e))?;
+	let address = context.get_proc_address("glClipControl");
+	let renderer = glium::HeadlessRenderer::new(context)
+		.map_err(|e| format!("Could not use {} context: {}", name, e))?;
+	let clip_control = ClipControl::load(renderer.get_context(), |_| address as *const _);
+	Ok(HeadlessContext {
+		renderer,
+		clip_control,
+		_events_loop: events_loop,
+	})
+}
+
+fn context_builder<'a>() -> glutin::ContextBuilder<'a, glutin::NotCurrent> {
+	glutin::ContextBuilder::new()
+		.with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (3, 3)))
+		.with_gl_profile(glutin::GlProfile::Core)
+		.with_depth_buffer(24)
+}
+
+// OSMesa needs no display server at all, so it's tried first. Failing that we
+// ask for a surfaceless or pbuffer context, which Mesa's llvmpipe provides too.
+fn create_context(width: u32, height: u32) -> Result<HeadlessContext, String> {
+	let size = PhysicalSize::new(f64::from(width), f64::from(height));
+	#[cfg(target_os = "linux")]
+	{
+		use glutin::os::unix::HeadlessContextExt;
+		match context_builder().build_osmesa(size) {
+			Ok(context) => return use_context(context, "OSMesa", None),
+			Err(e) => eprintln!("Could not create OSMesa context: {}", e),
+		}
+		// Creating an events loop with nothing to connect to aborts
+		if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+			return Err("Neither OSMesa nor a display server is available".to_string());
+		}
+	}
+	let events_loop = glutin::EventsLoop::new();
+	let context = context_builder()
+		.build_headless(&events_loop, size)
+		.map_err(|e| format!("Could not create headless context: {}", e))?;
+	use_context(context, "headless", Some(events_loop))
+}
+
+// Render `frames` frames of the scene into `out_dir` as numbered PNGs
+pub fn render(
+	scenes: &[String],
+	frames: usize,
+	out_dir: &Path,
+	mode: ParseMode,
+) -> Result<Vec<PathBuf>, String> {
+	let context = create_context(WIDTH, HEIGHT)?;
+	let facade = context.renderer.get_context();
+	let mut world = world::create_world(scenes, mode)?;
+
+	let gpu = Rc::new(RefCell::new(GpuAssets::new()));
+	let target = Rc::new(RefCell::new(None));
+	let mut dispatcher = build_dispatcher(RenderSystem::new(
+		facade.clone(),
+		target.clone(),
+		gpu.clone(),
+		Rc::new(RefCell::new(ShadowDebug::default())),
+		Rc::new(RefCell::new(TextureViews::default())),
+		context.clip_control,
+		&world,
+	)?);
+	dispatcher.setup(&mut world);
+	{
+		*world.write_resource::<delta_time::DeltaTime>() = delta_time::DeltaTime(TIMESTEP);
+		world.write_resource::<window_size::WindowSize>().0 =
+			glm::vec2(WIDTH as f32, HEIGHT as f32);
+	}
+
+	let mut offscreen = Some(RenderTarget::Offscreen(Offscreen::new(
+		facade, WIDTH, HEIGHT,
+	)?));
+	let mut paths = Vec::new();
+	for frame in 0..frames {
+		world.write_resource::<time::CurrentTime>().0 = frame as f64 * f64::from(TIMESTEP);
+		world.maintain();
+		gpu.borrow_mut().maintain(&world);
+
+		*target.borrow_mut() = offscreen.take();
+		dispatcher.dispatch(&world);
+		offscreen = target.borrow_mut().take();
+
+		let image = match &offscreen {
+			Some(RenderTarget::Offscreen(offscreen)) => offscreen.read_image()?,
+			_ => return Err("The offscreen target went missing".to_string()),
+		};
+		let path = screenshot::frame_path(out_dir, frame);
+		screenshot::save(&image, &path)?;
+		paths.push(path);
+	}
+	Ok(paths)
+}
+
+// Check a rendered image against a reference one, allowing every channel
+// to be off by `tolerance`. Meant for golden-image tests.
+pub fn compare_images(actual: &Path, reference: &Path, tolerance: u8) -> Result<(), String> {
+	let open = |path: &Path| {
+		image::open(path)
+			.map(|image| image.to_rgba())
+			.map_err(|e| format!("Could not open {}: {}", path.display(), e))
+	};
+	let (actual_image, reference_image) = (open(actual)?, open(reference)?);
+	if actual_image.dimensions() != reference_image.dimensions() {
+		return Err(format!(
+			"{} is {:?} but {} is {:?}",
+			actual.display(),
+			actual_image.dimensions(),
+			reference.display(),
+			reference_image.dimensions()
+		));
+	}
+	let different = actual_image
+		.pixels()
+		.zip(reference_image.pixels())
+		.filter(|(a, b)| {
+			a.0.iter()
+				.zip(b.0.iter())
+				.any(|(a, b)| (i16::from(*a) - i16::from(*b)).abs() > i16::from(tolerance))
+		})
+		.count();
+	if different > 0 {
+		return Err(format!(
+			"{} pixels of {} differ from {}",
+			different,
+			actual.display(),
+			reference.display()
+		));
+	}
+	Ok(())
+}
+
+// `--headless <frames> <output dir> [--compare <reference dir>] [scenes...]`
+// Renders without a window, and optionally compares every frame against the
+// file of the same name in the reference directory.
+pub fn run(args: &[String], mode: ParseMode) -> Result<(), String> {
+	let usage = "Usage: --headless <frames> <output dir> [--compare <reference dir>] [scenes...]";
+	let (frames, out_dir, mut rest) = match args {
+		[frames, out_dir, rest @ ..] => (
+			frames.parse::<usize>().map_err(|_| usage.to_string())?,
+			Path::new(out_dir),
+			rest,
+		),
+		_ => return Err(usage.to_string()),
+	};
+	let mut reference_dir = None;
+	if let [flag, dir, scenes @ ..] = rest {
+		if flag == "--compare" {
+			reference_dir = Some(Path::new(dir));
+			rest = scenes;
+		}
+	}
+
+	let paths = render(rest, frames, out_dir, mode)?;
+	println!("Rendered {} frames into {}", paths.len(), out_dir.display());
+
+	if let Some(reference_dir) = reference_dir {
+		let failures: Vec<String> = paths
+			.iter()
+			.filter_map(|path| {
+				let reference = reference_dir.join(path.file_name()?);
+				compare_images(path, &reference, TOLERANCE).err()
+			})
+			.collect();
+		if !failures.is_empty() {
+			return Err(failures.join("\n"));
+		}
+		println!("All frames match {}", reference_dir.display());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::TempDir;
+
+	fn write_image(dir: &TempDir, name: &str, width: u32, height: u32, pixel: [u8; 4]) -> PathBuf {
+		let path = dir.join(name);
+		let image = image::RgbaImage::from_pixel(width, height, image::Rgba(pixel));
+		screenshot::save(&image, &path).unwrap();
+		path
+	}
+
+	#[test]
+	fn images_within_the_tolerance_match() {
+		let dir = TempDir::new("images-within");
+		let reference = write_image(&dir, "reference.png", 4, 4, [100, 100, 100, 255]);
+		let close = write_image(
+			&dir,
+			"close.png",
+			4,
+			4,
+			[100 + TOLERANCE, 100, 100 - TOLERANCE, 255],
+		);
+		assert!(compare_images(&reference, &reference, 0).is_ok());
+		assert!(compare_images(&close, &reference, TOLERANCE).is_ok());
+	}
+
+	#[test]
+	fn images_beyond_the_tolerance_differ() {
+		let dir = TempDir::new("images-beyond");
+		let reference = write_image(&dir, "reference.png", 4, 4, [100, 100, 100, 255]);
+		let far = write_image(&dir, "far.png", 4, 4, [100, 101 + TOLERANCE, 100, 255]);
+		let error = compare_images(&far, &reference, TOLERANCE).unwrap_err();
+		assert!(error.starts_with("16 pixels"), "{}", error);
+	}
+
+	#[test]
+	fn images_of_other_sizes_differ() {
+		let dir = TempDir::new("images-sizes");
+		let reference = write_image(&dir, "reference.png", 4, 4, [0, 0, 0, 255]);
+		let small = write_image(&dir, "small.png", 2, 4, [0, 0, 0, 255]);
+		assert!(compare_images(&small, &reference, TOLERANCE).is_err());
+	}
+
+	// The mesh caches next to the default scene's models
+	fn mesh_caches() -> Vec<PathBuf> {
+		std::fs::read_dir("objs")
+			.map(|entries| {
+				entries
+					.filter_map(|entry| Some(entry.ok()?.path()))
+					.filter(|path| path.extension() == Some("cache".as_ref()))
+					.collect()
+			})
+			.unwrap_or_default()
+	}
+
+	// Renders the default scene and compares it against tests/golden.
+	// Skipped where there are no references yet, or where no context can be
+	// created, which needs OSMesa or a display server.
+	// After a change to the renderer that is meant to alter the output,
+	// `cargo run -- --headless 2 tests/golden` renders new references.
+	#[test]
+	fn golden_images() {
+		let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+		let references: Vec<PathBuf> = (0..2)
+			.map(|frame| screenshot::frame_path(&golden_dir, frame))
+			.collect();
+		if let Some(missing) = references.iter().find(|path| !path.exists()) {
+			eprintln!(
+				"Skipping golden images: {} is missing, `cargo run -- --headless 2 tests/golden` renders it",
+				missing.display()
+			);
+			return;
+		}
+		if let Err(e) = create_context(WIDTH, HEIGHT) {
+			eprintln!("Skipping golden images: {}", e);
+			return;
+		}
+
+		// Loading the scene caches its models, only keep the caches that were already there
+		let caches = mesh_caches();
+		let out_dir = TempDir::new("golden");
+		let rendered = render(&[], 2, out_dir.path(), ParseMode::Strict);
+		for cache in mesh_caches() {
+			if !caches.contains(&cache) {
+				let _ = std::fs::remove_file(cache);
+			}
+		}
+
+		let paths = rendered.unwrap();
+		assert_eq!(paths.len(), references.len());
+		for (path, reference) in paths.iter().zip(&references) {
+			if let Err(e) = compare_images(path, reference, TOLERANCE) {
+				panic!("{}", e);
+			}
+		}
+	}
+}
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use glium::backend::Facade;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use specs::prelude::*;
+
+use crate::gltf_loader;
+use crate::gpu_store::{self, GpuAssets};
+use crate::obj_parser;
+use crate::resources::*;
+use crate::world;
+
+// Watches the files that assets were loaded from, and swaps in
+// new versions of them behind their existing handles when they change.
+pub struct HotReloader {
+	watcher: RecommendedWatcher,
+	events: Receiver<DebouncedEvent>,
+	watched: HashSet<PathBuf>,
+	// Files of assets already seen, so only newly loaded ones are looked at
+	asset_files: HashSet<String>,
+	// Failed reloads by asset, the previous version stays in use meanwhile
+	errors: BTreeMap<String, String>,
+}
+
+// The file a mesh was loaded from, mesh keys look like `path#submesh`
+fn mesh_source(key: &str) -> &str {
+	key.split('#').next().unwrap_or(key)
+}
+
+fn is_changed(path: &str, changed: &HashSet<PathBuf>) -> bool {
+	fs::canonicalize(path)
+		.map(|path| changed.contains(&path))
+		.unwrap_or(false)
+}
+
+impl HotReloader {
+	pub fn new() -> Result<Self, String> {
+		let (sender, events) = channel();
+		// Editors tend to write a file in several steps, wait for them to finish
+		let watcher = notify::watcher(sender, Duration::from_millis(200))
+			.map_err(|e| format!("Could not start file watcher: {}", e))?;
+		Ok(Self {
+			watcher,
+			events,
+			watched: HashSet::new(),
+			asset_files: HashSet::new(),
+			errors: BTreeMap::new(),
+		})
+	}
+
+	// Watch the directory a file is in, rather than the file itself,
+	// so files replaced by a rename are still picked up.
+	fn watch(&mut self, path: &str) {
+		let dir = match Path::new(path).parent() {
+			Some(dir) if dir != Path::new("") => dir,
+			_ => Path::new("."),
+		};
+		let dir = match fs::canonicalize(dir) {
+			Ok(dir) => dir,
+			Err(_) => return,
+		};
+		if self.watched.contains(&dir) {
+			return;
+		}
+		match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+			Ok(()) => {
+				self.watched.insert(dir);
+			}
+			Err(e) => eprintln!("Warning: could not watch {}: {}", dir.display(), e),
+		}
+	}
+
+	// Start watching every asset in the world that was loaded from a file,
+	// including those loaded since the last call
+	fn watch_assets(&mut self, world: &World) {
+		let mut paths = Vec::new();
+		{
+			let texture_map = world.read_resource::<texture_map::TextureMap>();
+			paths.extend(
+				texture_map
+					.paths()
+					.filter(|path| !path.contains('#'))
+					.map(str::to_string),
+			);
+			let mesh_store = world.read_resource::<mesh_store::MeshStore>();
+			paths.extend(mesh_store.paths().map(|key| mesh_source(key).to_string()));
+			let shader_store = world.read_resource::<shader_store::ShaderStore>();
+			for key in shader_store.paths() {
+				if let Some(source) = shader_store.find(key).and_then(|h| shader_store.get(&h)) {
+					paths.extend(source.files().map(str::to_string));
+				}
+			}
+		}
+		for path in paths {
+			if self.asset_files.insert(path.clone()) {
+				self.watch(&path);
+			}
+		}
+	}
+
+	fn changed_files(&self) -> HashSet<PathBuf> {
+		self.events
+			.try_iter()
+			.filter_map(|event| match event {
+				DebouncedEvent::Create(path)
+				| DebouncedEvent::Write(path)
+				| DebouncedEvent::Rename(_, path) => fs::canonicalize(path).ok(),
+				_ => None,
+			})
+			.collect()
+	}
+
+	fn report(&mut self, key: &str, result: Result<(), String>) {
+		match result {
+			Ok(()) => {
+				println!("Reloaded {}", key);
+				self.errors.remove(key);
+			}
+			Err(e) => {
+				eprintln!("Could not reload {}: {}", key, e);
+				self.errors.insert(key.to_string(), e);
+			}
+		}
+	}
+
+	// Reload whatever changed on disk since the last call
+	pub fn update<F: Facade>(&mut self, facade: &F, world: &World, gpu: &mut GpuAssets) {
+		self.watch_assets(world);
+		let changed = self.changed_files();
+		if changed.is_empty() {
+			return;
+		}
+
+		{
+			let mut shader_store = world.write_resource::<shader_store::ShaderStore>();
+			let keys: Vec<String> = shader_store.paths().map(str::to_string).collect();
+			for key in keys {
+				let handle = match shader_store.find(&key) {
+					Some(handle) => handle,
+					None => continue,
+				};
+				let (vertex_path, fragment_path) = match shader_store.get(&handle) {
+					Some(source) if source.files().any(|path| is_changed(path, &changed)) => {
+						(source.vertex_path.clone(), source.fragment_path.clone())
+					}
+					_ => continue,
+				};
+				// Only swap the program out once the new one compiles,
+				// until then we keep drawing with the old one.
+				let result =
+					shader_store::ShaderSource::load_from_files(&vertex_path, &fragment_path)
+						.and_then(|source| {
+							let program = gpu_store::compile_program(facade, &source)?;
+							let files: Vec<String> = source.files().map(str::to_string).collect();
+							gpu.programs.uploaded.insert(handle.id(), program);
+							shader_store.replace(&handle, source);
+							Ok(files)
+						});
+				// The new version may include files we aren't watching yet
+				let result = result.map(|files| {
+					for file in files {
+						self.watch(&file);
+					}
+				});
+				self.report(&key, result);
+			}
+		}
+
+		{
+			let mut texture_map = world.write_resource::<texture_map::TextureMap>();
+			let keys: Vec<String> = texture_map
+				.paths()
+				.filter(|path| !path.contains('#') && is_changed(path, &changed))
+				.map(str::to_string)
+				.collect();
+			for key in keys {
+				let handle = match texture_map.find(&key) {
+					Some(handle) => handle,
+					None => continue,
+				};
+				let result = texture_map::RawTexture::load_from_file(key.clone()).map(|texture| {
+					texture_map.replace(&handle, texture);
+					// It'll be uploaded again the next time it's drawn
+					gpu.free_textures(&[handle.id()]);
+				});
+				self.report(&key, result);
+			}
+		}
+
+		{
+			let mut mesh_store = world.write_resource::<mesh_store::MeshStore>();
+			let mode = *world.read_resource::<obj_parser::ParseMode>();
+			let mut sources: Vec<String> = mesh_store
+				.paths()
+				.map(mesh_source)
+				.filter(|path| is_changed(path, &changed))
+				.map(str::to_string)
+				.collect();
+			sources.sort();
+			sources.dedup();
+			for source in sources {
+				let handles: Vec<_> = mesh_store
+					.paths()
+					.filter(|key| mesh_source(key) == source)
+					.filter_map(|key| mesh_store.find(key))
+					.collect();
+				let reloaded = if source.ends_with(".obj") {
+					world::reload_obj(&mut mesh_store, &source, mode)
+				} else {
+					gltf_loader::reload_meshes(&mut mesh_store, &source)
+				};
+				let result = reloaded.map(|ids| {
+					// They'll be uploaded again the next time they're drawn
+					gpu.meshes.free(&ids);
+					// Meshes the file doesn't have anymore stop being drawn
+					for handle in handles.iter().filter(|handle| !ids.contains(&handle.id())) {
+						if let Some(id) = mesh_store.unload(handle) {
+							gpu.meshes.free(&[id]);
+						}
+					}
+				});
+				self.report(&source, result);
+			}
+		}
+	}
+
+	pub fn draw_ui(&self, ui: &mut imgui::Ui) {
+		use imgui::Condition;
+		if self.errors.is_empty() {
+			return;
+		}
+		imgui::Window::new(ui, im_str!("Reload errors"))
+			.size([500.0, 200.0], Condition::FirstUseEver)
+			.position([50.0, 350.0], Condition::FirstUseEver)
+			.build(|| {
+				for (key, error) in &self.errors {
+					ui.text_colored([1.0, 0.4, 0.4, 1.0], key);
+					ui.text(error);
+					ui.separator();
+				}
+			});
+	}
+}
@@ -1,7 +1,5 @@
-extern crate gl;
-extern crate glutin;
-#[macro_use]
 extern crate glium;
+extern crate glutin;
 
 extern crate image;
 
@@ -21,15 +19,33 @@ extern crate shred_derive;
 
 use std::time::Instant;
 
-use std::fs;
-use std::io::Cursor;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::thread;
 
+mod bounds;
+mod cameras;
+mod clip_control;
+mod gltf_loader;
+mod gpu_store;
+mod headless;
+mod hot_reload;
 mod object;
 #[macro_use]
 mod macros;
+mod mesh_cache;
+mod mesh_processing;
+mod mtl_parser;
 mod obj_parser;
+mod picking;
 mod profiler;
+mod render_target;
+mod screenshot;
+mod shadows;
+#[cfg(test)]
+mod test_util;
+mod texture_views;
+mod uniforms;
 
 mod components;
 mod resources;
@@ -39,11 +55,11 @@ mod world;
 
 use specs::prelude::*;
 
-use components::*;
+use render_target::RenderTarget;
 use resources::*;
 use systems::*;
 
-use glium::Surface;
+use glium::backend::Facade;
 
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
 
@@ -55,6 +71,18 @@ const SCR_HEIGHT: f64 = 720.0;
 /* Holy what the heck! */
 
 pub fn main() -> Result<(), String> {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	// `--strict` makes any malformed line of an OBJ or MTL file fail the load
+	let mode = if args.iter().any(|arg| arg == "--strict") {
+		obj_parser::ParseMode::Strict
+	} else {
+		obj_parser::ParseMode::Lenient
+	};
+	let args: Vec<String> = args.into_iter().filter(|arg| arg != "--strict").collect();
+	if args.first().map(String::as_str) == Some("--headless") {
+		return headless::run(&args[1..], mode);
+	}
+
 	let mut event_loop = glutin::EventsLoop::new();
 	let wb = glutin::WindowBuilder::new()
 		.with_dimensions(glutin::dpi::LogicalSize::new(SCR_WIDTH, SCR_HEIGHT))
@@ -62,16 +90,8 @@ pub fn main() -> Result<(), String> {
 	let cb = glutin::ContextBuilder::new().with_depth_buffer(24);
 	let display = glium::Display::new(wb, cb, &event_loop).unwrap();
 
-	gl::load_with(|s| display.gl_window().get_proc_address(&s) as _);
-
-	let mut world = world::create_world()?;
-	let mut dispatcher = DispatcherBuilder::new()
-		// .with_thread_local(render_sys::RenderSystem)
-		.with(logger_sys::LoggerSystem, "logger_system", &[])
-		.with(camera_sys::CameraSystem, "camera_system", &[])
-		// .with(input_sys::InputSystem, "input_system", &[])
-		.build();
-	dispatcher.setup(&mut world);
+	// Any arguments are extra scenes to load
+	let mut world = world::create_world(&args, mode)?;
 
 	let mut imgui = Context::create();
 	imgui.set_ini_filename(None);
@@ -82,26 +102,29 @@ pub fn main() -> Result<(), String> {
 	{
 		let gl_window = display.gl_window();
 		let window = gl_window.window();
-		platform.attach_window(imgui.io_mut(), &window, HiDpiMode::Rounded);
+		platform.attach_window(imgui.io_mut(), window, HiDpiMode::Rounded);
 	}
 
-	let vertex_shader = fs::read_to_string("./vertex.vs").expect("Can't read vertex shader");
-	let fragment_shader = fs::read_to_string("./fragment.fs").expect("Can't read fragment shader");
-	let image = image::load(
-		Cursor::new(&include_bytes!("../assets/textures/wall.jpg")[..]),
-		image::JPEG,
-	)
-	.unwrap()
-	.to_rgba();
-	let image_dimensions = image.dimensions();
-	let image =
-		glium::texture::RawImage2d::from_raw_rgba_reversed(&image.into_raw(), image_dimensions);
-	let texture = glium::texture::Texture2d::new(&display, image).unwrap();
-
-	let program =
-		glium::Program::from_source(&display, &vertex_shader, &fragment_shader, None).unwrap();
+	let gpu = Rc::new(RefCell::new(gpu_store::GpuAssets::new()));
+	let frame = Rc::new(RefCell::new(None));
+	let shadow_debug = Rc::new(RefCell::new(shadows::ShadowDebug::default()));
+	let camera_views = Rc::new(RefCell::new(texture_views::TextureViews::default()));
+	let mut dispatcher = build_dispatcher(render_sys::RenderSystem::new(
+		display.get_context().clone(),
+		frame.clone(),
+		gpu.clone(),
+		shadow_debug.clone(),
+		camera_views.clone(),
+		clip_control::ClipControl::load(display.get_context(), |symbol| {
+			display.gl_window().get_proc_address(symbol) as *const _
+		}),
+		&world,
+	)?);
+	dispatcher.setup(&mut world);
 
 	let mut profiler = profiler::Profiler::new(100);
+	// Watches assets as they get loaded
+	let mut hot_reload = hot_reload::HotReloader::new()?;
 
 	let mut last_frame = Instant::now();
 	let mut closed = false;
@@ -114,8 +137,13 @@ pub fn main() -> Result<(), String> {
 		let delta_time = delta.as_secs() as f32 + delta.subsec_nanos() as f32 / 1_000_000_000.0;
 		profiler.record_delay(delta_time);
 		{
+			// Recordings advance time by a fixed step, however long frames take
+			let timestep = world
+				.read_resource::<capture::Capture>()
+				.timestep(delta_time);
 			let mut delta = world.write_resource::<delta_time::DeltaTime>();
-			*delta = delta_time::DeltaTime(delta_time);
+			*delta = delta_time::DeltaTime(timestep);
+			world.write_resource::<time::CurrentTime>().0 += f64::from(timestep);
 		}
 
 		// EVENT LOOP
@@ -123,69 +151,73 @@ pub fn main() -> Result<(), String> {
 			let mut key_state = world.write_resource::<key_state::Keystate>();
 			let mut mouse_state = world.write_resource::<mouse_state::MouseState>();
 			mouse_state.update_delta();
+			key_state.clear_pressed();
+			mouse_state.clear_pressed();
 
 			event_loop.poll_events(|event| {
-				platform.handle_event(imgui.io_mut(), &window, &event);
+				platform.handle_event(imgui.io_mut(), window, &event);
 				mouse_state.handle_event(&event);
 				key_state.handle_event(&event);
-				match event {
-					glutin::Event::WindowEvent { event, .. } => match event {
-						glutin::WindowEvent::CloseRequested => closed = true,
-						_ => (),
-					},
-					_ => (),
+				if let glutin::Event::WindowEvent {
+					event: glutin::WindowEvent::CloseRequested,
+					..
+				} = event
+				{
+					closed = true;
 				}
 			});
 		}
 		let size = window.get_inner_size().expect("Could not get window size");
-		{
-			let camera = world.read_resource::<camera::Camera>();
-			let mut projection = world.write_resource::<projection::Projection>();
-			projection.0 = glm::perspective(
-				(size.width as f32) / (size.height as f32),
-				camera.zoom,
-				0.1,
-				10000.0,
-			);
-		}
-		dispatcher.dispatch(&world);
+		world.write_resource::<window_size::WindowSize>().0 =
+			glm::vec2(size.width as f32, size.height as f32);
+		world.maintain();
+		gpu.borrow_mut().maintain(&world);
+		hot_reload.update(&display, &world, &mut gpu.borrow_mut());
 
 		// IMGUI PREPARE
 		let io = imgui.io_mut();
 		last_frame = io.update_delta_time(last_frame);
+		// Clicks on the overlay shouldn't reach the scene behind it
+		world.write_resource::<mouse_state::MouseState>().over_ui = io.want_capture_mouse;
 		let mut ui = imgui.frame();
-		profiler.draw_ui(delta_time, &mut ui);
+		profiler.draw_ui(
+			delta_time,
+			&world.read_resource::<render_stats::RenderStats>(),
+			&mut ui,
+		);
+		hot_reload.draw_ui(&mut ui);
+		picking::draw_ui(&ui, &world);
+		world.write_resource::<camera::Camera>().draw_ui(&ui);
+		world
+			.write_resource::<projection::Projection>()
+			.draw_ui(&ui);
+		shadows::draw_ui(
+			&ui,
+			&world,
+			&mut shadow_debug.borrow_mut(),
+			renderer.textures(),
+		);
+		cameras::draw_ui(
+			&ui,
+			&world,
+			&mut camera_views.borrow_mut(),
+			renderer.textures(),
+		);
 
-		let mut target = display.draw();
-		target.clear_color_srgb_and_depth((0.0, 0.0, 0.0, 1.0), 24.0);
 		// SCENE RENDER
-		{
-			let trans = world.read_component::<transformation::TransformationComponent>();
-			let models = world.read_component::<model::ModelComponent>();
-			let materials = world.read_component::<material::MaterialComponent>();
-			let camera = world.read_resource::<camera::Camera>();
-			let projection = world.read_resource::<projection::Projection>();
-
-			for (trans, model, material) in (&trans, &models, &materials).join() {
-				let uniforms = uniform! {
-					camera: *camera.get_view_matrix().as_ref(),
-					projection: *projection.0.as_ref(),
-					model: *trans.0.as_ref(),
-					our_texture: &texture,
-				};
-				let vertex_buffer = glium::VertexBuffer::new(&display, &model.vertices).unwrap();
-				let params = glium::DrawParameters {
-					depth: glium::Depth {
-						test: glium::draw_parameters::DepthTest::IfLess,
-						write: true,
-						..Default::default()
-					},
-					..Default::default()
-				};
-				target
-					.draw(&vertex_buffer, &model.indices, &program, &uniforms, &params)
-					.unwrap();
-			}
+		*frame.borrow_mut() = Some(RenderTarget::Frame(display.draw()));
+		dispatcher.dispatch(&world);
+		let mut target = match frame.borrow_mut().take() {
+			Some(RenderTarget::Frame(target)) => target,
+			_ => panic!("The frame went missing"),
+		};
+		// Screenshots and recordings leave out the overlay
+		if let Err(e) = screenshot::capture_frame(
+			&display,
+			&target,
+			&mut world.write_resource::<capture::Capture>(),
+		) {
+			eprintln!("Could not capture frame: {}", e);
 		}
 
 		// IMGUI RENDER
@@ -0,0 +1,567 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::obj_parser::{ErrorReason, ParseError, SubMesh};
+use crate::object::{Object, Vertex, VertexIndex};
+
+// Layout of a cache file, all numbers little endian:
+//
+//   magic     b"APMC"
+//   version   u32
+//   source    u64 length and u32 CRC32 of the OBJ file it was made from
+//   checksum  u32, CRC32 of the payload
+//   length    u64, length of the payload
+//   payload   material libraries, submeshes, then unknown directives
+//
+// Bump VERSION whenever the payload layout or the
+// processing done by the OBJ loader changes.
+const MAGIC: &[u8; 4] = b"APMC";
+pub const VERSION: u32 = 2;
+const HEADER_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum CacheError {
+	Io(io::Error),
+	BadMagic,
+	UnsupportedVersion(u32),
+	// Made from a different version of the source
+	Stale,
+	ChecksumMismatch,
+	Truncated,
+	InvalidData,
+}
+
+impl fmt::Display for CacheError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CacheError::Io(e) => write!(f, "could not access mesh cache: {}", e),
+			CacheError::BadMagic => write!(f, "not a mesh cache file"),
+			CacheError::UnsupportedVersion(v) => {
+				write!(f, "mesh cache version {} is not {}", v, VERSION)
+			}
+			CacheError::Stale => write!(f, "mesh cache is out of date"),
+			CacheError::ChecksumMismatch => write!(f, "mesh cache is corrupt"),
+			CacheError::Truncated => write!(f, "mesh cache is truncated"),
+			CacheError::InvalidData => write!(f, "mesh cache contains invalid data"),
+		}
+	}
+}
+
+impl Error for CacheError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			CacheError::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for CacheError {
+	fn from(e: io::Error) -> Self {
+		CacheError::Io(e)
+	}
+}
+
+// Where the cache for a source file lives
+pub fn cache_path(source: &str) -> PathBuf {
+	PathBuf::from(format!("{}.cache", source))
+}
+
+// The OBJ file a cache is made from, which the cache is only usable for
+// as long as the contents stay the same. Modification times can't tell,
+// they are too coarse on some filesystems and are reset by copies and checkouts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Source<'a> {
+	pub path: &'a str,
+	len: u64,
+	crc: u32,
+}
+
+impl<'a> Source<'a> {
+	pub fn read(path: &'a str) -> io::Result<Self> {
+		let mut reader = BufReader::new(File::open(path)?);
+		let mut hasher = crc32fast::Hasher::new();
+		let mut len = 0;
+		loop {
+			let buf = reader.fill_buf()?;
+			if buf.is_empty() {
+				break;
+			}
+			hasher.update(buf);
+			let read = buf.len();
+			len += read as u64;
+			reader.consume(read);
+		}
+		Ok(Self {
+			path,
+			len,
+			crc: hasher.finalize(),
+		})
+	}
+}
+
+// What a cache holds, the unknown directives so that loading from the
+// cache warns about them just like parsing did.
+pub struct Cached {
+	pub submeshes: Vec<SubMesh>,
+	pub libraries: Vec<String>,
+	pub warnings: Vec<ParseError>,
+}
+
+#[derive(Default)]
+struct Writer {
+	bytes: Vec<u8>,
+}
+
+impl Writer {
+	fn u32(&mut self, value: u32) {
+		self.bytes.extend_from_slice(&value.to_le_bytes());
+	}
+
+	fn u64(&mut self, value: u64) {
+		self.bytes.extend_from_slice(&value.to_le_bytes());
+	}
+
+	fn f32(&mut self, value: f32) {
+		self.bytes.extend_from_slice(&value.to_le_bytes());
+	}
+
+	fn floats(&mut self, values: &[f32]) {
+		for &value in values {
+			self.f32(value);
+		}
+	}
+
+	fn string(&mut self, value: &str) {
+		self.u32(value.len() as u32);
+		self.bytes.extend_from_slice(value.as_bytes());
+	}
+
+	// Zero stands in for None, as lines and columns start at 1
+	fn position(&mut self, value: Option<usize>) {
+		self.u32(value.unwrap_or(0) as u32);
+	}
+
+	fn object(&mut self, obj: &Object) {
+		self.u32(obj.vertexes.len() as u32);
+		for vertex in &obj.vertexes {
+			self.floats(vertex.position.as_slice());
+			self.floats(vertex.normal.as_slice());
+			self.floats(vertex.uv.as_slice());
+			match vertex.tangent {
+				Some(tangent) => {
+					self.u32(1);
+					self.floats(tangent.as_slice());
+				}
+				None => {
+					self.u32(0);
+					self.floats(&[0.0; 4]);
+				}
+			}
+		}
+		self.u32(obj.triangle_indices.len() as u32);
+		for (i, VertexIndex(a, b, c)) in obj.triangle_indices.iter().enumerate() {
+			self.u32(*a);
+			self.u32(*b);
+			self.u32(*c);
+			self.u32(obj.smoothing_groups.get(i).copied().unwrap_or(0));
+		}
+	}
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], CacheError> {
+		let end = self.pos.checked_add(len).ok_or(CacheError::Truncated)?;
+		let slice = self.bytes.get(self.pos..end).ok_or(CacheError::Truncated)?;
+		self.pos = end;
+		Ok(slice)
+	}
+
+	fn u32(&mut self) -> Result<u32, CacheError> {
+		let mut buf = [0; 4];
+		buf.copy_from_slice(self.take(4)?);
+		Ok(u32::from_le_bytes(buf))
+	}
+
+	fn u64(&mut self) -> Result<u64, CacheError> {
+		Ok(u64::from(self.u32()?) | (u64::from(self.u32()?) << 32))
+	}
+
+	fn f32(&mut self) -> Result<f32, CacheError> {
+		Ok(f32::from_bits(self.u32()?))
+	}
+
+	fn vec2(&mut self) -> Result<glm::Vec2, CacheError> {
+		Ok(glm::vec2(self.f32()?, self.f32()?))
+	}
+
+	fn vec3(&mut self) -> Result<glm::Vec3, CacheError> {
+		Ok(glm::vec3(self.f32()?, self.f32()?, self.f32()?))
+	}
+
+	fn vec4(&mut self) -> Result<glm::Vec4, CacheError> {
+		Ok(glm::vec4(
+			self.f32()?,
+			self.f32()?,
+			self.f32()?,
+			self.f32()?,
+		))
+	}
+
+	fn string(&mut self) -> Result<String, CacheError> {
+		let len = self.u32()? as usize;
+		let bytes = self.take(len)?;
+		String::from_utf8(bytes.to_vec()).map_err(|_| CacheError::InvalidData)
+	}
+
+	fn position(&mut self) -> Result<Option<usize>, CacheError> {
+		Ok(match self.u32()? {
+			0 => None,
+			value => Some(value as usize),
+		})
+	}
+
+	fn object(&mut self) -> Result<Object, CacheError> {
+		let mut obj = Object::default();
+		let vertex_count = self.u32()?;
+		for _ in 0..vertex_count {
+			let position = self.vec3()?;
+			let normal = self.vec3()?;
+			let uv = self.vec2()?;
+			let has_tangent = self.u32()? != 0;
+			let tangent = self.vec4()?;
+			obj.vertexes.push(Vertex {
+				position,
+				normal,
+				uv,
+				tangent: if has_tangent { Some(tangent) } else { None },
+			});
+		}
+		let triangle_count = self.u32()?;
+		for _ in 0..triangle_count {
+			let triangle = VertexIndex(self.u32()?, self.u32()?, self.u32()?);
+			if triangle.0.max(triangle.1).max(triangle.2) >= vertex_count {
+				return Err(CacheError::InvalidData);
+			}
+			obj.triangle_indices.push(triangle);
+			obj.smoothing_groups.push(self.u32()?);
+		}
+		Ok(obj)
+	}
+}
+
+// Only the unknown directive warnings are kept,
+// any others mean lines were skipped and nothing should be cached.
+pub fn write(
+	path: &Path,
+	source: &Source,
+	submeshes: &[SubMesh],
+	libraries: &[String],
+	warnings: &[ParseError],
+) -> Result<(), CacheError> {
+	let mut payload = Writer::default();
+	payload.u32(libraries.len() as u32);
+	for library in libraries {
+		payload.string(library);
+	}
+	payload.u32(submeshes.len() as u32);
+	for submesh in submeshes {
+		match &submesh.material {
+			Some(material) => {
+				payload.u32(1);
+				payload.string(material);
+			}
+			None => payload.u32(0),
+		}
+		payload.object(&submesh.object);
+	}
+	let unknown: Vec<&ParseError> = warnings
+		.iter()
+		.filter(|warning| matches!(warning.reason, ErrorReason::UnknownDirective))
+		.collect();
+	payload.u32(unknown.len() as u32);
+	for warning in unknown {
+		payload.position(warning.line);
+		payload.position(warning.column);
+		payload.string(warning.token.as_deref().unwrap_or_default());
+	}
+
+	let mut file = Writer {
+		bytes: Vec::with_capacity(HEADER_LEN + payload.bytes.len()),
+	};
+	file.bytes.extend_from_slice(MAGIC);
+	file.u32(VERSION);
+	file.u64(source.len);
+	file.u32(source.crc);
+	file.u32(crc32fast::hash(&payload.bytes));
+	file.u64(payload.bytes.len() as u64);
+	file.bytes.extend_from_slice(&payload.bytes);
+
+	// Write next to the destination and move it in place,
+	// so a crash never leaves a half written cache behind.
+	let temp = path.with_extension("cache.tmp");
+	fs::write(&temp, &file.bytes)?;
+	fs::rename(&temp, path)?;
+	Ok(())
+}
+
+pub fn read(path: &Path, source: &Source) -> Result<Cached, CacheError> {
+	let file = File::open(path)?;
+	// Safety: cache files are only ever replaced through a rename,
+	// never modified in place, so the mapping can't change under us.
+	let map = unsafe { Mmap::map(&file)? };
+
+	let mut header = Reader {
+		bytes: &map,
+		pos: 0,
+	};
+	if header.take(4)? != MAGIC {
+		return Err(CacheError::BadMagic);
+	}
+	let version = header.u32()?;
+	if version != VERSION {
+		return Err(CacheError::UnsupportedVersion(version));
+	}
+	if header.u64()? != source.len || header.u32()? != source.crc {
+		return Err(CacheError::Stale);
+	}
+	let checksum = header.u32()?;
+	let length = header.u64()?;
+	let payload = header.take(length as usize)?;
+	if crc32fast::hash(payload) != checksum {
+		return Err(CacheError::ChecksumMismatch);
+	}
+
+	let mut reader = Reader {
+		bytes: payload,
+		pos: 0,
+	};
+	let library_count = reader.u32()?;
+	let mut libraries = Vec::new();
+	for _ in 0..library_count {
+		libraries.push(reader.string()?);
+	}
+	let submesh_count = reader.u32()?;
+	let mut submeshes = Vec::new();
+	for _ in 0..submesh_count {
+		let material = match reader.u32()? {
+			0 => None,
+			_ => Some(reader.string()?),
+		};
+		submeshes.push(SubMesh {
+			material,
+			object: reader.object()?,
+		});
+	}
+	let warning_count = reader.u32()?;
+	let mut warnings = Vec::new();
+	for _ in 0..warning_count {
+		warnings.push(ParseError {
+			path: source.path.to_string(),
+			line: reader.position()?,
+			column: reader.position()?,
+			token: Some(reader.string()?),
+			reason: ErrorReason::UnknownDirective,
+		});
+	}
+	Ok(Cached {
+		submeshes,
+		libraries,
+		warnings,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::TempDir;
+
+	fn triangle() -> Object {
+		let vertex = |x: f32, y: f32, tangent| Vertex {
+			position: glm::vec3(x, y, 0.0),
+			normal: glm::vec3(0.0, 0.0, 1.0),
+			uv: glm::vec2(x, y),
+			tangent,
+		};
+		Object {
+			vertexes: vec![
+				vertex(0.0, 0.0, Some(glm::vec4(1.0, 0.0, 0.0, -1.0))),
+				vertex(1.0, 0.0, None),
+				vertex(0.0, 1.0, None),
+			],
+			triangle_indices: vec![VertexIndex(0, 1, 2)],
+			smoothing_groups: vec![3],
+			..Default::default()
+		}
+	}
+
+	const OBJ: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvp 1 2\nf 1 2 3\n";
+
+	fn source(dir: &TempDir, contents: &str) -> String {
+		dir.write("model.obj", contents)
+			.to_string_lossy()
+			.into_owned()
+	}
+
+	// What parsing OBJ warns about
+	fn unknown_directive(path: &str) -> ParseError {
+		ParseError {
+			path: path.to_string(),
+			line: Some(4),
+			column: Some(1),
+			token: Some("vp".to_string()),
+			reason: ErrorReason::UnknownDirective,
+		}
+	}
+
+	// A valid cache file made from OBJ, in a directory of its own, and its bytes
+	fn written(dir: &TempDir) -> (PathBuf, String, Vec<u8>) {
+		let obj = source(dir, OBJ);
+		let path = dir.join("model.cache");
+		let submeshes = [
+			SubMesh {
+				material: Some("brick".to_string()),
+				object: triangle(),
+			},
+			SubMesh {
+				material: None,
+				object: triangle(),
+			},
+		];
+		let warnings = [
+			unknown_directive(&obj),
+			ParseError::io(&obj, io::Error::from(io::ErrorKind::Other)),
+		];
+		let source = Source::read(&obj).unwrap();
+		write(
+			&path,
+			&source,
+			&submeshes,
+			&["scene.mtl".to_string()],
+			&warnings,
+		)
+		.unwrap();
+		let bytes = fs::read(&path).unwrap();
+		(path, obj, bytes)
+	}
+
+	fn read_back(path: &Path, obj: &str) -> Result<Cached, CacheError> {
+		read(path, &Source::read(obj).unwrap())
+	}
+
+	#[test]
+	fn round_trip() {
+		let dir = TempDir::new("cache-round_trip");
+		let (path, obj, _) = written(&dir);
+		let cached = read_back(&path, &obj).unwrap();
+		assert_eq!(cached.libraries, vec!["scene.mtl".to_string()]);
+		assert_eq!(cached.submeshes.len(), 2);
+		assert_eq!(cached.submeshes[0].material.as_deref(), Some("brick"));
+		assert_eq!(cached.submeshes[1].material, None);
+
+		let (read_back, original) = (&cached.submeshes[0].object, triangle());
+		assert_eq!(read_back.vertexes.len(), 3);
+		for (a, b) in read_back.vertexes.iter().zip(original.vertexes.iter()) {
+			assert_eq!(a.position, b.position);
+			assert_eq!(a.normal, b.normal);
+			assert_eq!(a.uv, b.uv);
+			assert_eq!(a.tangent, b.tangent);
+		}
+		let VertexIndex(a, b, c) = read_back.triangle_indices[0];
+		assert_eq!((a, b, c), (0, 1, 2));
+		assert_eq!(read_back.smoothing_groups, vec![3]);
+	}
+
+	#[test]
+	fn unknown_directives_are_kept() {
+		let dir = TempDir::new("cache-warnings");
+		let (path, obj, _) = written(&dir);
+		let warnings = read_back(&path, &obj).unwrap().warnings;
+		// Without the error that wasn't about an unknown directive
+		assert_eq!(warnings.len(), 1);
+		let expected = unknown_directive(&obj);
+		assert!(matches!(warnings[0].reason, ErrorReason::UnknownDirective));
+		assert_eq!(warnings[0].path, expected.path);
+		assert_eq!(warnings[0].line, expected.line);
+		assert_eq!(warnings[0].column, expected.column);
+		assert_eq!(warnings[0].token, expected.token);
+	}
+
+	#[test]
+	fn changed_sources_make_the_cache_stale() {
+		let dir = TempDir::new("cache-stale");
+		let (path, _, _) = written(&dir);
+		// The same length and modification time, but different contents
+		let modified = fs::metadata(dir.join("model.obj"))
+			.and_then(|m| m.modified())
+			.unwrap();
+		let obj = source(&dir, &OBJ.replace("v 1 0 0", "v 2 0 0"));
+		File::options()
+			.write(true)
+			.open(&obj)
+			.and_then(|f| f.set_modified(modified))
+			.unwrap();
+		assert!(matches!(read_back(&path, &obj), Err(CacheError::Stale)));
+		// And anything else, like another file
+		let other = source(&dir, "v 0 0 0\n");
+		assert!(matches!(read_back(&path, &other), Err(CacheError::Stale)));
+	}
+
+	#[test]
+	fn corrupt_payloads_fail_the_checksum() {
+		let dir = TempDir::new("cache-bad_crc");
+		let (path, obj, mut bytes) = written(&dir);
+		let last = bytes.len() - 1;
+		bytes[last] ^= 0xff;
+		fs::write(&path, &bytes).unwrap();
+		assert!(matches!(
+			read_back(&path, &obj),
+			Err(CacheError::ChecksumMismatch)
+		));
+	}
+
+	#[test]
+	fn other_versions_are_rejected() {
+		let dir = TempDir::new("cache-bad_version");
+		let (path, obj, mut bytes) = written(&dir);
+		bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+		fs::write(&path, &bytes).unwrap();
+		assert!(matches!(
+			read_back(&path, &obj),
+			Err(CacheError::UnsupportedVersion(version)) if version == VERSION + 1
+		));
+	}
+
+	#[test]
+	fn other_files_are_rejected() {
+		let dir = TempDir::new("cache-bad_magic");
+		let (path, obj, mut bytes) = written(&dir);
+		bytes[..4].copy_from_slice(b"OBJ ");
+		fs::write(&path, &bytes).unwrap();
+		assert!(matches!(read_back(&path, &obj), Err(CacheError::BadMagic)));
+	}
+
+	#[test]
+	fn truncated_files_are_rejected() {
+		let dir = TempDir::new("cache-truncated");
+		let (path, obj, bytes) = written(&dir);
+		for &len in &[0, 10, HEADER_LEN, bytes.len() - 1] {
+			fs::write(&path, &bytes[..len]).unwrap();
+			assert!(
+				matches!(read_back(&path, &obj), Err(CacheError::Truncated)),
+				"{} bytes",
+				len
+			);
+		}
+	}
+}
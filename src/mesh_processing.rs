@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use crate::object::{bits, Object, VertexIndex};
+
+// A normal of all zeroes means the file didn't provide one
+fn is_missing(normal: &glm::Vec3) -> bool {
+	glm::length2(normal) == 0.0
+}
+
+fn position_key(position: &glm::Vec3) -> [u32; 3] {
+	[bits(position.x), bits(position.y), bits(position.z)]
+}
+
+// The interior angle of the triangle at `a`
+fn corner_angle(a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> f32 {
+	let ab = b - a;
+	let ac = c - a;
+	if glm::length2(&ab) == 0.0 || glm::length2(&ac) == 0.0 {
+		return 0.0;
+	}
+	glm::angle(&ab, &ac)
+}
+
+fn face_normal(a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3) -> glm::Vec3 {
+	let normal = (b - a).cross(&(c - a));
+	if glm::length2(&normal) == 0.0 {
+		normal
+	} else {
+		normal.normalize()
+	}
+}
+
+fn corners(triangle: &VertexIndex) -> [usize; 3] {
+	let VertexIndex(a, b, c) = *triangle;
+	[a as usize, b as usize, c as usize]
+}
+
+fn smoothing_group(obj: &Object, triangle: usize) -> u32 {
+	obj.smoothing_groups.get(triangle).copied().unwrap_or(0)
+}
+
+// Fill in the normals of vertices that don't have one.
+// Triangles in smoothing group 0 get flat normals, the others get the
+// angle-weighted average of all faces in the same group sharing the position.
+//
+// Every triangle ends up with its own vertices,
+// so this should be followed by `Object::deduplicate`.
+pub fn generate_normals(obj: &mut Object) {
+	if !obj.vertexes.iter().any(|v| is_missing(&v.normal)) {
+		return;
+	}
+
+	let mut smooth: HashMap<([u32; 3], u32), glm::Vec3> = HashMap::new();
+	let mut face_normals = Vec::with_capacity(obj.triangle_indices.len());
+	for (i, triangle) in obj.triangle_indices.iter().enumerate() {
+		let group = smoothing_group(obj, i);
+		let idx = corners(triangle);
+		let p = [
+			obj.vertexes[idx[0]].position,
+			obj.vertexes[idx[1]].position,
+			obj.vertexes[idx[2]].position,
+		];
+		let normal = face_normal(&p[0], &p[1], &p[2]);
+		face_normals.push(normal);
+		if group == 0 {
+			continue;
+		}
+		for k in 0..3 {
+			let angle = corner_angle(&p[k], &p[(k + 1) % 3], &p[(k + 2) % 3]);
+			*smooth
+				.entry((position_key(&p[k]), group))
+				.or_insert_with(glm::Vec3::zeros) += normal * angle;
+		}
+	}
+
+	let mut vertexes = Vec::with_capacity(obj.triangle_indices.len() * 3);
+	let mut triangle_indices = Vec::with_capacity(obj.triangle_indices.len());
+	for (i, (triangle, flat)) in obj.triangle_indices.iter().zip(&face_normals).enumerate() {
+		let group = smoothing_group(obj, i);
+		let first = vertexes.len() as u32;
+		for &idx in &corners(triangle) {
+			let mut vertex = obj.vertexes[idx].clone();
+			if is_missing(&vertex.normal) {
+				vertex.normal = match smooth.get(&(position_key(&vertex.position), group)) {
+					Some(sum) if glm::length2(sum) > 0.0 => sum.normalize(),
+					_ => *flat,
+				};
+			}
+			vertexes.push(vertex);
+		}
+		triangle_indices.push(VertexIndex(first, first + 1, first + 2));
+	}
+
+	obj.vertexes = vertexes;
+	obj.triangle_indices = triangle_indices;
+}
+
+// Any unit vector perpendicular to `normal`
+fn perpendicular(normal: &glm::Vec3) -> glm::Vec3 {
+	if glm::length2(normal) == 0.0 {
+		return glm::vec3(1.0, 0.0, 0.0);
+	}
+	let axis = if normal.x.abs() < 0.9 {
+		glm::vec3(1.0, 0.0, 0.0)
+	} else {
+		glm::vec3(0.0, 1.0, 0.0)
+	};
+	normal.cross(&axis).normalize()
+}
+
+// Compute per-vertex tangents from the texture coordinates, in the spirit of
+// MikkTSpace: per-face tangents and bitangents are angle-weighted onto each
+// corner, then orthogonalized against the vertex normal. The handedness of
+// the basis is stored in the tangent's w.
+pub fn generate_tangents(obj: &mut Object) {
+	let mut tangents = vec![glm::Vec3::zeros(); obj.vertexes.len()];
+	let mut bitangents = vec![glm::Vec3::zeros(); obj.vertexes.len()];
+
+	for triangle in &obj.triangle_indices {
+		let idx = corners(triangle);
+		let v = [
+			&obj.vertexes[idx[0]],
+			&obj.vertexes[idx[1]],
+			&obj.vertexes[idx[2]],
+		];
+		let edge1 = v[1].position - v[0].position;
+		let edge2 = v[2].position - v[0].position;
+		let duv1 = v[1].uv - v[0].uv;
+		let duv2 = v[2].uv - v[0].uv;
+
+		let det = duv1.x * duv2.y - duv2.x * duv1.y;
+		if det.abs() <= f32::EPSILON {
+			// No usable texture mapping on this face
+			continue;
+		}
+		let r = 1.0 / det;
+		let tangent = (edge1 * duv2.y - edge2 * duv1.y) * r;
+		let bitangent = (edge2 * duv1.x - edge1 * duv2.x) * r;
+
+		for k in 0..3 {
+			let angle = corner_angle(
+				&v[k].position,
+				&v[(k + 1) % 3].position,
+				&v[(k + 2) % 3].position,
+			);
+			tangents[idx[k]] += tangent * angle;
+			bitangents[idx[k]] += bitangent * angle;
+		}
+	}
+
+	for ((vertex, tangent), bitangent) in obj.vertexes.iter_mut().zip(tangents).zip(bitangents) {
+		let normal = vertex.normal;
+		// Gram-Schmidt
+		let ortho = tangent - normal * normal.dot(&tangent);
+		let t = if glm::length2(&ortho) > f32::EPSILON {
+			ortho.normalize()
+		} else {
+			perpendicular(&normal)
+		};
+		let handedness = if normal.cross(&t).dot(&bitangent) < 0.0 {
+			-1.0
+		} else {
+			1.0
+		};
+		vertex.tangent = Some(glm::vec4(t.x, t.y, t.z, handedness));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::object::Vertex;
+
+	// Triangles without normals, each with its own vertices like a parsed OBJ
+	fn object(triangles: &[[[f32; 3]; 3]], groups: &[u32]) -> Object {
+		let mut obj = Object::default();
+		for (i, triangle) in triangles.iter().enumerate() {
+			for &[x, y, z] in triangle {
+				obj.vertexes.push(Vertex {
+					position: glm::vec3(x, y, z),
+					normal: glm::Vec3::zeros(),
+					uv: glm::vec2(0.0, 0.0),
+					tangent: None,
+				});
+			}
+			let first = i as u32 * 3;
+			obj.triangle_indices
+				.push(VertexIndex(first, first + 1, first + 2));
+		}
+		obj.smoothing_groups = groups.to_vec();
+		obj
+	}
+
+	// Two faces of a roof, meeting along the ridge from (0, 1, 0) to (0, 1, 1)
+	const ROOF: [[[f32; 3]; 3]; 2] = [
+		[[-1.0, 0.0, 0.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]],
+		[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 1.0]],
+	];
+
+	fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+		assert!(glm::distance(&a, &b) < 1e-5, "{:?} != {:?}", a, b);
+	}
+
+	#[test]
+	fn flat_normals_follow_their_face() {
+		// `s off`
+		let mut obj = object(&ROOF, &[0, 0]);
+		generate_normals(&mut obj);
+		let left = glm::vec3(-1.0, 1.0, 0.0).normalize();
+		let right = glm::vec3(1.0, 1.0, 0.0).normalize();
+		for vertex in &obj.vertexes[..3] {
+			assert_close(vertex.normal, left);
+		}
+		for vertex in &obj.vertexes[3..] {
+			assert_close(vertex.normal, right);
+		}
+	}
+
+	#[test]
+	fn smooth_normals_are_shared_along_the_ridge() {
+		let mut obj = object(&ROOF, &[1, 1]);
+		generate_normals(&mut obj);
+		let up = glm::vec3(0.0, 1.0, 0.0);
+		// The ridge vertices of both faces
+		for &i in &[1, 2, 4, 5] {
+			assert_close(obj.vertexes[i].normal, up);
+		}
+		// The eaves belong to a single face
+		assert_close(
+			obj.vertexes[0].normal,
+			glm::vec3(-1.0, 1.0, 0.0).normalize(),
+		);
+	}
+
+	#[test]
+	fn smoothing_groups_are_kept_apart() {
+		// `s 1` and `s 2`
+		let mut obj = object(&ROOF, &[1, 2]);
+		generate_normals(&mut obj);
+		assert_close(
+			obj.vertexes[2].normal,
+			glm::vec3(-1.0, 1.0, 0.0).normalize(),
+		);
+		assert_close(obj.vertexes[4].normal, glm::vec3(1.0, 1.0, 0.0).normalize());
+	}
+
+	#[test]
+	fn smooth_normals_are_weighted_by_angle() {
+		// Facing +z with a right angle at the origin, and facing +y with half of one
+		let mut obj = object(
+			&[
+				[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+				[[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]],
+			],
+			&[1, 1],
+		);
+		generate_normals(&mut obj);
+		let expected = glm::vec3(0.0, 1.0, 2.0).normalize();
+		assert_close(obj.vertexes[0].normal, expected);
+		assert_close(obj.vertexes[3].normal, expected);
+	}
+
+	#[test]
+	fn negative_zero_is_the_same_position() {
+		let mut roof = ROOF;
+		roof[1][1] = [-0.0, 1.0, -0.0];
+		let mut obj = object(&roof, &[1, 1]);
+		generate_normals(&mut obj);
+		assert_close(obj.vertexes[2].normal, glm::vec3(0.0, 1.0, 0.0));
+		assert_close(obj.vertexes[4].normal, glm::vec3(0.0, 1.0, 0.0));
+	}
+
+	// A unit quad facing +z, with its texture mirrored horizontally or not
+	fn quad(mirrored: bool) -> Object {
+		let mut obj = object(
+			&[
+				[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+				[[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+			],
+			&[0, 0],
+		);
+		for vertex in &mut obj.vertexes {
+			let u = if mirrored {
+				1.0 - vertex.position.x
+			} else {
+				vertex.position.x
+			};
+			vertex.uv = glm::vec2(u, vertex.position.y);
+		}
+		generate_normals(&mut obj);
+		generate_tangents(&mut obj);
+		obj
+	}
+
+	#[test]
+	fn tangents_follow_the_texture() {
+		for vertex in &quad(false).vertexes {
+			assert_eq!(vertex.normal, glm::vec3(0.0, 0.0, 1.0));
+			assert_eq!(vertex.tangent, Some(glm::vec4(1.0, 0.0, 0.0, 1.0)));
+		}
+	}
+
+	#[test]
+	fn mirrored_textures_flip_the_handedness() {
+		for vertex in &quad(true).vertexes {
+			assert_eq!(vertex.tangent, Some(glm::vec4(-1.0, 0.0, 0.0, -1.0)));
+		}
+	}
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::obj_parser::{parse_lines, ErrorReason, Line, ParseError, ParseMode};
+use crate::resources::material_store::Material;
+use crate::resources::texture_map::TextureMap;
+
+// A material as described by a `newmtl` block.
+// Texture maps are stored as paths, relative to the working directory.
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+	pub name: String,
+	pub ambient: glm::Vec3,
+	pub diffuse: glm::Vec3,
+	pub specular: glm::Vec3,
+	pub shininess: f32,
+	pub diffuse_map: Option<String>,
+	pub specular_map: Option<String>,
+	pub bump_map: Option<String>,
+}
+
+impl MtlMaterial {
+	pub fn new(name: String) -> Self {
+		let defaults = Material::default();
+		Self {
+			name,
+			ambient: defaults.ambient,
+			diffuse: defaults.diffuse,
+			specular: defaults.specular,
+			shininess: defaults.shininess,
+			diffuse_map: None,
+			specular_map: None,
+			bump_map: None,
+		}
+	}
+
+	// Load the referenced textures and build the material.
+	// Parsing leniently, textures that can't be loaded are left out with a warning.
+	pub fn to_material(
+		&self,
+		texture_map: &mut TextureMap,
+		mode: ParseMode,
+	) -> Result<Material, String> {
+		let mut load = |path: &Option<String>| -> Result<_, String> {
+			let path = match path {
+				Some(path) => path,
+				None => return Ok(None),
+			};
+			match texture_map.load_from_file(path.clone()) {
+				Ok(texture) => Ok(Some(texture)),
+				Err(e) => {
+					let e = format!("Material {} could not load {}: {}", self.name, path, e);
+					if mode == ParseMode::Strict {
+						return Err(e);
+					}
+					eprintln!("Warning: {}", e);
+					Ok(None)
+				}
+			}
+		};
+		Ok(Material {
+			ambient: self.ambient,
+			diffuse: self.diffuse,
+			specular: self.specular,
+			shininess: self.shininess,
+			diffuse_map: load(&self.diffuse_map)?,
+			specular_map: load(&self.specular_map)?,
+			bump_map: load(&self.bump_map)?,
+		})
+	}
+}
+
+// Texture statements may carry options (`-bm 1.0`, `-s 1 1 1`, ...)
+// before the file name, which always comes last.
+fn texture_path(line: &Line, dir: &Path) -> Result<String, ParseError> {
+	if line.len() < 2 {
+		return Err(line.error(1, ErrorReason::MissingValue));
+	}
+	let file = line.tokens[line.len() - 1].text;
+	Ok(dir.join(file).to_string_lossy().into_owned())
+}
+
+pub fn parse(
+	path: String,
+	mode: ParseMode,
+	warnings: &mut Vec<ParseError>,
+) -> Result<HashMap<String, MtlMaterial>, ParseError> {
+	let dir = Path::new(&path)
+		.parent()
+		.map(Path::to_path_buf)
+		.unwrap_or_default();
+
+	let mut materials = HashMap::new();
+	let mut current: Option<MtlMaterial> = None;
+
+	parse_lines(&path, mode, warnings, |line, warnings| {
+		let directive = line.directive().unwrap_or_default();
+		if directive == "newmtl" {
+			if let Some(material) = current.take() {
+				materials.insert(material.name.clone(), material);
+			}
+			current = Some(MtlMaterial::new(line.rest()));
+			return Ok(());
+		}
+
+		// Everything else describes the material currently being defined
+		let material = match current.as_mut() {
+			Some(material) => material,
+			None => return Ok(()),
+		};
+		match directive {
+			"Ka" => material.ambient = line.vec3(1)?,
+			"Kd" => material.diffuse = line.vec3(1)?,
+			"Ks" => material.specular = line.vec3(1)?,
+			"Ns" => material.shininess = line.float(1)?,
+			"map_Kd" => material.diffuse_map = Some(texture_path(line, &dir)?),
+			"map_Ks" => material.specular_map = Some(texture_path(line, &dir)?),
+			"map_Bump" | "map_bump" | "bump" => material.bump_map = Some(texture_path(line, &dir)?),
+			// Properties we have no use for (yet)
+			"Ke" | "Ni" | "d" | "Tr" | "Tf" | "illum" | "map_Ka" | "map_d" | "map_Ns" | "disp"
+			| "decal" | "refl" => {}
+			_ => warnings.push(line.error(0, ErrorReason::UnknownDirective)),
+		}
+		Ok(())
+	})?;
+
+	if let Some(material) = current.take() {
+		materials.insert(material.name.clone(), material);
+	}
+
+	Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::TempDir;
+
+	fn parse_mtl(
+		dir: &TempDir,
+		contents: &str,
+		mode: ParseMode,
+		warnings: &mut Vec<ParseError>,
+	) -> Result<HashMap<String, MtlMaterial>, ParseError> {
+		let path = dir.write("scene.mtl", contents);
+		parse(path.to_string_lossy().into_owned(), mode, warnings)
+	}
+
+	fn texture(dir: &TempDir, file: &str) -> Option<String> {
+		Some(dir.join(file).to_string_lossy().into_owned())
+	}
+
+	#[test]
+	fn colours_and_shininess() {
+		let dir = TempDir::new("mtl-colours");
+		let mut warnings = Vec::new();
+		let materials = parse_mtl(
+			&dir,
+			"newmtl brick\nKa 0.1 0.2 0.3\nKd 0.4 0.5 0.6\nKs 0.7 0.8 0.9\nNs 12.5\nd 0.5\nTr 0.5\n",
+			ParseMode::Strict,
+			&mut warnings,
+		)
+		.unwrap();
+		// Transparency is accepted, though not used
+		assert!(warnings.is_empty());
+		let brick = &materials["brick"];
+		assert_eq!(brick.ambient, glm::vec3(0.1, 0.2, 0.3));
+		assert_eq!(brick.diffuse, glm::vec3(0.4, 0.5, 0.6));
+		assert_eq!(brick.specular, glm::vec3(0.7, 0.8, 0.9));
+		assert_eq!(brick.shininess, 12.5);
+		assert_eq!(brick.diffuse_map, None);
+	}
+
+	#[test]
+	fn texture_maps_are_relative_to_the_library() {
+		let dir = TempDir::new("mtl-maps");
+		let materials = parse_mtl(
+			&dir,
+			"newmtl brick\nmap_Kd brick.png\nmap_Ks -s 1 1 1 textures/shine.png\nmap_Bump -bm 0.5 bump.png\n",
+			ParseMode::Strict,
+			&mut Vec::new(),
+		)
+		.unwrap();
+		let brick = &materials["brick"];
+		assert_eq!(brick.diffuse_map, texture(&dir, "brick.png"));
+		assert_eq!(brick.specular_map, texture(&dir, "textures/shine.png"));
+		assert_eq!(brick.bump_map, texture(&dir, "bump.png"));
+	}
+
+	#[test]
+	fn every_block_is_a_material() {
+		let dir = TempDir::new("mtl-blocks");
+		let materials = parse_mtl(
+			&dir,
+			"# Two materials\nnewmtl brick\nKd 1 0 0\n\nnewmtl grass\nKd 0 1 0\nmap_Kd grass.png\n",
+			ParseMode::Strict,
+			&mut Vec::new(),
+		)
+		.unwrap();
+		assert_eq!(materials.len(), 2);
+		assert_eq!(materials["brick"].diffuse, glm::vec3(1.0, 0.0, 0.0));
+		assert_eq!(materials["brick"].diffuse_map, None);
+		assert_eq!(materials["grass"].diffuse, glm::vec3(0.0, 1.0, 0.0));
+		assert_eq!(materials["grass"].diffuse_map, texture(&dir, "grass.png"));
+	}
+
+	const MALFORMED: &str = "newmtl brick\nKd 1 x 0\nKs 1 1 1\nmap_Kd\n";
+
+	#[test]
+	fn strict_parsing_stops_at_a_malformed_line() {
+		let dir = TempDir::new("mtl-strict");
+		let error = parse_mtl(&dir, MALFORMED, ParseMode::Strict, &mut Vec::new()).unwrap_err();
+		assert!(matches!(error.reason, ErrorReason::InvalidNumber));
+		assert_eq!(error.line, Some(2));
+		assert_eq!(error.token.as_deref(), Some("x"));
+	}
+
+	#[test]
+	fn lenient_parsing_skips_malformed_lines() {
+		let dir = TempDir::new("mtl-lenient");
+		let mut warnings = Vec::new();
+		let materials = parse_mtl(&dir, MALFORMED, ParseMode::Lenient, &mut warnings).unwrap();
+		assert_eq!(warnings.len(), 2);
+		assert!(matches!(warnings[0].reason, ErrorReason::InvalidNumber));
+		assert!(matches!(warnings[1].reason, ErrorReason::MissingValue));
+		let brick = &materials["brick"];
+		assert_eq!(brick.diffuse, Material::default().diffuse);
+		assert_eq!(brick.specular, glm::vec3(1.0, 1.0, 1.0));
+		assert_eq!(brick.diffuse_map, None);
+	}
+
+	#[test]
+	fn missing_textures_only_fail_strict_parsing() {
+		let dir = TempDir::new("mtl-missing");
+		let mut brick = MtlMaterial::new("brick".to_string());
+		brick.diffuse_map = texture(&dir, "missing.png");
+		let mut texture_map = TextureMap::new();
+		assert!(brick
+			.to_material(&mut texture_map, ParseMode::Strict)
+			.is_err());
+		let material = brick
+			.to_material(&mut texture_map, ParseMode::Lenient)
+			.unwrap();
+		assert!(material.diffuse_map.is_none());
+		assert_eq!(material.diffuse, brick.diffuse);
+	}
+}
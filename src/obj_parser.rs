@@ -1,100 +1,787 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::path::Path;
 
+use crate::mesh_cache::{self, CacheError};
+use crate::mesh_processing;
+use crate::mtl_parser::{self, MtlMaterial};
 use crate::object::{Object, Vertex, VertexIndex};
 
 #[derive(Debug)]
-pub enum ParseError {
-	FileReadError,
-	LineReadError,
-	StrParseError,
-	MalformedVertex(usize),
+pub enum ErrorReason {
+	Io(io::Error),
+	Cache(CacheError),
+	InvalidNumber,
+	MissingValue,
+	MalformedFaceVertex,
+	TooFewVertices,
+	ZeroIndex,
+	IndexOutOfBounds,
+	UnknownDirective,
 }
 
-pub fn str_to_float(s: &str) -> Result<f32, ParseError> {
-	s.parse::<f32>().map_err(|_| ParseError::StrParseError)
+impl fmt::Display for ErrorReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ErrorReason::Io(e) => write!(f, "could not read file: {}", e),
+			ErrorReason::Cache(e) => write!(f, "{}", e),
+			ErrorReason::InvalidNumber => write!(f, "not a valid number"),
+			ErrorReason::MissingValue => write!(f, "expected a value"),
+			ErrorReason::MalformedFaceVertex => {
+				write!(f, "face vertex must be one of v, v/vt, v//vn or v/vt/vn")
+			}
+			ErrorReason::TooFewVertices => write!(f, "face needs at least 3 vertices"),
+			ErrorReason::ZeroIndex => write!(f, "indices start at 1"),
+			ErrorReason::IndexOutOfBounds => write!(f, "index refers to an undefined element"),
+			ErrorReason::UnknownDirective => write!(f, "unknown directive, ignored"),
+		}
+	}
+}
+
+// Where something went wrong while parsing an OBJ or MTL file.
+// Line and column are 1-based, and absent for errors about the whole file.
+#[derive(Debug)]
+pub struct ParseError {
+	pub path: String,
+	pub line: Option<usize>,
+	pub column: Option<usize>,
+	pub token: Option<String>,
+	pub reason: ErrorReason,
+}
+
+impl ParseError {
+	pub fn io(path: &str, error: io::Error) -> Self {
+		Self {
+			path: path.to_string(),
+			line: None,
+			column: None,
+			token: None,
+			reason: ErrorReason::Io(error),
+		}
+	}
+
+	fn cache(path: &Path, error: CacheError) -> Self {
+		Self {
+			path: path.to_string_lossy().into_owned(),
+			line: None,
+			column: None,
+			token: None,
+			reason: ErrorReason::Cache(error),
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.path)?;
+		if let Some(line) = self.line {
+			write!(f, ":{}", line)?;
+		}
+		if let Some(column) = self.column {
+			write!(f, ":{}", column)?;
+		}
+		write!(f, ": {}", self.reason)?;
+		if let Some(token) = &self.token {
+			write!(f, " (at `{}`)", token)?;
+		}
+		Ok(())
+	}
+}
+
+impl Error for ParseError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match &self.reason {
+			ErrorReason::Io(e) => Some(e),
+			ErrorReason::Cache(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+// Strict parsing fails on the first bad line,
+// lenient parsing skips it and records it as a warning instead.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseMode {
+	Strict,
+	Lenient,
 }
 
-pub fn parse(path: String) -> Result<Object, ParseError> {
-	let file = File::open(path).map_err(|_| ParseError::FileReadError)?;
+#[derive(Copy, Clone, Debug)]
+pub struct Token<'a> {
+	pub text: &'a str,
+	pub column: usize,
+}
+
+// A whitespace separated line of an OBJ or MTL file
+pub struct Line<'a> {
+	pub path: &'a str,
+	pub number: usize,
+	pub tokens: Vec<Token<'a>>,
+	end_column: usize,
+}
+
+impl<'a> Line<'a> {
+	pub fn new(path: &'a str, number: usize, text: &'a str) -> Self {
+		let mut tokens = Vec::new();
+		let mut start = None;
+		for (idx, c) in text.char_indices() {
+			match (c.is_whitespace(), start) {
+				(false, None) => start = Some(idx),
+				(true, Some(s)) => {
+					tokens.push(Token {
+						text: &text[s..idx],
+						column: text[..s].chars().count() + 1,
+					});
+					start = None;
+				}
+				_ => {}
+			}
+		}
+		if let Some(s) = start {
+			tokens.push(Token {
+				text: &text[s..],
+				column: text[..s].chars().count() + 1,
+			});
+		}
+		Self {
+			path,
+			number,
+			tokens,
+			end_column: text.chars().count() + 1,
+		}
+	}
+
+	pub fn directive(&self) -> Option<&'a str> {
+		self.tokens.first().map(|t| t.text)
+	}
+
+	pub fn len(&self) -> usize {
+		self.tokens.len()
+	}
+
+	// An error pointing at the token at `idx`, or the end of the line if there is none
+	pub fn error(&self, idx: usize, reason: ErrorReason) -> ParseError {
+		let token = self.tokens.get(idx);
+		ParseError {
+			path: self.path.to_string(),
+			line: Some(self.number),
+			column: Some(token.map_or(self.end_column, |t| t.column)),
+			token: token.map(|t| t.text.to_string()),
+			reason,
+		}
+	}
+
+	pub fn float(&self, idx: usize) -> Result<f32, ParseError> {
+		let token = self
+			.tokens
+			.get(idx)
+			.ok_or_else(|| self.error(idx, ErrorReason::MissingValue))?;
+		token
+			.text
+			.parse::<f32>()
+			.map_err(|_| self.error(idx, ErrorReason::InvalidNumber))
+	}
+
+	pub fn vec3(&self, idx: usize) -> Result<glm::Vec3, ParseError> {
+		Ok(glm::vec3(
+			self.float(idx)?,
+			self.float(idx + 1)?,
+			self.float(idx + 2)?,
+		))
+	}
+
+	// Everything after the directive, for names that may contain spaces
+	pub fn rest(&self) -> String {
+		let words: Vec<&str> = self.tokens.iter().skip(1).map(|t| t.text).collect();
+		words.join(" ")
+	}
+}
+
+// Run `parse_line` over every line of a file, handling errors according to `mode`
+pub fn parse_lines<F>(
+	path: &str,
+	mode: ParseMode,
+	warnings: &mut Vec<ParseError>,
+	mut parse_line: F,
+) -> Result<(), ParseError>
+where
+	F: FnMut(&Line, &mut Vec<ParseError>) -> Result<(), ParseError>,
+{
+	let file = File::open(path).map_err(|e| ParseError::io(path, e))?;
 	let reader = BufReader::new(file);
 
-	let mut positions: Vec<glm::Vec3> = Vec::new();
-	let mut normals: Vec<glm::Vec3> = Vec::new();
-	let mut uvs: Vec<glm::Vec2> = Vec::new();
-	let mut obj = Object::default();
+	for (line_no, text) in reader.lines().enumerate() {
+		let text = text.map_err(|e| ParseError::io(path, e))?;
+		let line = Line::new(path, line_no + 1, &text);
+
+		match line.directive() {
+			// Empty line or comment; skip
+			None => continue,
+			Some(directive) if directive.starts_with('#') => continue,
+			_ => {}
+		}
+
+		match parse_line(&line, warnings) {
+			Ok(()) => {}
+			Err(e) if mode == ParseMode::Lenient => warnings.push(e),
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(())
+}
+
+// OBJ indices are 1-based, and negative ones count back from the
+// most recently defined element.
+fn resolve_index(token: &str, len: usize) -> Result<usize, ErrorReason> {
+	let idx = token
+		.parse::<i64>()
+		.map_err(|_| ErrorReason::MalformedFaceVertex)?;
+	let resolved = match idx {
+		0 => return Err(ErrorReason::ZeroIndex),
+		idx if idx > 0 => idx - 1,
+		idx => len as i64 + idx,
+	};
+	if resolved < 0 || resolved >= len as i64 {
+		return Err(ErrorReason::IndexOutOfBounds);
+	}
+	Ok(resolved as usize)
+}
+
+// Accepts `v`, `v/vt`, `v//vn` and `v/vt/vn`.
+// Missing texture coordinates default to zero, as do missing normals.
+fn parse_face_vertex(
+	token: &str,
+	positions: &[glm::Vec3],
+	uvs: &[glm::Vec2],
+	normals: &[glm::Vec3],
+) -> Result<Vertex, ErrorReason> {
+	let data: Vec<&str> = token.split('/').collect();
+	let (pos, uv, norm) = match data.as_slice() {
+		[pos] => (*pos, None, None),
+		[pos, uv] => (*pos, Some(*uv), None),
+		[pos, uv, norm] => (*pos, Some(*uv).filter(|s| !s.is_empty()), Some(*norm)),
+		_ => return Err(ErrorReason::MalformedFaceVertex),
+	};
+
+	let position = positions[resolve_index(pos, positions.len())?];
+	let uv = match uv {
+		Some(uv) => uvs[resolve_index(uv, uvs.len())?],
+		None => glm::vec2(0.0, 0.0),
+	};
+	let normal = match norm {
+		Some(norm) => normals[resolve_index(norm, normals.len())?],
+		None => glm::vec3(0.0, 0.0, 0.0),
+	};
+
+	Ok(Vertex {
+		position,
+		normal,
+		uv,
+		tangent: None,
+	})
+}
+
+// Newell's method, works for non-planar and concave polygons alike
+fn polygon_normal(points: &[glm::Vec3]) -> glm::Vec3 {
+	let mut normal = glm::vec3(0.0, 0.0, 0.0);
+	for (i, current) in points.iter().enumerate() {
+		let next = points[(i + 1) % points.len()];
+		normal.x += (current.y - next.y) * (current.z + next.z);
+		normal.y += (current.z - next.z) * (current.x + next.x);
+		normal.z += (current.x - next.x) * (current.y + next.y);
+	}
+	normal
+}
+
+fn cross_2d(a: glm::Vec2, b: glm::Vec2, c: glm::Vec2) -> f32 {
+	(b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+fn point_in_triangle(p: glm::Vec2, a: glm::Vec2, b: glm::Vec2, c: glm::Vec2) -> bool {
+	cross_2d(a, b, p) >= 0.0 && cross_2d(b, c, p) >= 0.0 && cross_2d(c, a, p) >= 0.0
+}
+
+// Fewer than three points make no triangle at all
+fn fan(count: usize) -> Vec<[usize; 3]> {
+	(1..count.saturating_sub(1))
+		.map(|i| [0, i, i + 1])
+		.collect()
+}
+
+// Split a polygon into triangles, preserving its winding.
+// Convex polygons are fanned, concave ones are ear clipped in the
+// plane the polygon (roughly) lies in.
+pub fn triangulate(points: &[glm::Vec3]) -> Vec<[usize; 3]> {
+	if points.len() <= 3 {
+		return fan(points.len());
+	}
+
+	let normal = polygon_normal(points);
+	if glm::length(&normal) <= f32::EPSILON {
+		return fan(points.len());
+	}
+
+	// Drop the dominant axis of the normal to project onto a 2D plane,
+	// flipping so the polygon is always counter-clockwise.
+	let (ax, ay, flip) = if normal.x.abs() >= normal.y.abs() && normal.x.abs() >= normal.z.abs() {
+		(1, 2, normal.x < 0.0)
+	} else if normal.y.abs() >= normal.z.abs() {
+		(2, 0, normal.y < 0.0)
+	} else {
+		(0, 1, normal.z < 0.0)
+	};
+	let projected: Vec<glm::Vec2> = points
+		.iter()
+		.map(|p| {
+			if flip {
+				glm::vec2(p[ay], p[ax])
+			} else {
+				glm::vec2(p[ax], p[ay])
+			}
+		})
+		.collect();
+
+	let n = projected.len();
+	let is_convex = (0..n)
+		.all(|i| cross_2d(projected[i], projected[(i + 1) % n], projected[(i + 2) % n]) >= 0.0);
+	if is_convex {
+		return fan(n);
+	}
+
+	let mut remaining: Vec<usize> = (0..n).collect();
+	let mut triangles = Vec::with_capacity(n - 2);
+	while remaining.len() > 3 {
+		let len = remaining.len();
+		let ear = (0..len).find(|&i| {
+			let prev = remaining[(i + len - 1) % len];
+			let curr = remaining[i];
+			let next = remaining[(i + 1) % len];
+			let (a, b, c) = (projected[prev], projected[curr], projected[next]);
+			if cross_2d(a, b, c) <= 0.0 {
+				return false;
+			}
+			remaining
+				.iter()
+				.filter(|&&other| other != prev && other != curr && other != next)
+				.all(|&other| !point_in_triangle(projected[other], a, b, c))
+		});
+
+		match ear {
+			Some(i) => {
+				let prev = remaining[(i + len - 1) % len];
+				let next = remaining[(i + 1) % len];
+				triangles.push([prev, remaining[i], next]);
+				remaining.remove(i);
+			}
+			None => {
+				// Self-intersecting or degenerate; fan whatever is left
+				for i in 1..remaining.len() - 1 {
+					triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+				}
+				return triangles;
+			}
+		}
+	}
+	triangles.push([remaining[0], remaining[1], remaining[2]]);
+	triangles
+}
+
+// The faces of an OBJ file that share a `usemtl` material
+#[derive(Debug)]
+pub struct SubMesh {
+	pub material: Option<String>,
+	pub object: Object,
+}
+
+#[derive(Debug)]
+pub struct ObjModel {
+	pub submeshes: Vec<SubMesh>,
+	pub materials: HashMap<String, MtlMaterial>,
+	// Problems that did not stop the model from loading
+	pub warnings: Vec<ParseError>,
+}
 
-	for (line_no, line) in reader.lines().enumerate() {
-		let unwrapped_line = line.map_err(|_| ParseError::LineReadError)?;
-		let line_bits: Vec<&str> = unwrapped_line.split_whitespace().collect();
+impl ObjModel {
+	pub fn material(&self, submesh: &SubMesh) -> Option<&MtlMaterial> {
+		submesh
+			.material
+			.as_ref()
+			.and_then(|name| self.materials.get(name))
+	}
+}
+
+// Parse the geometry split up per material,
+// along with the material libraries it references.
+pub fn load(path: String, mode: ParseMode) -> Result<ObjModel, ParseError> {
+	let dir = Path::new(&path)
+		.parent()
+		.map(Path::to_path_buf)
+		.unwrap_or_default();
+	let mut warnings = Vec::new();
 
-		if line_bits.is_empty() {
-			// Empty line; skip
-			continue;
+	// Reuse the processed geometry from the last load if the OBJ hasn't changed since
+	let source = mesh_cache::Source::read(&path).map_err(|e| ParseError::io(&path, e))?;
+	let cache = mesh_cache::cache_path(&path);
+	let cached = match mesh_cache::read(&cache, &source) {
+		Ok(cached) => Some(cached),
+		// Caches that were never written or are out of date are simply written again
+		Err(CacheError::Stale) => None,
+		Err(CacheError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => None,
+		Err(e) => {
+			warnings.push(ParseError::cache(&cache, e));
+			None
 		}
+	};
 
-		match line_bits[0usize] {
-			"v" => {
-				positions.push(glm::vec3(
-					str_to_float(line_bits[1usize])?,
-					str_to_float(line_bits[2usize])?,
-					str_to_float(line_bits[3usize])?,
-				));
+	let (submeshes, libraries) = match cached {
+		Some(cached) => {
+			warnings.extend(cached.warnings);
+			(cached.submeshes, cached.libraries)
+		}
+		None => {
+			let mut parse_warnings = Vec::new();
+			let (mut submeshes, libraries) = parse_submeshes(&path, mode, &mut parse_warnings)?;
+			for submesh in &mut submeshes {
+				mesh_processing::generate_normals(&mut submesh.object);
+				submesh.object.deduplicate();
+				mesh_processing::generate_tangents(&mut submesh.object);
 			}
-			"vn" => {
-				normals.push(glm::vec3(
-					str_to_float(line_bits[1usize])?,
-					str_to_float(line_bits[2usize])?,
-					str_to_float(line_bits[3usize])?,
-				));
+
+			// Lenient parsing may have skipped lines, which the cache would then
+			// be missing for good. Unknown directives are ignored either way.
+			let skipped_lines = parse_warnings
+				.iter()
+				.any(|warning| !matches!(warning.reason, ErrorReason::UnknownDirective));
+			if !skipped_lines {
+				if let Err(e) =
+					mesh_cache::write(&cache, &source, &submeshes, &libraries, &parse_warnings)
+				{
+					warnings.push(ParseError::cache(&cache, e));
+				}
 			}
+			warnings.extend(parse_warnings);
+			(submeshes, libraries)
+		}
+	};
+
+	let mut materials = HashMap::new();
+	for library in libraries {
+		let library_path = dir.join(library).to_string_lossy().into_owned();
+		match mtl_parser::parse(library_path, mode, &mut warnings) {
+			Ok(library) => materials.extend(library),
+			Err(e) if mode == ParseMode::Lenient => warnings.push(e),
+			Err(e) => return Err(e),
+		}
+	}
+
+	Ok(ObjModel {
+		submeshes,
+		materials,
+		warnings,
+	})
+}
+
+fn parse_submeshes(
+	path: &str,
+	mode: ParseMode,
+	warnings: &mut Vec<ParseError>,
+) -> Result<(Vec<SubMesh>, Vec<String>), ParseError> {
+	let mut positions: Vec<glm::Vec3> = Vec::new();
+	let mut normals: Vec<glm::Vec3> = Vec::new();
+	let mut uvs: Vec<glm::Vec2> = Vec::new();
+	let mut libraries = Vec::new();
+	let mut submeshes = vec![SubMesh {
+		material: None,
+		object: Object::default(),
+	}];
+	let mut current = 0;
+	// Smoothing is off until an `s` statement says otherwise
+	let mut smoothing_group = 0;
+
+	parse_lines(path, mode, warnings, |line, warnings| {
+		match line.directive().unwrap_or_default() {
+			"v" => positions.push(line.vec3(1)?),
+			"vn" => normals.push(line.vec3(1)?),
 			"vt" => {
-				uvs.push(glm::vec2(
-					str_to_float(line_bits[1usize])?,
-					str_to_float(line_bits[2usize])?,
-				));
+				// The v coordinate is optional
+				let v = if line.len() > 2 { line.float(2)? } else { 0.0 };
+				uvs.push(glm::vec2(line.float(1)?, v));
 			}
 
 			"f" => {
-				let mut vertex_idxs = Vec::new();
-				for vfinder in line_bits[1usize..].iter() {
-					let data: Vec<&str> = vfinder.split('/').collect();
-					if data.len() == 3 {
-						if let [pos, uv, norm] = data.as_slice() {
-							let vertex = Vertex {
-								position: positions[pos
-									.parse::<usize>()
-									.map_err(|_| ParseError::MalformedVertex(line_no))?
-									- 1],
-								uv: uvs[uv
-									.parse::<usize>()
-									.map_err(|_| ParseError::MalformedVertex(line_no))?
-									- 1],
-								normal: normals[norm
-									.parse::<usize>()
-									.map_err(|_| ParseError::MalformedVertex(line_no))?
-									- 1],
-							};
-							obj.vertexes.push(vertex);
-							vertex_idxs.push(obj.vertexes.len() as u32 - 1);
-						}
-					} else {
-						return Err(ParseError::MalformedVertex(line_no));
-					}
+				if line.len() < 4 {
+					return Err(line.error(line.len(), ErrorReason::TooFewVertices));
 				}
-				if vertex_idxs.len() != 3 {
-					unreachable!();
+				let mut face = Vec::with_capacity(line.len() - 1);
+				for (idx, vfinder) in line.tokens.iter().enumerate().skip(1) {
+					face.push(
+						parse_face_vertex(vfinder.text, &positions, &uvs, &normals)
+							.map_err(|reason| line.error(idx, reason))?,
+					);
+				}
+
+				let face_positions: Vec<glm::Vec3> = face.iter().map(|v| v.position).collect();
+				let obj = &mut submeshes[current].object;
+				let first = obj.vertexes.len() as u32;
+				obj.vertexes.extend(face);
+				for [a, b, c] in triangulate(&face_positions) {
+					obj.triangle_indices.push(VertexIndex(
+						first + a as u32,
+						first + b as u32,
+						first + c as u32,
+					));
+					obj.smoothing_groups.push(smoothing_group);
 				}
-				obj.triangle_indices.push(VertexIndex(
-					vertex_idxs[0],
-					vertex_idxs[1],
-					vertex_idxs[2],
-				));
 			}
-			_ => {}
+			"mtllib" => libraries.extend(line.tokens.iter().skip(1).map(|t| t.text.to_string())),
+			"usemtl" => {
+				let name = line.rest();
+				current = match submeshes
+					.iter()
+					.position(|m| m.material.as_ref() == Some(&name))
+				{
+					Some(idx) => idx,
+					None => {
+						submeshes.push(SubMesh {
+							material: Some(name),
+							object: Object::default(),
+						});
+						submeshes.len() - 1
+					}
+				};
+			}
+			"s" => {
+				smoothing_group = match line.tokens.get(1).map(|t| t.text) {
+					Some("off") => 0,
+					Some(group) => group
+						.parse::<u32>()
+						.map_err(|_| line.error(1, ErrorReason::InvalidNumber))?,
+					None => return Err(line.error(1, ErrorReason::MissingValue)),
+				};
+			}
+			// Grouping doesn't affect the geometry we build
+			"o" | "g" => {}
+			_ => warnings.push(line.error(0, ErrorReason::UnknownDirective)),
+		}
+		Ok(())
+	})?;
+
+	submeshes.retain(|m| !m.object.triangle_indices.is_empty());
+	Ok((submeshes, libraries))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::TempDir;
+	use std::fs;
+
+	// Twice the signed area of a triangle in the XY plane
+	fn area(points: &[glm::Vec3], [a, b, c]: [usize; 3]) -> f32 {
+		let (a, b, c) = (points[a].xy(), points[b].xy(), points[c].xy());
+		cross_2d(a, b, c)
+	}
+
+	fn write_obj(dir: &TempDir, contents: &str) -> String {
+		dir.write("model.obj", contents)
+			.to_string_lossy()
+			.into_owned()
+	}
+
+	#[test]
+	fn quads_are_fanned() {
+		let points = [
+			glm::vec3(0.0, 0.0, 0.0),
+			glm::vec3(1.0, 0.0, 0.0),
+			glm::vec3(1.0, 1.0, 0.0),
+			glm::vec3(0.0, 1.0, 0.0),
+		];
+		assert_eq!(triangulate(&points), vec![[0, 1, 2], [0, 2, 3]]);
+	}
+
+	#[test]
+	fn degenerate_polygons_make_no_triangles() {
+		let points = [glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0)];
+		assert!(triangulate(&[]).is_empty());
+		assert!(triangulate(&points[..1]).is_empty());
+		assert!(triangulate(&points).is_empty());
+	}
+
+	#[test]
+	fn concave_pentagon_is_ear_clipped() {
+		// The notch at (1, 1) makes a fan from the first vertex degenerate
+		let points = [
+			glm::vec3(0.0, 0.0, 0.0),
+			glm::vec3(2.0, 0.0, 0.0),
+			glm::vec3(2.0, 2.0, 0.0),
+			glm::vec3(1.0, 1.0, 0.0),
+			glm::vec3(0.0, 2.0, 0.0),
+		];
+		let triangles = triangulate(&points);
+		assert_eq!(triangles.len(), 3);
+		for triangle in &triangles {
+			assert!(area(&points, *triangle) > 0.0, "{:?} is flipped", triangle);
+		}
+		let total: f32 = triangles.iter().map(|t| area(&points, *t)).sum();
+		assert!((total - 6.0).abs() < 1e-5);
+	}
+
+	#[test]
+	fn clockwise_polygons_keep_their_winding() {
+		let points = [
+			glm::vec3(0.0, 2.0, 0.0),
+			glm::vec3(1.0, 1.0, 0.0),
+			glm::vec3(2.0, 2.0, 0.0),
+			glm::vec3(2.0, 0.0, 0.0),
+			glm::vec3(0.0, 0.0, 0.0),
+		];
+		let triangles = triangulate(&points);
+		assert_eq!(triangles.len(), 3);
+		for triangle in &triangles {
+			assert!(area(&points, *triangle) < 0.0, "{:?} is flipped", triangle);
 		}
 	}
 
-	Ok(obj)
+	#[test]
+	fn indices_resolve() {
+		assert_eq!(resolve_index("1", 3).unwrap(), 0);
+		assert_eq!(resolve_index("3", 3).unwrap(), 2);
+		assert_eq!(resolve_index("-1", 3).unwrap(), 2);
+		assert_eq!(resolve_index("-3", 3).unwrap(), 0);
+		assert!(matches!(resolve_index("0", 3), Err(ErrorReason::ZeroIndex)));
+		assert!(matches!(
+			resolve_index("4", 3),
+			Err(ErrorReason::IndexOutOfBounds)
+		));
+		assert!(matches!(
+			resolve_index("-4", 3),
+			Err(ErrorReason::IndexOutOfBounds)
+		));
+		assert!(matches!(
+			resolve_index("x", 3),
+			Err(ErrorReason::MalformedFaceVertex)
+		));
+	}
+
+	#[test]
+	fn faces_with_normals_and_negative_indices() {
+		let dir = TempDir::new("obj-negative");
+		let path = write_obj(
+			&dir,
+			"v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf -3//-1 -2//-1 -1//1\n",
+		);
+		let mut warnings = Vec::new();
+		let (submeshes, _) = parse_submeshes(&path, ParseMode::Strict, &mut warnings).unwrap();
+		assert!(warnings.is_empty());
+		let object = &submeshes[0].object;
+		assert_eq!(object.vertexes.len(), 3);
+		assert_eq!(object.vertexes[1].position, glm::vec3(1.0, 0.0, 0.0));
+		assert_eq!(object.vertexes[2].normal, glm::vec3(0.0, 0.0, 1.0));
+		assert_eq!(object.vertexes[2].uv, glm::vec2(0.0, 0.0));
+	}
+
+	#[test]
+	fn out_of_range_index_is_an_error() {
+		let dir = TempDir::new("obj-out_of_range");
+		let path = write_obj(&dir, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 5\n");
+		let error = parse_submeshes(&path, ParseMode::Strict, &mut Vec::new()).unwrap_err();
+		assert!(matches!(error.reason, ErrorReason::IndexOutOfBounds));
+		assert_eq!(error.line, Some(4));
+		assert_eq!(error.column, Some(7));
+		assert_eq!(error.token.as_deref(), Some("5"));
+	}
+
+	const BAD_LINE: &str = "v 0 0 0\nv 1 x 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+
+	fn assert_points_at_bad_line(error: &ParseError) {
+		assert!(matches!(error.reason, ErrorReason::InvalidNumber));
+		assert_eq!(error.line, Some(2));
+		assert_eq!(error.column, Some(5));
+		assert_eq!(error.token.as_deref(), Some("x"));
+	}
+
+	#[test]
+	fn strict_parsing_stops_at_a_bad_line() {
+		let dir = TempDir::new("obj-strict");
+		let path = write_obj(&dir, BAD_LINE);
+		let error = parse_submeshes(&path, ParseMode::Strict, &mut Vec::new()).unwrap_err();
+		assert_points_at_bad_line(&error);
+	}
+
+	#[test]
+	fn lenient_parsing_skips_a_bad_line() {
+		let dir = TempDir::new("obj-lenient");
+		let path = write_obj(&dir, BAD_LINE);
+		let mut warnings = Vec::new();
+		let (submeshes, _) = parse_submeshes(&path, ParseMode::Lenient, &mut warnings).unwrap();
+		assert_eq!(warnings.len(), 1);
+		assert_points_at_bad_line(&warnings[0]);
+		// The bad vertex never made it in, so the face uses the ones after it
+		assert_eq!(
+			submeshes[0].object.vertexes[0].position,
+			glm::vec3(0.0, 0.0, 0.0)
+		);
+		assert_eq!(
+			submeshes[0].object.vertexes[1].position,
+			glm::vec3(1.0, 0.0, 0.0)
+		);
+	}
+
+	#[test]
+	fn unknown_directives_are_still_cached() {
+		let dir = TempDir::new("obj-unknown");
+		let path = write_obj(&dir, "v 0 0 0\nv 1 0 0\nv 0 1 0\nvp 1 2\nf 1 2 3\n");
+		let cache = mesh_cache::cache_path(&path);
+		// Loading from the cache warns just the same
+		for _ in 0..2 {
+			let model = load(path.clone(), ParseMode::Lenient).unwrap();
+			assert_eq!(model.warnings.len(), 1);
+			let warning = &model.warnings[0];
+			assert!(matches!(warning.reason, ErrorReason::UnknownDirective));
+			assert_eq!(
+				warning.to_string(),
+				format!("{}:4:1: unknown directive, ignored (at `vp`)", path)
+			);
+			assert!(cache.exists());
+		}
+	}
+
+	#[test]
+	fn changed_files_are_parsed_again() {
+		let dir = TempDir::new("obj-changed");
+		let path = write_obj(&dir, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n");
+		load(path.clone(), ParseMode::Strict).unwrap();
+		let modified = fs::metadata(&path).and_then(|m| m.modified()).unwrap();
+		// Even when the modification time and length stay the same
+		write_obj(&dir, "v 0 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\n");
+		File::options()
+			.write(true)
+			.open(&path)
+			.and_then(|f| f.set_modified(modified))
+			.unwrap();
+		let model = load(path, ParseMode::Strict).unwrap();
+		let positions: Vec<glm::Vec3> = model.submeshes[0]
+			.object
+			.vertexes
+			.iter()
+			.map(|v| v.position)
+			.collect();
+		assert!(positions.contains(&glm::vec3(2.0, 0.0, 0.0)));
+		assert!(model.warnings.is_empty());
+	}
+
+	#[test]
+	fn skipped_lines_are_not_cached() {
+		let dir = TempDir::new("obj-skipped");
+		let path = write_obj(&dir, BAD_LINE);
+		let cache = mesh_cache::cache_path(&path);
+		let model = load(path, ParseMode::Lenient).unwrap();
+		assert_eq!(model.warnings.len(), 1);
+		assert!(!cache.exists());
+	}
 }
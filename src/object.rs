@@ -1,17 +1,43 @@
-use crate::components::model::ModelComponent;
-use gl::types::*;
-use std::ffi::c_void;
-use std::mem;
-use std::ptr;
+use crate::bounds::Aabb;
+use crate::resources::mesh_store::{Indices, Mesh};
+use std::collections::HashMap;
 
 use glium::implement_vertex;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[repr(C)]
 pub struct Vertex {
 	pub position: glm::Vec3,
 	pub normal: glm::Vec3,
 	pub uv: glm::Vec2,
+	// The bitangent is cross(normal, tangent.xyz) * tangent.w
+	pub tangent: Option<glm::Vec4>,
+}
+
+// Adding zero turns -0.0 into 0.0, so they compare equal
+pub fn bits(f: f32) -> u32 {
+	(f + 0.0).to_bits()
+}
+
+impl Vertex {
+	// Bitwise identity of all attributes, used for deduplication
+	fn key(&self) -> [u32; 12] {
+		let tangent = self.tangent.unwrap_or_else(glm::Vec4::zeros);
+		[
+			bits(self.position.x),
+			bits(self.position.y),
+			bits(self.position.z),
+			bits(self.normal.x),
+			bits(self.normal.y),
+			bits(self.normal.z),
+			bits(self.uv.x),
+			bits(self.uv.y),
+			bits(tangent.x),
+			bits(tangent.y),
+			bits(tangent.z),
+			bits(tangent.w),
+		]
+	}
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -19,9 +45,11 @@ pub struct VertexArray {
 	pub position: [f32; 3],
 	pub normal: [f32; 3],
 	pub uv: [f32; 2],
+	// All zeroes when the mesh has no tangents
+	pub tangent: [f32; 4],
 }
 
-implement_vertex!(VertexArray, position, normal, uv);
+implement_vertex!(VertexArray, position, normal, uv, tangent);
 
 #[derive(Debug)]
 #[repr(C)]
@@ -32,23 +60,58 @@ pub struct VertexIndex(pub u32, pub u32, pub u32);
 pub struct Object {
 	pub vertexes: Vec<Vertex>,
 	pub triangle_indices: Vec<VertexIndex>,
+	// OBJ smoothing group of every triangle, 0 meaning flat
+	pub smoothing_groups: Vec<u32>,
 	pub model: glm::Mat4,
 }
 
 impl Object {
-	#[allow(dead_code)]
-	pub fn get_component(&self) -> ModelComponent {
+	// Merge vertices with identical position, normal, uv and tangent,
+	// rewriting the triangles to point at the remaining ones.
+	pub fn deduplicate(&mut self) {
+		let mut seen: HashMap<[u32; 12], u32> = HashMap::new();
+		let mut vertexes = Vec::new();
+		let mut remap = Vec::with_capacity(self.vertexes.len());
+		for vertex in self.vertexes.drain(..) {
+			let next = vertexes.len() as u32;
+			let idx = *seen.entry(vertex.key()).or_insert(next);
+			if idx == next {
+				vertexes.push(vertex);
+			}
+			remap.push(idx);
+		}
+		self.vertexes = vertexes;
+		for VertexIndex(a, b, c) in &mut self.triangle_indices {
+			*a = remap[*a as usize];
+			*b = remap[*b as usize];
+			*c = remap[*c as usize];
+		}
+	}
+
+	pub fn get_mesh(&self) -> Mesh {
 		let mut v = Vec::new();
 		for vertex in &self.vertexes {
 			v.push(VertexArray {
 				position: [vertex.position.x, vertex.position.y, vertex.position.z],
 				normal: [vertex.normal.x, vertex.normal.y, vertex.normal.z],
 				uv: [vertex.uv.x, vertex.uv.y],
+				tangent: vertex.tangent.unwrap_or_else(glm::Vec4::zeros).into(),
 			});
 		}
-		ModelComponent {
+		let flat = self
+			.triangle_indices
+			.iter()
+			.flat_map(|&VertexIndex(a, b, c)| [a, b, c]);
+		// Use the smallest index type that can address every vertex
+		let indices = if self.vertexes.len() <= u16::MAX as usize + 1 {
+			Indices::U16(flat.map(|i| i as u16).collect())
+		} else {
+			Indices::U32(flat.collect())
+		};
+		Mesh {
+			bounds: Aabb::from_points(self.vertexes.iter().map(|vertex| vertex.position)),
 			vertices: v,
-			indices: glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+			indices,
 		}
 	}
 }
@@ -58,6 +121,7 @@ impl Default for Object {
 		Self {
 			vertexes: Vec::new(),
 			triangle_indices: Vec::new(),
+			smoothing_groups: Vec::new(),
 			model: glm::mat4(
 				1.0, 0.0, 0.0, 0.0, //
 				0.0, 1.0, 0.0, 0.0, //
@@ -67,3 +131,96 @@ impl Default for Object {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn vertex(x: f32, y: f32) -> Vertex {
+		Vertex {
+			position: glm::vec3(x, y, 0.0),
+			normal: glm::vec3(0.0, 0.0, 1.0),
+			uv: glm::vec2(x, y),
+			tangent: None,
+		}
+	}
+
+	// A quad as two triangles with vertices of their own, like a parsed OBJ
+	fn quad() -> Object {
+		let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+		let mut object = Object::default();
+		for &i in &[0, 1, 2, 0, 2, 3] {
+			let (x, y) = corners[i];
+			object.vertexes.push(vertex(x, y));
+		}
+		object.triangle_indices = vec![VertexIndex(0, 1, 2), VertexIndex(3, 4, 5)];
+		object
+	}
+
+	fn indices(object: &Object) -> Vec<(u32, u32, u32)> {
+		object
+			.triangle_indices
+			.iter()
+			.map(|&VertexIndex(a, b, c)| (a, b, c))
+			.collect()
+	}
+
+	#[test]
+	fn shared_corners_are_merged() {
+		let mut object = quad();
+		object.deduplicate();
+		assert_eq!(object.vertexes.len(), 4);
+		assert_eq!(indices(&object), vec![(0, 1, 2), (0, 2, 3)]);
+		assert_eq!(object.vertexes[3].position, glm::vec3(0.0, 1.0, 0.0));
+	}
+
+	#[test]
+	fn differing_attributes_are_kept_apart() {
+		let mut object = quad();
+		// A seam in the texture, and the tangent on the other side of a mirror
+		object.vertexes[3].uv = glm::vec2(0.5, 0.5);
+		object.vertexes[4].tangent = Some(glm::vec4(1.0, 0.0, 0.0, -1.0));
+		object.deduplicate();
+		assert_eq!(object.vertexes.len(), 6);
+		assert_eq!(indices(&object), vec![(0, 1, 2), (3, 4, 5)]);
+
+		// While the sign of zero doesn't matter
+		let mut object = quad();
+		object.vertexes[3].position.x = -0.0;
+		object.deduplicate();
+		assert_eq!(object.vertexes.len(), 4);
+	}
+
+	// A strip of `count` vertices, with triangles using the last ones
+	fn strip(count: u32) -> Object {
+		let mut object = Object::default();
+		for i in 0..count {
+			object.vertexes.push(vertex(i as f32, (i % 2) as f32));
+		}
+		object.triangle_indices = vec![
+			VertexIndex(0, 1, 2),
+			VertexIndex(count - 3, count - 2, count - 1),
+		];
+		object
+	}
+
+	#[test]
+	fn small_meshes_use_short_indices() {
+		let mesh = strip(u16::MAX as u32 + 1).get_mesh();
+		assert_eq!(mesh.vertices.len(), 65536);
+		match mesh.indices {
+			Indices::U16(indices) => assert_eq!(indices, vec![0, 1, 2, 65533, 65534, 65535]),
+			Indices::U32(_) => panic!("65536 vertices fit in 16 bit indices"),
+		}
+	}
+
+	#[test]
+	fn large_meshes_use_long_indices() {
+		let mesh = strip(u16::MAX as u32 + 2).get_mesh();
+		match mesh.indices {
+			Indices::U32(indices) => assert_eq!(indices, vec![0, 1, 2, 65534, 65535, 65536]),
+			Indices::U16(_) => panic!("65537 vertices don't fit in 16 bit indices"),
+		}
+		assert_eq!(mesh.bounds.max, glm::vec3(65536.0, 1.0, 0.0));
+	}
+}
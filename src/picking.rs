@@ -0,0 +1,372 @@
+use specs::prelude::*;
+
+use crate::bounds::Ray;
+use crate::components::{
+	model::ModelComponent,
+	name::NameComponent,
+	transformation::{GlobalTransformationComponent, TransformationComponent},
+};
+use crate::resources::{
+	bvh::Bvh,
+	camera::Camera,
+	mesh_store::{Mesh, MeshStore},
+	projection::Projection,
+	selected_entity::SelectedEntity,
+};
+
+// The ray from the camera through a point on the window, given in the
+// same units as the window size with the origin in the top left corner.
+pub fn screen_ray(
+	position: &glm::Vec2,
+	window_size: &glm::Vec2,
+	projection: &Projection,
+	view: &glm::Mat4,
+) -> Ray {
+	let x = 2.0 * position.x / window_size.x - 1.0;
+	let y = 1.0 - 2.0 * position.y / window_size.y;
+	let matrix = projection.matrix(window_size.x / window_size.y.max(1.0));
+	let inverse = glm::inverse(&(matrix * view));
+	let unproject = |z: f32| {
+		let point = inverse * glm::vec4(x, y, z, 1.0);
+		glm::vec4_to_vec3(&point) / point.w
+	};
+	// Where the point is on the near plane, and halfway in depth.
+	// The far plane may be infinitely far away.
+	let (near, far) = projection.depth_range();
+	let (near, middle) = (unproject(near), unproject((near + far) / 2.0));
+	Ray {
+		origin: near,
+		direction: (middle - near).normalize(),
+	}
+}
+
+// Where a point on the window is within a viewport, given in fractions of the
+// window from the bottom left like those of camera entities, and how big the
+// viewport is. Nothing if the point is outside of it.
+pub fn viewport_position(
+	position: &glm::Vec2,
+	window_size: &glm::Vec2,
+	viewport: &glm::Vec4,
+) -> Option<(glm::Vec2, glm::Vec2)> {
+	let size = glm::vec2(viewport.z * window_size.x, viewport.w * window_size.y);
+	// The top left corner, as the window's origin is there
+	let corner = glm::vec2(
+		viewport.x * window_size.x,
+		(1.0 - viewport.y - viewport.w) * window_size.y,
+	);
+	let inside = position - corner;
+	if inside.x < 0.0 || inside.y < 0.0 || inside.x >= size.x || inside.y >= size.y {
+		return None;
+	}
+	Some((inside, size))
+}
+
+// Möller-Trumbore, how far along the ray it hits the triangle if it does.
+// Triangles are hit from either side.
+fn intersect_triangle(ray: &Ray, [a, b, c]: &[glm::Vec3; 3]) -> Option<f32> {
+	let (edge1, edge2) = (b - a, c - a);
+	let p = ray.direction.cross(&edge2);
+	let determinant = edge1.dot(&p);
+	if determinant.abs() < f32::EPSILON {
+		return None;
+	}
+	let inverse = 1.0 / determinant;
+	let to_origin = ray.origin - a;
+	let u = to_origin.dot(&p) * inverse;
+	if !(0.0..=1.0).contains(&u) {
+		return None;
+	}
+	let q = to_origin.cross(&edge1);
+	let v = ray.direction.dot(&q) * inverse;
+	if v < 0.0 || u + v > 1.0 {
+		return None;
+	}
+	let distance = edge2.dot(&q) * inverse;
+	if distance >= 0.0 {
+		Some(distance)
+	} else {
+		None
+	}
+}
+
+// How far along the ray it first hits the mesh, placed by `model`
+pub fn intersect_mesh(ray: &Ray, mesh: &Mesh, model: &glm::Mat4) -> Option<f32> {
+	// Bringing the ray into model space keeps distances along it the same,
+	// and spares transforming every vertex.
+	let inverse = glm::inverse(model);
+	let origin = inverse * glm::vec4(ray.origin.x, ray.origin.y, ray.origin.z, 1.0);
+	let local = Ray {
+		origin: glm::vec4_to_vec3(&origin),
+		direction: glm::mat4_to_mat3(&inverse) * ray.direction,
+	};
+	mesh.triangles()
+		.filter_map(|triangle| intersect_triangle(&local, &triangle))
+		.fold(None, |closest: Option<f32>, distance| {
+			Some(closest.map_or(distance, |closest| closest.min(distance)))
+		})
+}
+
+// The entity whose mesh the ray hits first, and how far along the ray that is.
+// Bounds in the BVH narrow it down before testing actual triangles.
+pub fn pick(
+	ray: &Ray,
+	bvh: &Bvh,
+	trans: &ReadStorage<GlobalTransformationComponent>,
+	models: &ReadStorage<ModelComponent>,
+	mesh_store: &MeshStore,
+) -> Option<(Entity, f32)> {
+	let mut closest: Option<(Entity, f32)> = None;
+	for (entity, entered) in bvh.cast_ray(ray) {
+		// Candidates come in the order the ray enters their bounds,
+		// so once those are further than a hit nothing can beat it.
+		if closest.is_some_and(|(_, closest)| entered > closest) {
+			break;
+		}
+		let (trans, mesh) = match (
+			trans.get(entity),
+			models
+				.get(entity)
+				.and_then(|model| mesh_store.get(&model.0)),
+		) {
+			(Some(trans), Some(mesh)) => (trans, mesh),
+			_ => continue,
+		};
+		if let Some(distance) = intersect_mesh(ray, mesh, &trans.0) {
+			if closest.is_none_or(|(_, closest)| distance < closest) {
+				closest = Some((entity, distance));
+			}
+		}
+	}
+	closest
+}
+
+// Show what's selected in the overlay
+pub fn draw_ui(ui: &imgui::Ui, world: &World) {
+	use imgui::Condition;
+
+	let mut selected = world.write_resource::<SelectedEntity>();
+	let names = world.read_storage::<NameComponent>();
+	let trans = world.read_storage::<GlobalTransformationComponent>();
+	let mut locals = world.write_storage::<TransformationComponent>();
+	let bvh = world.read_resource::<Bvh>();
+	let camera = world.read_resource::<Camera>();
+	imgui::Window::new(ui, im_str!("Selection"))
+		.size([300.0, 200.0], Condition::FirstUseEver)
+		.position([50.0, 350.0], Condition::FirstUseEver)
+		.build(|| {
+			// Handy for finding small things, or when there's nothing under the cursor
+			if ui.button(im_str!("Select nearest"), [0.0, 0.0]) {
+				if let Some((nearest, _)) = bvh.nearest(&camera.position) {
+					selected.0 = Some(nearest);
+				}
+			}
+			let entity = match selected.0 {
+				Some(entity) => entity,
+				None => {
+					ui.text("Click on something to select it");
+					return;
+				}
+			};
+			let name = names
+				.get(entity)
+				.map(|name| name.0.clone())
+				.unwrap_or_else(|| format!("Entity {}", entity.id()));
+			ui.text_colored([1.0, 0.8, 0.2, 1.0], name);
+			if let Some(trans) = trans.get(entity) {
+				let position = trans.get_pos();
+				ui.text(format!(
+					"Position: {:.2}, {:.2}, {:.2}",
+					position.x, position.y, position.z
+				));
+			}
+			if let Some(local) = locals.get_mut(entity) {
+				local.edit(ui);
+			}
+			if ui.button(im_str!("Deselect"), [0.0, 0.0]) {
+				selected.0 = None;
+			}
+		});
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::object::{Object, Vertex, VertexIndex};
+
+	fn triangle() -> [glm::Vec3; 3] {
+		[
+			glm::vec3(0.0, 0.0, 0.0),
+			glm::vec3(1.0, 0.0, 0.0),
+			glm::vec3(0.0, 1.0, 0.0),
+		]
+	}
+
+	fn ray(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+		Ray { origin, direction }
+	}
+
+	fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+		assert!(glm::distance(&a, &b) < 1e-4, "{:?} != {:?}", a, b);
+	}
+
+	#[test]
+	fn rays_hit_inside_the_triangle() {
+		let down = glm::vec3(0.0, 0.0, -1.0);
+		let hit = intersect_triangle(&ray(glm::vec3(0.25, 0.25, 2.0), down), &triangle());
+		assert_eq!(hit, Some(2.0));
+		// From the back too
+		let up = ray(glm::vec3(0.25, 0.25, -3.0), glm::vec3(0.0, 0.0, 1.0));
+		assert_eq!(intersect_triangle(&up, &triangle()), Some(3.0));
+	}
+
+	#[test]
+	fn rays_miss_outside_the_triangle() {
+		let down = glm::vec3(0.0, 0.0, -1.0);
+		assert_eq!(
+			intersect_triangle(&ray(glm::vec3(0.75, 0.75, 2.0), down), &triangle()),
+			None
+		);
+		assert_eq!(
+			intersect_triangle(&ray(glm::vec3(-0.1, 0.5, 2.0), down), &triangle()),
+			None
+		);
+	}
+
+	#[test]
+	fn parallel_rays_miss() {
+		let along = ray(glm::vec3(-1.0, 0.25, 0.0), glm::vec3(1.0, 0.0, 0.0));
+		assert_eq!(intersect_triangle(&along, &triangle()), None);
+		let above = ray(glm::vec3(-1.0, 0.25, 1.0), glm::vec3(1.0, 0.0, 0.0));
+		assert_eq!(intersect_triangle(&above, &triangle()), None);
+	}
+
+	#[test]
+	fn triangles_behind_the_ray_are_missed() {
+		let away = ray(glm::vec3(0.25, 0.25, 2.0), glm::vec3(0.0, 0.0, 1.0));
+		assert_eq!(intersect_triangle(&away, &triangle()), None);
+	}
+
+	// A unit quad in the XY plane, facing +z
+	fn quad() -> Mesh {
+		let mut object = Object::default();
+		for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+			object.vertexes.push(Vertex {
+				position: glm::vec3(x, y, 0.0),
+				normal: glm::vec3(0.0, 0.0, 1.0),
+				uv: glm::vec2(x, y),
+				tangent: None,
+			});
+		}
+		object.triangle_indices = vec![VertexIndex(0, 1, 2), VertexIndex(0, 2, 3)];
+		object.get_mesh()
+	}
+
+	#[test]
+	fn meshes_are_hit_where_their_model_puts_them() {
+		let mesh = quad();
+		let down = glm::vec3(0.0, 0.0, -1.0);
+		let moved = glm::translation(&glm::vec3(0.0, 0.0, -5.0));
+		assert_eq!(
+			intersect_mesh(&ray(glm::vec3(0.5, 0.5, 0.0), down), &mesh, &moved),
+			Some(5.0)
+		);
+
+		// Only the scaled quad reaches this far out, distances stay in world units
+		let scaled = moved * glm::scaling(&glm::vec3(2.0, 2.0, 2.0));
+		let outside = ray(glm::vec3(1.5, 1.5, 0.0), down);
+		assert_eq!(intersect_mesh(&outside, &mesh, &moved), None);
+		assert_eq!(intersect_mesh(&outside, &mesh, &scaled), Some(5.0));
+
+		// Turned to lie flat, spanning x and z from -5 to -4
+		let rotated = moved * glm::rotation(90f32.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+		let from_above = ray(glm::vec3(0.5, 3.0, -4.5), glm::vec3(0.0, -1.0, 0.0));
+		let distance = intersect_mesh(&from_above, &mesh, &rotated).unwrap();
+		assert!((distance - 3.0).abs() < 1e-5, "{}", distance);
+		assert_eq!(intersect_mesh(&from_above, &mesh, &moved), None);
+	}
+
+	#[test]
+	fn the_screen_centre_looks_forward() {
+		let mut camera = Camera {
+			position: glm::vec3(1.0, 2.0, 3.0),
+			yaw: 30.0,
+			pitch: -20.0,
+			..Camera::default()
+		};
+		camera.update_camera_vectors();
+		let window_size = glm::vec2(800.0, 600.0);
+		for &projection in &[
+			Projection::default(),
+			Projection::Perspective {
+				fov: 1.0,
+				near: 0.1,
+				far: None,
+				reverse_z: false,
+			},
+			Projection::Perspective {
+				fov: 1.0,
+				near: 0.1,
+				far: Some(100.0),
+				reverse_z: true,
+			},
+			Projection::Perspective {
+				fov: 1.0,
+				near: 0.1,
+				far: None,
+				reverse_z: true,
+			},
+		] {
+			let ray = screen_ray(
+				&(window_size / 2.0),
+				&window_size,
+				&projection,
+				&camera.get_view_matrix(),
+			);
+			assert_close(ray.direction, camera.front);
+			// Starting on the near plane
+			assert_close(ray.origin, camera.position + camera.front * 0.1);
+		}
+	}
+
+	#[test]
+	fn reversed_depth_picks_along_the_same_rays() {
+		let window_size = glm::vec2(800.0, 600.0);
+		let view = glm::look_at(
+			&glm::vec3(1.0, 2.0, 3.0),
+			&glm::Vec3::zeros(),
+			&glm::vec3(0.0, 1.0, 0.0),
+		);
+		for &far in &[Some(100.0), None] {
+			let reversed = Projection::Perspective {
+				fov: 1.0,
+				near: 0.1,
+				far,
+				reverse_z: true,
+			};
+			let position = glm::vec2(100.0, 500.0);
+			let a = screen_ray(&position, &window_size, &reversed, &view);
+			let b = screen_ray(&position, &window_size, &reversed.regular(), &view);
+			assert_close(a.origin, b.origin);
+			assert_close(a.direction, b.direction);
+		}
+	}
+
+	#[test]
+	fn viewports_contain_points_from_the_top_left() {
+		let window_size = glm::vec2(800.0, 600.0);
+		// The top right quarter
+		let viewport = glm::vec4(0.5, 0.5, 0.5, 0.5);
+		assert_eq!(
+			viewport_position(&glm::vec2(500.0, 100.0), &window_size, &viewport),
+			Some((glm::vec2(100.0, 100.0), glm::vec2(400.0, 300.0)))
+		);
+		assert_eq!(
+			viewport_position(&glm::vec2(500.0, 400.0), &window_size, &viewport),
+			None
+		);
+		assert_eq!(
+			viewport_position(&glm::vec2(300.0, 100.0), &window_size, &viewport),
+			None
+		);
+	}
+}
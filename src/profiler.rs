@@ -1,5 +1,7 @@
 use std::collections::VecDeque;
 
+use crate::resources::render_stats::RenderStats;
+
 pub struct Profiler {
 	pub delays: VecDeque<f32>,
 	pub view_window: i32,
@@ -25,13 +27,14 @@ impl Profiler {
 		self.delays.iter().copied().collect()
 	}
 
-	pub fn draw_ui(&mut self, delta_time: f32, ui: &mut imgui::Ui) {
+	pub fn draw_ui(&mut self, delta_time: f32, stats: &RenderStats, ui: &mut imgui::Ui) {
 		use imgui::Condition;
 		imgui::Window::new(ui, im_str!("Profiler"))
-			.size([400.0, 125.0], Condition::FirstUseEver)
+			.size([400.0, 145.0], Condition::FirstUseEver)
 			.position([50.0, 200.0], Condition::FirstUseEver)
 			.build(|| {
 				ui.text(format!("FPS: {:.2}/{:.5}ms", 1.0 / delta_time, delta_time));
+				ui.text(format!("Drawn: {}, culled: {}", stats.drawn, stats.culled));
 				ui.slider_int(im_str!("View window"), &mut self.view_window, 10, 1000).build();
 
 				ui.plot_lines(im_str!("Delay (ms)"), self.as_vec().as_ref())
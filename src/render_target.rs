@@ -0,0 +1,54 @@
+use std::rc::Rc;
+
+use glium::backend::Facade;
+use glium::framebuffer::{DepthRenderBuffer, SimpleFrameBuffer};
+use glium::texture::{DepthFormat, RawImage2d, Texture2d};
+
+// Where the renderer draws a frame, handed to it by whoever drives the frame loop
+pub enum RenderTarget {
+	// The window's back buffer
+	Frame(glium::Frame),
+	// Textures, for rendering without a window
+	Offscreen(Offscreen),
+}
+
+pub struct Offscreen {
+	// Shared so the overlay can show it
+	pub color: Rc<Texture2d>,
+	pub depth: DepthRenderBuffer,
+}
+
+impl Offscreen {
+	pub fn new<F: Facade>(facade: &F, width: u32, height: u32) -> Result<Self, String> {
+		Ok(Self {
+			color: Rc::new(
+				Texture2d::empty(facade, width, height)
+					.map_err(|e| format!("Could not create color buffer: {:?}", e))?,
+			),
+			// Floating point, for the precision reversed depth gains
+			depth: DepthRenderBuffer::new(facade, DepthFormat::F32, width, height)
+				.map_err(|e| format!("Could not create depth buffer: {:?}", e))?,
+		})
+	}
+
+	pub fn framebuffer<'a, F: Facade>(
+		&'a self,
+		facade: &F,
+	) -> Result<SimpleFrameBuffer<'a>, String> {
+		SimpleFrameBuffer::with_depth_buffer(facade, &*self.color, &self.depth)
+			.map_err(|e| format!("Could not create framebuffer: {:?}", e))
+	}
+
+	pub fn dimensions(&self) -> (u32, u32) {
+		self.color.dimensions()
+	}
+
+	// Read back what was drawn, with the top row first
+	pub fn read_image(&self) -> Result<image::RgbaImage, String> {
+		let raw: RawImage2d<u8> = self.color.read();
+		let image = image::RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned())
+			.ok_or_else(|| "Framebuffer has the wrong size".to_string())?;
+		// OpenGL puts the first row at the bottom
+		Ok(image::imageops::flip_vertical(&image))
+	}
+}
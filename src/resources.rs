@@ -1,10 +1,19 @@
 // These are the resources that the world has available to it
 // We can access these through specs::Read
 
+pub mod asset_store;
+pub mod bvh;
 pub mod camera;
+pub mod capture;
 pub mod delta_time;
 pub mod key_state;
+pub mod material_store;
+pub mod mesh_store;
 pub mod mouse_state;
 pub mod projection;
+pub mod render_stats;
+pub mod selected_entity;
+pub mod shader_store;
 pub mod texture_map;
 pub mod time;
+pub mod window_size;
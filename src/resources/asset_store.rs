@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+// Identifies a slot in an AssetStore at a point in time.
+// Reusing a slot bumps its generation, so stale ids never alias new assets.
+#[derive(Copy, Clone, Debug, Ord, Eq, Hash, PartialEq, PartialOrd)]
+pub struct HandleId {
+	pub index: u32,
+	pub generation: u32,
+}
+
+// A counted reference to an asset.
+// Every clone keeps the asset alive until the store is maintained without it.
+pub struct Handle<T> {
+	id: HandleId,
+	refs: Arc<()>,
+	_marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+	pub fn id(&self) -> HandleId {
+		self.id
+	}
+}
+
+impl<T> Clone for Handle<T> {
+	fn clone(&self) -> Self {
+		Self {
+			id: self.id,
+			refs: self.refs.clone(),
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<T> PartialEq for Handle<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.id == other.id
+	}
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Handle({}v{})", self.id.index, self.id.generation)
+	}
+}
+
+struct Entry<T> {
+	asset: T,
+	// The store's own reference, compared against the handles handed out
+	refs: Arc<()>,
+	path: Option<String>,
+}
+
+struct Slot<T> {
+	generation: u32,
+	entry: Option<Entry<T>>,
+}
+
+pub struct AssetStore<T> {
+	slots: Vec<Slot<T>>,
+	free_slots: Vec<u32>,
+	paths: HashMap<String, u32>,
+}
+
+impl<T> Default for AssetStore<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T> AssetStore<T> {
+	pub fn new() -> Self {
+		Self {
+			slots: Vec::new(),
+			free_slots: Vec::new(),
+			paths: HashMap::new(),
+		}
+	}
+
+	fn insert_entry(&mut self, asset: T, path: Option<String>) -> Handle<T> {
+		let index = match self.free_slots.pop() {
+			Some(index) => index,
+			None => {
+				self.slots.push(Slot {
+					generation: 0,
+					entry: None,
+				});
+				self.slots.len() as u32 - 1
+			}
+		};
+		if let Some(path) = &path {
+			self.paths.insert(path.clone(), index);
+		}
+
+		let slot = &mut self.slots[index as usize];
+		let refs = Arc::new(());
+		slot.entry = Some(Entry {
+			asset,
+			refs: refs.clone(),
+			path,
+		});
+		Handle {
+			id: HandleId {
+				index,
+				generation: slot.generation,
+			},
+			refs,
+			_marker: PhantomData,
+		}
+	}
+
+	fn entry(&self, id: HandleId) -> Option<&Entry<T>> {
+		self.slots
+			.get(id.index as usize)
+			.filter(|slot| slot.generation == id.generation)
+			.and_then(|slot| slot.entry.as_ref())
+	}
+
+	fn handle_at(&self, index: u32) -> Option<Handle<T>> {
+		let slot = &self.slots[index as usize];
+		slot.entry.as_ref().map(|entry| Handle {
+			id: HandleId {
+				index,
+				generation: slot.generation,
+			},
+			refs: entry.refs.clone(),
+			_marker: PhantomData,
+		})
+	}
+
+	// Load the asset at `path`, or hand out another reference
+	// to it if it has been loaded before.
+	pub fn load<E, F>(&mut self, path: &str, load: F) -> Result<Handle<T>, E>
+	where
+		F: FnOnce(&str) -> Result<T, E>,
+	{
+		if let Some(handle) = self.find(path) {
+			return Ok(handle);
+		}
+		let asset = load(path)?;
+		Ok(self.insert_entry(asset, Some(path.to_string())))
+	}
+
+	pub fn get(&self, handle: &Handle<T>) -> Option<&T> {
+		self.entry(handle.id).map(|entry| &entry.asset)
+	}
+
+	// Another reference to the asset loaded from `path`, if there is one
+	pub fn find(&self, path: &str) -> Option<Handle<T>> {
+		self.paths
+			.get(path)
+			.and_then(|&index| self.handle_at(index))
+	}
+
+	// The paths of every asset that was loaded from one
+	pub fn paths(&self) -> impl Iterator<Item = &str> {
+		self.paths.keys().map(String::as_str)
+	}
+
+	// Swap in a new version of an asset, for instance after its file changed.
+	// Existing handles keep working and refer to the new version.
+	pub fn replace(&mut self, handle: &Handle<T>, asset: T) -> Option<T> {
+		let slot = self
+			.slots
+			.get_mut(handle.id.index as usize)
+			.filter(|slot| slot.generation == handle.id.generation)?;
+		let entry = slot.entry.as_mut()?;
+		Some(std::mem::replace(&mut entry.asset, asset))
+	}
+
+	fn remove(&mut self, index: u32) -> Option<HandleId> {
+		let slot = &mut self.slots[index as usize];
+		let entry = slot.entry.take()?;
+		let id = HandleId {
+			index,
+			generation: slot.generation,
+		};
+		slot.generation += 1;
+		if let Some(path) = entry.path {
+			self.paths.remove(&path);
+		}
+		self.free_slots.push(index);
+		Some(id)
+	}
+
+	// Drop an asset right away, even if handles to it remain.
+	// Those handles won't resolve to anything anymore.
+	pub fn unload(&mut self, handle: &Handle<T>) -> Option<HandleId> {
+		self.entry(handle.id)?;
+		self.remove(handle.id.index)
+	}
+
+	// Drop every asset that no handle refers to anymore,
+	// returning their ids so GPU copies can be freed as well.
+	pub fn maintain(&mut self) -> Vec<HandleId> {
+		let unused: Vec<u32> = self
+			.slots
+			.iter()
+			.enumerate()
+			.filter_map(|(index, slot)| match &slot.entry {
+				Some(entry) if Arc::strong_count(&entry.refs) == 1 => Some(index as u32),
+				_ => None,
+			})
+			.collect();
+		unused
+			.into_iter()
+			.filter_map(|index| self.remove(index))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn loading_a_path_twice_shares_the_asset() {
+		let mut store = AssetStore::new();
+		let mut loads = 0;
+		let mut load = |path: &str| {
+			loads += 1;
+			Ok::<_, ()>(path.len())
+		};
+		let first = store.load("a.png", &mut load).unwrap();
+		let second = store.load("a.png", &mut load).unwrap();
+		let other = store.load("bb.png", &mut load).unwrap();
+		assert_eq!(loads, 2);
+		assert_eq!(first, second);
+		assert_ne!(first, other);
+		assert_eq!(store.get(&second), Some(&5));
+		assert_eq!(store.find("bb.png"), Some(other));
+	}
+
+	#[test]
+	fn maintain_drops_unreferenced_assets() {
+		let mut store = AssetStore::new();
+		let handle = store.load("a.png", |_| Ok::<_, ()>(1)).unwrap();
+		let copy = handle.clone();
+		let id = handle.id();
+
+		drop(handle);
+		assert!(store.maintain().is_empty());
+		assert_eq!(store.get(&copy), Some(&1));
+
+		drop(copy);
+		assert_eq!(store.maintain(), vec![id]);
+		assert!(store.find("a.png").is_none());
+		assert_eq!(store.paths().count(), 0);
+	}
+
+	#[test]
+	fn unload_invalidates_remaining_handles() {
+		let mut store = AssetStore::new();
+		let handle = store.load("a.png", |_| Ok::<_, ()>(1)).unwrap();
+		assert_eq!(store.unload(&handle), Some(handle.id()));
+		assert_eq!(store.get(&handle), None);
+		assert!(store.find("a.png").is_none());
+		// Already gone, and maintaining doesn't report it a second time
+		assert_eq!(store.unload(&handle), None);
+		assert!(store.maintain().is_empty());
+
+		// The slot is reused with a new generation, which the old handle doesn't match
+		let reloaded = store.load("a.png", |_| Ok::<_, ()>(2)).unwrap();
+		assert_eq!(reloaded.id().index, handle.id().index);
+		assert_ne!(reloaded.id().generation, handle.id().generation);
+		assert_eq!(store.get(&handle), None);
+		assert_eq!(store.get(&reloaded), Some(&2));
+		assert_eq!(store.replace(&handle, 3), None);
+	}
+}
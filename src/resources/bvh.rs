@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+
+use specs::{BitSet, Entity};
+
+use crate::bounds::{Aabb, Frustum, Ray};
+
+// How much leaves are padded, relative to their size, so that entities
+// moving a little don't have to be put back in the tree every frame.
+const MARGIN: f32 = 0.1;
+
+#[derive(Debug)]
+enum NodeKind {
+	Leaf { entity: Entity, bounds: Aabb },
+	Branch { children: [usize; 2] },
+}
+
+#[derive(Debug)]
+struct Node {
+	// Around everything below the node, padded for leaves
+	aabb: Aabb,
+	parent: Option<usize>,
+	kind: NodeKind,
+}
+
+// A dynamic bounding volume hierarchy over the world bounds of every
+// entity with a model, kept up to date by the BVH system.
+// Leaves go where they grow the tree's surface area the least.
+#[derive(Debug, Default)]
+pub struct Bvh {
+	nodes: Vec<Option<Node>>,
+	free: Vec<usize>,
+	root: Option<usize>,
+	leaves: HashMap<Entity, usize>,
+}
+
+impl Bvh {
+	fn node(&self, index: usize) -> &Node {
+		self.nodes[index].as_ref().expect("BVH node was freed")
+	}
+
+	fn node_mut(&mut self, index: usize) -> &mut Node {
+		self.nodes[index].as_mut().expect("BVH node was freed")
+	}
+
+	fn allocate(&mut self, node: Node) -> usize {
+		match self.free.pop() {
+			Some(index) => {
+				self.nodes[index] = Some(node);
+				index
+			}
+			None => {
+				self.nodes.push(Some(node));
+				self.nodes.len() - 1
+			}
+		}
+	}
+
+	fn release(&mut self, index: usize) {
+		self.nodes[index] = None;
+		self.free.push(index);
+	}
+
+	// Put an entity in the tree, or move it if it's already there
+	pub fn update(&mut self, entity: Entity, bounds: Aabb) {
+		if let Some(&leaf) = self.leaves.get(&entity) {
+			let node = self.node_mut(leaf);
+			if node.aabb.contains(&bounds) {
+				node.kind = NodeKind::Leaf { entity, bounds };
+				return;
+			}
+			self.remove_leaf(leaf);
+			self.release(leaf);
+		}
+		let margin = (bounds.max - bounds.min) * MARGIN;
+		let leaf = self.allocate(Node {
+			aabb: bounds.grown(&margin),
+			parent: None,
+			kind: NodeKind::Leaf { entity, bounds },
+		});
+		self.insert_leaf(leaf);
+		self.leaves.insert(entity, leaf);
+	}
+
+	pub fn remove(&mut self, entity: Entity) {
+		if let Some(leaf) = self.leaves.remove(&entity) {
+			self.remove_leaf(leaf);
+			self.release(leaf);
+		}
+	}
+
+	// Drop every entity for which `keep` is false
+	pub fn retain<F: FnMut(Entity) -> bool>(&mut self, mut keep: F) {
+		let removed: Vec<Entity> = self
+			.leaves
+			.keys()
+			.copied()
+			.filter(|entity| !keep(*entity))
+			.collect();
+		for entity in removed {
+			self.remove(entity);
+		}
+	}
+
+	fn insert_leaf(&mut self, leaf: usize) {
+		let root = match self.root {
+			Some(root) => root,
+			None => {
+				self.root = Some(leaf);
+				return;
+			}
+		};
+		let aabb = self.node(leaf).aabb;
+
+		// Walk down to the sibling that makes the tree grow the least
+		let mut index = root;
+		while let NodeKind::Branch { children } = self.node(index).kind {
+			let node_aabb = self.node(index).aabb;
+			let combined = node_aabb.union(&aabb).surface_area();
+			// Making a new parent here costs the combined area, and going
+			// further down grows this node by the difference in any case.
+			let cost = 2.0 * combined;
+			let inherited = 2.0 * (combined - node_aabb.surface_area());
+			let child_cost = |child: usize| {
+				let child = self.node(child);
+				let grown = child.aabb.union(&aabb).surface_area();
+				match child.kind {
+					NodeKind::Leaf { .. } => grown + inherited,
+					NodeKind::Branch { .. } => grown - child.aabb.surface_area() + inherited,
+				}
+			};
+			let (left, right) = (child_cost(children[0]), child_cost(children[1]));
+			if cost < left && cost < right {
+				break;
+			}
+			index = if left < right {
+				children[0]
+			} else {
+				children[1]
+			};
+		}
+
+		let sibling = index;
+		let old_parent = self.node(sibling).parent;
+		let parent = self.allocate(Node {
+			aabb: self.node(sibling).aabb.union(&aabb),
+			parent: old_parent,
+			kind: NodeKind::Branch {
+				children: [sibling, leaf],
+			},
+		});
+		self.node_mut(sibling).parent = Some(parent);
+		self.node_mut(leaf).parent = Some(parent);
+		match old_parent {
+			Some(old_parent) => self.replace_child(old_parent, sibling, parent),
+			None => self.root = Some(parent),
+		}
+		self.refit(old_parent);
+	}
+
+	fn remove_leaf(&mut self, leaf: usize) {
+		let parent = match self.node(leaf).parent {
+			Some(parent) => parent,
+			None => {
+				self.root = None;
+				return;
+			}
+		};
+		let sibling = match self.node(parent).kind {
+			NodeKind::Branch { children } if children[0] == leaf => children[1],
+			NodeKind::Branch { children } => children[0],
+			NodeKind::Leaf { .. } => unreachable!("BVH leaf has a leaf as parent"),
+		};
+		// The sibling takes the place of the parent
+		let grandparent = self.node(parent).parent;
+		self.node_mut(sibling).parent = grandparent;
+		match grandparent {
+			Some(grandparent) => self.replace_child(grandparent, parent, sibling),
+			None => self.root = Some(sibling),
+		}
+		self.release(parent);
+		self.node_mut(leaf).parent = None;
+		self.refit(grandparent);
+	}
+
+	fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+		if let NodeKind::Branch { children } = &mut self.node_mut(parent).kind {
+			for child in children.iter_mut() {
+				if *child == old {
+					*child = new;
+				}
+			}
+		}
+	}
+
+	// Fit the boxes from `index` up to the root around their children again
+	fn refit(&mut self, mut index: Option<usize>) {
+		while let Some(current) = index {
+			if let NodeKind::Branch { children } = self.node(current).kind {
+				let aabb = self
+					.node(children[0])
+					.aabb
+					.union(&self.node(children[1]).aabb);
+				self.node_mut(current).aabb = aabb;
+			}
+			index = self.node(current).parent;
+		}
+	}
+
+	// Visit every leaf whose padded box passes `test`
+	fn visit<T, V>(&self, mut test: T, mut visit: V)
+	where
+		T: FnMut(&Aabb) -> bool,
+		V: FnMut(Entity, &Aabb),
+	{
+		let mut stack: Vec<usize> = self.root.into_iter().collect();
+		while let Some(index) = stack.pop() {
+			let node = self.node(index);
+			if !test(&node.aabb) {
+				continue;
+			}
+			match node.kind {
+				NodeKind::Leaf { entity, bounds } => visit(entity, &bounds),
+				NodeKind::Branch { children } => stack.extend(children.iter()),
+			}
+		}
+	}
+
+	// Every entity the ray hits, closest first, along with the distance
+	// along the ray to where it enters the entity's bounds.
+	pub fn cast_ray(&self, ray: &Ray) -> Vec<(Entity, f32)> {
+		let mut hits = Vec::new();
+		self.visit(
+			|aabb| aabb.ray_distance(ray).is_some(),
+			|entity, bounds| {
+				if let Some(distance) = bounds.ray_distance(ray) {
+					hits.push((entity, distance));
+				}
+			},
+		);
+		hits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+		hits
+	}
+
+	// The ids of every entity whose bounds may be in view, for culling
+	pub fn in_frustum(&self, frustum: &Frustum) -> BitSet {
+		let mut found = BitSet::new();
+		self.visit(
+			|node| frustum.intersects_aabb(node),
+			|entity, bounds| {
+				if frustum.intersects_aabb(bounds) {
+					found.add(entity.id());
+				}
+			},
+		);
+		found
+	}
+
+	// Every entity whose bounds overlap the box
+	pub fn overlapping(&self, aabb: &Aabb) -> Vec<Entity> {
+		let mut found = Vec::new();
+		self.visit(
+			|node| node.intersects(aabb),
+			|entity, bounds| {
+				if bounds.intersects(aabb) {
+					found.push(entity);
+				}
+			},
+		);
+		found
+	}
+
+	// The entity whose bounds are closest to the point, and how far away they are
+	pub fn nearest(&self, point: &glm::Vec3) -> Option<(Entity, f32)> {
+		let mut best: Option<(Entity, f32)> = None;
+		let mut stack: Vec<usize> = self.root.into_iter().collect();
+		while let Some(index) = stack.pop() {
+			let node = self.node(index);
+			// Padded boxes are never further away than what's inside them
+			let bound = node.aabb.distance_squared(point);
+			if best.is_some_and(|(_, best)| bound >= best) {
+				continue;
+			}
+			match node.kind {
+				NodeKind::Leaf { entity, bounds } => {
+					let distance = bounds.distance_squared(point);
+					if best.is_none_or(|(_, best)| distance < best) {
+						best = Some((entity, distance));
+					}
+				}
+				NodeKind::Branch { children } => {
+					// Try the closer child first, so the other one is more likely skipped
+					let distances =
+						children.map(|child| self.node(child).aabb.distance_squared(point));
+					if distances[0] < distances[1] {
+						stack.extend(&[children[1], children[0]]);
+					} else {
+						stack.extend(&children);
+					}
+				}
+			}
+		}
+		best.map(|(entity, distance)| (entity, distance.sqrt()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use specs::{Builder, World, WorldExt};
+
+	// Deterministic numbers between 0 and 1, so failures can be reproduced
+	struct Random(u32);
+
+	impl Random {
+		fn next(&mut self) -> f32 {
+			self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+			(self.0 >> 8) as f32 / (1 << 24) as f32
+		}
+
+		fn vec3(&mut self, scale: f32) -> glm::Vec3 {
+			glm::vec3(self.next(), self.next(), self.next()) * scale
+		}
+
+		fn aabb(&mut self) -> Aabb {
+			let min = self.vec3(100.0) - glm::vec3(50.0, 50.0, 50.0);
+			Aabb {
+				min,
+				max: min + self.vec3(10.0),
+			}
+		}
+	}
+
+	// Every entity along with its bounds
+	fn build(count: usize, random: &mut Random) -> (Bvh, Vec<(Entity, Aabb)>) {
+		let mut world = World::new();
+		let mut bvh = Bvh::default();
+		let mut entities = Vec::new();
+		for _ in 0..count {
+			let entity = world.create_entity().build();
+			let bounds = random.aabb();
+			bvh.update(entity, bounds);
+			entities.push((entity, bounds));
+		}
+		(bvh, entities)
+	}
+
+	// Walk the tree, checking that boxes contain their children,
+	// links go both ways, and every leaf is where the map says it is
+	fn check(bvh: &Bvh, expected: &[(Entity, Aabb)]) {
+		let mut seen = Vec::new();
+		let mut stack: Vec<usize> = bvh.root.into_iter().collect();
+		if let Some(root) = bvh.root {
+			assert_eq!(bvh.node(root).parent, None);
+		}
+		while let Some(index) = stack.pop() {
+			let node = bvh.node(index);
+			match node.kind {
+				NodeKind::Leaf { entity, bounds } => {
+					assert!(node.aabb.contains(&bounds));
+					assert_eq!(bvh.leaves[&entity], index);
+					seen.push((entity, bounds));
+				}
+				NodeKind::Branch { children } => {
+					for &child in &children {
+						assert_eq!(bvh.node(child).parent, Some(index));
+						assert!(node.aabb.contains(&bvh.node(child).aabb));
+						stack.push(child);
+					}
+				}
+			}
+		}
+		assert_eq!(seen.len(), expected.len());
+		assert_eq!(bvh.leaves.len(), expected.len());
+		for (entity, bounds) in expected {
+			assert!(seen.contains(&(*entity, *bounds)));
+		}
+		// Nodes are either in use or free to be reused
+		let used = bvh.nodes.iter().filter(|node| node.is_some()).count();
+		assert_eq!(used + bvh.free.len(), bvh.nodes.len());
+		assert_eq!(used, (2 * expected.len()).saturating_sub(1));
+	}
+
+	#[test]
+	fn updates_keep_the_tree_intact() {
+		let mut random = Random(1);
+		let (mut bvh, mut entities) = build(50, &mut random);
+		check(&bvh, &entities);
+
+		// Small moves stay within the padding, large ones don't
+		for (i, (entity, bounds)) in entities.iter_mut().enumerate() {
+			let offset = if i % 2 == 0 {
+				glm::vec3(0.1, 0.0, 0.0)
+			} else {
+				random.vec3(40.0)
+			};
+			*bounds = Aabb {
+				min: bounds.min + offset,
+				max: bounds.max + offset,
+			};
+			bvh.update(*entity, *bounds);
+		}
+		check(&bvh, &entities);
+
+		let removed: Vec<(Entity, Aabb)> = entities.drain(..20).collect();
+		for (entity, _) in &removed {
+			bvh.remove(*entity);
+		}
+		check(&bvh, &entities);
+		let kept: Vec<Entity> = entities
+			.iter()
+			.skip(10)
+			.map(|(entity, _)| *entity)
+			.collect();
+		bvh.retain(|entity| kept.contains(&entity));
+		entities.drain(..10);
+		check(&bvh, &entities);
+		for (entity, _) in entities.drain(..) {
+			bvh.remove(entity);
+		}
+		check(&bvh, &entities);
+		assert!(bvh.root.is_none());
+	}
+
+	#[test]
+	fn rays_hit_what_brute_force_hits() {
+		let mut random = Random(2);
+		let (bvh, entities) = build(100, &mut random);
+		for _ in 0..20 {
+			let ray = Ray {
+				origin: random.vec3(200.0) - glm::vec3(100.0, 100.0, 100.0),
+				direction: random.vec3(2.0) - glm::vec3(1.0, 1.0, 1.0),
+			};
+			let hits = bvh.cast_ray(&ray);
+			assert!(hits.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+			let mut expected: Vec<(Entity, f32)> = entities
+				.iter()
+				.filter_map(|(entity, bounds)| bounds.ray_distance(&ray).map(|d| (*entity, d)))
+				.collect();
+			let mut hits = hits;
+			hits.sort_by_key(|(entity, _)| entity.id());
+			expected.sort_by_key(|(entity, _)| entity.id());
+			assert_eq!(hits, expected);
+		}
+	}
+
+	#[test]
+	fn overlaps_match_brute_force() {
+		let mut random = Random(3);
+		let (bvh, entities) = build(100, &mut random);
+		for _ in 0..20 {
+			let query = random.aabb().grown(&random.vec3(20.0));
+			let mut found = bvh.overlapping(&query);
+			found.sort_by_key(|entity| entity.id());
+			let expected: Vec<Entity> = entities
+				.iter()
+				.filter(|(_, bounds)| bounds.intersects(&query))
+				.map(|(entity, _)| *entity)
+				.collect();
+			assert_eq!(found, expected);
+		}
+	}
+
+	#[test]
+	fn nearest_matches_brute_force() {
+		let mut random = Random(4);
+		let (bvh, entities) = build(100, &mut random);
+		assert_eq!(Bvh::default().nearest(&glm::Vec3::zeros()), None);
+		for _ in 0..20 {
+			let point = random.vec3(300.0) - glm::vec3(150.0, 150.0, 150.0);
+			let (_, distance) = bvh.nearest(&point).unwrap();
+			let expected = entities
+				.iter()
+				.map(|(_, bounds)| bounds.distance_squared(&point).sqrt())
+				.fold(f32::INFINITY, f32::min);
+			assert!(
+				(distance - expected).abs() < 1e-4,
+				"{} != {}",
+				distance,
+				expected
+			);
+		}
+	}
+
+	#[test]
+	fn frustums_keep_everything_in_view() {
+		let mut random = Random(5);
+		let (bvh, entities) = build(100, &mut random);
+		let projection = glm::perspective(1.5, 1.0, 0.1, 100.0);
+		let view = glm::look_at(
+			&glm::vec3(0.0, 0.0, 60.0),
+			&glm::Vec3::zeros(),
+			&glm::vec3(0.0, 1.0, 0.0),
+		);
+		let frustum = Frustum::from_matrix(&(projection * view));
+		let visible = bvh.in_frustum(&frustum);
+		for (entity, bounds) in &entities {
+			assert_eq!(
+				visible.contains(entity.id()),
+				frustum.intersects_aabb(bounds)
+			);
+		}
+	}
+}
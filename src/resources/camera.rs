@@ -1,4 +1,25 @@
-use glm;
+// How the camera system moves the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+	// Circles `target`, always looking at it
+	Orbit,
+	// Flies freely, picking up speed and slowing down gradually
+	Fly,
+	// Walks on the ground plane at eye height
+	FirstPerson,
+}
+
+impl CameraMode {
+	pub const ALL: [CameraMode; 3] = [CameraMode::Orbit, CameraMode::Fly, CameraMode::FirstPerson];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			CameraMode::Orbit => "Orbit",
+			CameraMode::Fly => "Fly",
+			CameraMode::FirstPerson => "First person",
+		}
+	}
+}
 
 #[derive(Debug)]
 pub struct Camera {
@@ -10,17 +31,27 @@ pub struct Camera {
 
 	pub yaw: f32,
 	pub pitch: f32,
+	// Turning eases towards these, rather than following the mouse exactly
+	pub yaw_goal: f32,
+	pub pitch_goal: f32,
 
 	pub speed: f32,
-	pub zoom: f32,
-}
 
-#[derive(Debug)]
-pub enum CameraDirection {
-	Forward,
-	Backward,
-	Left,
-	Right,
+	pub mode: CameraMode,
+	// What orbiting goes around, and how far from it the camera is
+	pub target: glm::Vec3,
+	pub distance: f32,
+	pub distance_goal: f32,
+	// The field of view, or orthographic height, zooming eases towards.
+	// Only set while zooming, so the projection can be changed elsewhere otherwise.
+	pub zoom_goal: Option<f32>,
+	// Only flying keeps going by itself
+	pub velocity: glm::Vec3,
+	// How high above the ground plane the first person camera is
+	pub eye_height: f32,
+	// Whether the main view is drawn, turned off when camera entities
+	// cover the whole window, like in split screen
+	pub active: bool,
 }
 
 impl Default for Camera {
@@ -33,58 +64,75 @@ impl Default for Camera {
 			world_up: glm::Vec3::new(0.0, 1.0, 0.0),
 			yaw: -90.0,
 			pitch: 0.0,
+			yaw_goal: -90.0,
+			pitch_goal: 0.0,
 			speed: 2.5,
-			zoom: 45.0,
+			mode: CameraMode::Fly,
+			target: glm::Vec3::new(0.0, 0.0, 0.0),
+			distance: 10.0,
+			distance_goal: 10.0,
+			zoom_goal: None,
+			velocity: glm::Vec3::new(0.0, 0.0, 0.0),
+			eye_height: 1.7,
+			active: true,
 		}
 	}
 }
 
 impl Camera {
-	pub fn new(position: glm::Vec3) -> Self {
-		let mut camera = Self {
-			position,
-			..Default::default()
-		};
-
-		camera.update_camera_vectors();
-		camera
-	}
-
 	// Project the view forwards,
 	// self.front is essentially the point we're looking it.
 	pub fn get_view_matrix(&self) -> glm::Mat4 {
 		glm::look_at(&self.position, &(self.position + self.front), &self.up)
 	}
 
-	// Primitive movement for keyboards, (dumb)
-	pub fn do_move(&mut self, d: CameraDirection, dt: f32) {
-		let velocity = self.speed * dt;
-		match d {
-			CameraDirection::Forward => self.position += self.front * velocity,
-			CameraDirection::Backward => self.position -= self.front * velocity,
-			CameraDirection::Right => self.position += self.right * velocity,
-			CameraDirection::Left => self.position -= self.right * velocity,
+	// Switch modes without the view jumping, as far as the new mode allows
+	pub fn set_mode(&mut self, mode: CameraMode) {
+		if mode == self.mode {
+			return;
 		}
+		self.velocity = glm::Vec3::zeros();
+		match mode {
+			// Keep looking at whatever is in front, at the same distance as before
+			CameraMode::Orbit => self.target = self.position + self.front * self.distance,
+			CameraMode::Fly => {}
+			CameraMode::FirstPerson => self.position.y = self.eye_height,
+		}
+		self.mode = mode;
 	}
 
-	pub fn do_move_relative(&mut self, delta: glm::Vec3, dt: f32) {
-		self.position += self.front * delta.z + self.up * delta.y + self.right * delta.x;
-	}
-
-	// Rotate Yaw and Pitch.
+	// Rotate Yaw and Pitch, eventually.
 	pub fn do_rotate(&mut self, offset: glm::Vec2) {
-		self.yaw += offset.x;
-		self.pitch += offset.y;
+		self.yaw_goal += offset.x;
+		self.pitch_goal += offset.y;
 
-		if self.pitch >= 90.0 {
-			self.pitch = 89.9;
-		} else if self.pitch <= -90.0 {
-			self.pitch = -89.9;
+		if self.pitch_goal >= 90.0 {
+			self.pitch_goal = 89.9;
+		} else if self.pitch_goal <= -90.0 {
+			self.pitch_goal = -89.9;
 		}
+	}
 
+	// Move towards the target, by a fraction of the distance to it, eventually
+	pub fn do_dolly(&mut self, amount: f32) {
+		self.distance_goal = (self.distance_goal * (1.0 - amount)).max(0.1);
+	}
+
+	// Cover `blend` of the way left to the rotation and distance goals
+	pub fn ease(&mut self, blend: f32) {
+		self.yaw += (self.yaw_goal - self.yaw) * blend;
+		self.pitch += (self.pitch_goal - self.pitch) * blend;
+		self.distance += (self.distance_goal - self.distance) * blend;
 		self.update_camera_vectors();
 	}
 
+	// Slide the target and camera along the view plane
+	pub fn do_pan(&mut self, offset: glm::Vec2) {
+		let offset = self.right * offset.x + self.up * offset.y;
+		self.target += offset;
+		self.position += offset;
+	}
+
 	pub fn update_camera_vectors(&mut self) {
 		self.front = glm::vec3(
 			self.yaw.to_radians().cos() * self.pitch.to_radians().cos(),
@@ -96,4 +144,125 @@ impl Camera {
 		self.right = self.front.cross(&self.world_up).normalize();
 		self.up = self.right.cross(&self.front).normalize();
 	}
+
+	pub fn draw_ui(&mut self, ui: &imgui::Ui) {
+		use imgui::Condition;
+		imgui::Window::new(ui, im_str!("Camera"))
+			.size([250.0, 120.0], Condition::FirstUseEver)
+			.position([50.0, 500.0], Condition::FirstUseEver)
+			.build(|| {
+				let mut selected = self.mode;
+				for mode in CameraMode::ALL.iter() {
+					ui.radio_button(&im_str!("{}", mode.name()), &mut selected, *mode);
+				}
+				self.set_mode(selected);
+				ui.text("1: orbit, 2: fly, 3: first person");
+			});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+		assert!(glm::distance(&a, &b) < 1e-4, "{:?} != {:?}", a, b);
+	}
+
+	// Somewhere off the origin, looking a little down
+	fn camera(mode: CameraMode) -> Camera {
+		let mut camera = Camera {
+			position: glm::vec3(1.0, 5.0, 3.0),
+			pitch: -30.0,
+			pitch_goal: -30.0,
+			mode,
+			..Camera::default()
+		};
+		camera.update_camera_vectors();
+		camera
+	}
+
+	#[test]
+	fn pitch_stops_short_of_straight_up_and_down() {
+		let mut camera = camera(CameraMode::Fly);
+		camera.do_rotate(glm::vec2(0.0, 500.0));
+		assert_eq!(camera.pitch_goal, 89.9);
+		camera.do_rotate(glm::vec2(0.0, -1000.0));
+		assert_eq!(camera.pitch_goal, -89.9);
+		// Turning sideways has no limit
+		camera.do_rotate(glm::vec2(720.0, 0.0));
+		assert_eq!(camera.yaw_goal, 630.0);
+	}
+
+	#[test]
+	fn dollying_never_reaches_the_target() {
+		let mut camera = camera(CameraMode::Orbit);
+		camera.do_dolly(0.5);
+		assert_eq!(camera.distance_goal, 5.0);
+		camera.do_dolly(-1.0);
+		assert_eq!(camera.distance_goal, 10.0);
+		for _ in 0..100 {
+			camera.do_dolly(0.9);
+		}
+		assert_eq!(camera.distance_goal, 0.1);
+	}
+
+	#[test]
+	fn easing_converges_on_the_goals() {
+		let mut camera = camera(CameraMode::Orbit);
+		camera.do_rotate(glm::vec2(40.0, 20.0));
+		camera.do_dolly(0.5);
+		camera.ease(0.5);
+		assert_eq!(camera.yaw, -70.0);
+		assert_eq!(camera.pitch, -20.0);
+		assert_eq!(camera.distance, 7.5);
+		for _ in 0..50 {
+			camera.ease(0.5);
+		}
+		assert!((camera.yaw - camera.yaw_goal).abs() < 1e-4);
+		assert!((camera.pitch - camera.pitch_goal).abs() < 1e-4);
+		assert!((camera.distance - camera.distance_goal).abs() < 1e-4);
+		// Covering the whole way gets there at once
+		camera.do_rotate(glm::vec2(10.0, 0.0));
+		camera.ease(1.0);
+		assert_eq!(camera.yaw, camera.yaw_goal);
+	}
+
+	#[test]
+	fn orbiting_keeps_the_view() {
+		let mut camera = camera(CameraMode::Fly);
+		camera.velocity = glm::vec3(1.0, 0.0, 0.0);
+		let (position, front) = (camera.position, camera.front);
+		camera.set_mode(CameraMode::Orbit);
+		assert_eq!(camera.mode, CameraMode::Orbit);
+		assert_eq!(camera.velocity, glm::Vec3::zeros());
+		assert_close(camera.target, position + front * camera.distance);
+		// Where the camera system then puts the camera
+		assert_close(camera.target - camera.front * camera.distance, position);
+	}
+
+	#[test]
+	fn first_person_walks_at_eye_height() {
+		let mut camera = camera(CameraMode::Orbit);
+		let position = camera.position;
+		camera.set_mode(CameraMode::FirstPerson);
+		assert_eq!(
+			camera.position,
+			glm::vec3(position.x, camera.eye_height, position.z)
+		);
+		// And flying takes off from there
+		camera.set_mode(CameraMode::Fly);
+		assert_eq!(
+			camera.position,
+			glm::vec3(position.x, camera.eye_height, position.z)
+		);
+	}
+
+	#[test]
+	fn switching_to_the_same_mode_changes_nothing() {
+		let mut camera = camera(CameraMode::Orbit);
+		camera.target = glm::vec3(7.0, 8.0, 9.0);
+		camera.set_mode(CameraMode::Orbit);
+		assert_eq!(camera.target, glm::vec3(7.0, 8.0, 9.0));
+	}
 }
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+// Frames waiting to be saved, the main loop writes them out once they're drawn
+pub struct Capture {
+	// Save the next frame as a timestamped PNG in `screenshot_dir`
+	pub screenshot: bool,
+	pub screenshot_dir: PathBuf,
+	pub recording: Option<Recording>,
+}
+
+// Every frame is saved as a numbered PNG, and time advances by a fixed
+// step per frame rather than by how long the frame took.
+pub struct Recording {
+	pub dir: PathBuf,
+	pub timestep: f32,
+	pub frame: usize,
+}
+
+impl Default for Capture {
+	fn default() -> Self {
+		Self {
+			screenshot: false,
+			screenshot_dir: PathBuf::from("screenshots"),
+			recording: None,
+		}
+	}
+}
+
+impl Capture {
+	pub fn take_screenshot(&mut self) {
+		self.screenshot = true;
+	}
+
+	pub fn start_recording<P: Into<PathBuf>>(&mut self, dir: P, timestep: f32) {
+		self.recording = Some(Recording {
+			dir: dir.into(),
+			timestep,
+			frame: 0,
+		});
+	}
+
+	pub fn stop_recording(&mut self) {
+		self.recording = None;
+	}
+
+	// The time between frames as the simulation sees it
+	pub fn timestep(&self, measured: f32) -> f32 {
+		match &self.recording {
+			Some(recording) => recording.timestep,
+			None => measured,
+		}
+	}
+}
@@ -4,11 +4,16 @@ use std::collections::HashSet;
 #[derive(Default)]
 pub struct Keystate {
 	pub keys: HashSet<glutin::VirtualKeyCode>,
+	// Keys that went down since the last call to `clear_pressed`
+	pub pressed: HashSet<glutin::VirtualKeyCode>,
 }
 
 impl Keystate {
 	pub fn set_key_down(&mut self, key: glutin::VirtualKeyCode) {
-		self.keys.insert(key);
+		// Held keys keep sending presses, those don't count
+		if self.keys.insert(key) {
+			self.pressed.insert(key);
+		}
 	}
 
 	pub fn set_key_up(&mut self, key: glutin::VirtualKeyCode) {
@@ -19,9 +24,17 @@ impl Keystate {
 		self.keys.contains(&key)
 	}
 
+	pub fn was_key_pressed(&self, key: glutin::VirtualKeyCode) -> bool {
+		self.pressed.contains(&key)
+	}
+
+	pub fn clear_pressed(&mut self) {
+		self.pressed.clear();
+	}
+
 	pub fn handle_event(&mut self, event: &glutin::Event) {
-		if let glutin::Event::WindowEvent { event, .. } = event {
-			match event {
+		if let glutin::Event::WindowEvent {
+			event:
 				glutin::WindowEvent::KeyboardInput {
 					input:
 						glutin::KeyboardInput {
@@ -30,11 +43,13 @@ impl Keystate {
 							..
 						},
 					..
-				} => match state {
-					ElementState::Pressed => self.set_key_down(*kc),
-					ElementState::Released => self.set_key_up(*kc),
 				},
-				_ => {}
+			..
+		} = event
+		{
+			match state {
+				ElementState::Pressed => self.set_key_down(*kc),
+				ElementState::Released => self.set_key_up(*kc),
 			}
 		}
 	}
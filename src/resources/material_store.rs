@@ -0,0 +1,103 @@
+use crate::resources::asset_store::{AssetStore, Handle};
+use crate::resources::texture_map::TextureHandle;
+
+// Surface properties shared by every entity drawn with the same material
+#[derive(Debug)]
+pub struct Material {
+	pub ambient: glm::Vec3,
+	pub diffuse: glm::Vec3,
+	pub specular: glm::Vec3,
+	pub shininess: f32,
+	pub diffuse_map: Option<TextureHandle>,
+	pub specular_map: Option<TextureHandle>,
+	pub bump_map: Option<TextureHandle>,
+}
+
+impl Default for Material {
+	fn default() -> Self {
+		Self {
+			ambient: glm::vec3(0.1, 0.1, 0.1),
+			diffuse: glm::vec3(0.5, 0.5, 0.5),
+			specular: glm::vec3(0.8, 0.8, 0.8),
+			shininess: 32.0,
+			diffuse_map: None,
+			specular_map: None,
+			bump_map: None,
+		}
+	}
+}
+
+impl Material {
+	// The maps the Phong shader samples besides the diffuse one, by the name it knows them by
+	pub fn maps(&self) -> [(&'static str, Option<&TextureHandle>); 2] {
+		[
+			("specular_map", self.specular_map.as_ref()),
+			("bump_map", self.bump_map.as_ref()),
+		]
+	}
+}
+
+pub type MaterialHandle = Handle<Material>;
+pub type MaterialStore = AssetStore<Material>;
+
+// A metallic-roughness material, drawn with the Cook-Torrance shader instead of the Phong one.
+// Factors are multiplied with their maps where present, like in glTF.
+#[derive(Debug, Clone)]
+pub struct PbrMaterial {
+	pub base_color: glm::Vec4,
+	pub base_color_map: Option<TextureHandle>,
+	pub metallic: f32,
+	// Metalness is read from the blue channel
+	pub metallic_map: Option<TextureHandle>,
+	pub roughness: f32,
+	// Roughness is read from the green channel
+	pub roughness_map: Option<TextureHandle>,
+	pub normal_scale: f32,
+	pub normal_map: Option<TextureHandle>,
+	pub occlusion_strength: f32,
+	pub occlusion_map: Option<TextureHandle>,
+	pub emissive: glm::Vec3,
+	pub emissive_map: Option<TextureHandle>,
+}
+
+impl Default for PbrMaterial {
+	fn default() -> Self {
+		Self {
+			base_color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+			base_color_map: None,
+			metallic: 1.0,
+			metallic_map: None,
+			roughness: 1.0,
+			roughness_map: None,
+			normal_scale: 1.0,
+			normal_map: None,
+			occlusion_strength: 1.0,
+			occlusion_map: None,
+			emissive: glm::Vec3::zeros(),
+			emissive_map: None,
+		}
+	}
+}
+
+impl PbrMaterial {
+	// The maps holding colors, which are stored in sRGB, by the name the PBR shader knows them by
+	pub fn color_maps(&self) -> [(&'static str, Option<&TextureHandle>); 2] {
+		[
+			("base_color_map", self.base_color_map.as_ref()),
+			("emissive_map", self.emissive_map.as_ref()),
+		]
+	}
+
+	// The maps holding other data, which is stored linearly
+	pub fn data_maps(&self) -> [(&'static str, Option<&TextureHandle>); 4] {
+		[
+			("metallic_map", self.metallic_map.as_ref()),
+			("roughness_map", self.roughness_map.as_ref()),
+			("normal_map", self.normal_map.as_ref()),
+			("occlusion_map", self.occlusion_map.as_ref()),
+		]
+	}
+}
+
+pub type PbrMaterialHandle = Handle<PbrMaterial>;
+pub type PbrMaterialStore = AssetStore<PbrMaterial>;
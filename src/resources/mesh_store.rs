@@ -0,0 +1,44 @@
+use crate::bounds::Aabb;
+use crate::object::VertexArray;
+use crate::resources::asset_store::{AssetStore, Handle};
+
+#[derive(Debug)]
+pub enum Indices {
+	U16(Vec<u16>),
+	U32(Vec<u32>),
+}
+
+// Geometry as it will be uploaded to the GPU
+#[derive(Debug)]
+pub struct Mesh {
+	pub vertices: Vec<VertexArray>,
+	pub indices: Indices,
+	// Around every vertex, in model space
+	pub bounds: Aabb,
+}
+
+pub type MeshHandle = Handle<Mesh>;
+pub type MeshStore = AssetStore<Mesh>;
+
+impl Mesh {
+	// The corners of every triangle
+	pub fn triangles(&self) -> Box<dyn Iterator<Item = [glm::Vec3; 3]> + '_> {
+		let position = move |i: usize| glm::make_vec3(&self.vertices[i].position);
+		match &self.indices {
+			Indices::U16(indices) => Box::new(indices.chunks_exact(3).map(move |triangle| {
+				[
+					position(triangle[0] as usize),
+					position(triangle[1] as usize),
+					position(triangle[2] as usize),
+				]
+			})),
+			Indices::U32(indices) => Box::new(indices.chunks_exact(3).map(move |triangle| {
+				[
+					position(triangle[0] as usize),
+					position(triangle[1] as usize),
+					position(triangle[2] as usize),
+				]
+			})),
+		}
+	}
+}
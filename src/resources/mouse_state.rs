@@ -1,11 +1,20 @@
-use glutin::ElementState;
+use glutin::{ElementState, MouseScrollDelta};
 use std::collections::HashSet;
 
+// Touchpads scroll by pixels, which are counted as lines of this many
+const PIXELS_PER_LINE: f32 = 20.0;
+
 pub struct MouseState {
 	pub position: glm::Vec2,
 	pub previous_position: glm::Vec2,
 	pub delta: glm::Vec2,
+	// Lines scrolled since the last call to `update_delta`, positive is away from the user
+	pub scroll: f32,
 	pub buttons: HashSet<glutin::MouseButton>,
+	// Buttons that went down since the last call to `clear_pressed`
+	pub pressed: HashSet<glutin::MouseButton>,
+	// Whether the overlay wants the mouse to itself, as it's hovering a window
+	pub over_ui: bool,
 }
 
 impl Default for MouseState {
@@ -14,14 +23,19 @@ impl Default for MouseState {
 			position: glm::vec2(0.0, 0.0),
 			previous_position: glm::vec2(0.0, 0.0),
 			delta: glm::vec2(0.0, 0.0),
+			scroll: 0.0,
 			buttons: HashSet::new(),
+			pressed: HashSet::new(),
+			over_ui: false,
 		}
 	}
 }
 
 impl MouseState {
 	pub fn set_button_down(&mut self, button: glutin::MouseButton) {
-		self.buttons.insert(button);
+		if self.buttons.insert(button) {
+			self.pressed.insert(button);
+		}
 	}
 
 	pub fn set_button_up(&mut self, button: glutin::MouseButton) {
@@ -32,9 +46,18 @@ impl MouseState {
 		self.buttons.contains(&button)
 	}
 
+	pub fn was_button_pressed(&self, button: glutin::MouseButton) -> bool {
+		self.pressed.contains(&button)
+	}
+
+	pub fn clear_pressed(&mut self) {
+		self.pressed.clear();
+	}
+
 	pub fn update_delta(&mut self) {
 		self.delta = self.position - self.previous_position;
 		self.previous_position = self.position;
+		self.scroll = 0.0;
 	}
 
 	pub fn handle_event(&mut self, event: &glutin::Event) {
@@ -44,6 +67,12 @@ impl MouseState {
 					ElementState::Pressed => self.set_button_down(*button),
 					ElementState::Released => self.set_button_up(*button),
 				},
+				glutin::WindowEvent::MouseWheel { delta, .. } => match delta {
+					MouseScrollDelta::LineDelta(_, y) => self.scroll += y,
+					MouseScrollDelta::PixelDelta(position) => {
+						self.scroll += position.y as f32 / PIXELS_PER_LINE
+					}
+				},
 				glutin::WindowEvent::CursorMoved { position, .. } => {
 					self.position = glm::vec2(position.x as f32, position.y as f32);
 				}
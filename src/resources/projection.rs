@@ -1,12 +1,257 @@
-pub struct Projection(pub glm::Mat4);
+// How a camera maps what it sees onto the screen.
+// The main camera uses the resource, camera entities have one of their own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+	Perspective {
+		// Vertical field of view in radians
+		fov: f32,
+		near: f32,
+		// Nothing is too far away to be seen without one
+		far: Option<f32>,
+		// Depth goes from 1 at the near plane down to 0 at the far plane,
+		// which together with a floating point depth buffer keeps far away
+		// things from fighting over the same depth.
+		reverse_z: bool,
+	},
+	// Things don't get smaller further away, for 2D and CAD-style views
+	Orthographic {
+		// How much of the world fits from the bottom to the top of the view
+		height: f32,
+		near: f32,
+		far: f32,
+	},
+}
 
 impl Default for Projection {
 	fn default() -> Self {
-		Self(glm::mat4(
-			1.0, 0.0, 0.0, 0.0, //
-			0.0, 1.0, 0.0, 0.0, //
-			0.0, 0.0, 1.0, 0.0, //
-			0.0, 0.0, 0.0, 1.0, //
-		))
+		Self::perspective(45f32.to_radians())
+	}
+}
+
+impl Projection {
+	pub fn perspective(fov: f32) -> Self {
+		Projection::Perspective {
+			fov,
+			near: 0.1,
+			far: Some(10000.0),
+			reverse_z: false,
+		}
+	}
+
+	pub fn orthographic(height: f32) -> Self {
+		Projection::Orthographic {
+			height,
+			near: 0.1,
+			far: 10000.0,
+		}
+	}
+
+	pub fn reverse_z(&self) -> bool {
+		match *self {
+			Projection::Perspective { reverse_z, .. } => reverse_z,
+			Projection::Orthographic { .. } => false,
+		}
+	}
+
+	// The same projection with depth the usual way round
+	pub fn regular(&self) -> Self {
+		match *self {
+			Projection::Perspective { fov, near, far, .. } => Projection::Perspective {
+				fov,
+				near,
+				far,
+				reverse_z: false,
+			},
+			orthographic => orthographic,
+		}
+	}
+
+	// The depth at the near and far planes after dividing by w. Reversed depth
+	// goes from 0 to 1 like the depth buffer, as glClipControl is used to
+	// keep it from being mapped from -1..1 there.
+	pub fn depth_range(&self) -> (f32, f32) {
+		if self.reverse_z() {
+			(1.0, 0.0)
+		} else {
+			(-1.0, 1.0)
+		}
+	}
+
+	// What the depth buffer is cleared to, the depth of the far plane
+	pub fn clear_depth(&self) -> f32 {
+		if self.reverse_z() {
+			0.0
+		} else {
+			1.0
+		}
+	}
+
+	pub fn matrix(&self, aspect: f32) -> glm::Mat4 {
+		match *self {
+			Projection::Perspective {
+				fov,
+				near,
+				far,
+				reverse_z,
+			} => {
+				let focal = 1.0 / (fov / 2.0).tan();
+				let mut matrix = glm::Mat4::zeros();
+				matrix[(0, 0)] = focal / aspect;
+				matrix[(1, 1)] = focal;
+				matrix[(3, 2)] = -1.0;
+				// Depth is the third row over -z, the infinite ones are
+				// what the others tend to as the far plane moves away.
+				let (scale, offset) = match (far, reverse_z) {
+					(Some(far), false) => {
+						((far + near) / (near - far), 2.0 * far * near / (near - far))
+					}
+					(None, false) => (-1.0, -2.0 * near),
+					(Some(far), true) => (near / (far - near), far * near / (far - near)),
+					(None, true) => (0.0, near),
+				};
+				matrix[(2, 2)] = scale;
+				matrix[(2, 3)] = offset;
+				matrix
+			}
+			Projection::Orthographic { height, near, far } => {
+				let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+				glm::ortho(
+					-half_width,
+					half_width,
+					-half_height,
+					half_height,
+					near,
+					far,
+				)
+			}
+		}
+	}
+
+	// The widgets for changing the projection, to go in a window
+	pub fn edit(&mut self, ui: &imgui::Ui) {
+		let mut orthographic = match self {
+			Projection::Perspective { .. } => false,
+			Projection::Orthographic { .. } => true,
+		};
+		ui.radio_button(im_str!("Perspective"), &mut orthographic, false);
+		ui.same_line(0.0);
+		ui.radio_button(im_str!("Orthographic"), &mut orthographic, true);
+		match (orthographic, &*self) {
+			(false, Projection::Orthographic { .. }) => *self = Self::default(),
+			(true, Projection::Perspective { .. }) => *self = Self::orthographic(100.0),
+			_ => {}
+		}
+
+		match self {
+			Projection::Perspective {
+				fov,
+				near,
+				far,
+				reverse_z,
+			} => {
+				let mut degrees = fov.to_degrees();
+				if ui
+					.slider_float(im_str!("Field of view"), &mut degrees, 10.0, 150.0)
+					.build()
+				{
+					*fov = degrees.to_radians();
+				}
+				ui.input_float(im_str!("Near"), near).build();
+				*near = near.max(0.001);
+				let mut infinite = far.is_none();
+				if ui.checkbox(im_str!("Infinite far plane"), &mut infinite) {
+					*far = if infinite { None } else { Some(10000.0) };
+				}
+				if let Some(far) = far {
+					ui.input_float(im_str!("Far"), far).build();
+					*far = far.max(*near + 0.001);
+				}
+				ui.checkbox(im_str!("Reverse Z"), reverse_z);
+			}
+			Projection::Orthographic { height, near, far } => {
+				ui.slider_float(im_str!("Height"), height, 1.0, 1000.0)
+					.build();
+				ui.input_float(im_str!("Near"), near).build();
+				ui.input_float(im_str!("Far"), far).build();
+				*far = far.max(*near + 0.001);
+			}
+		}
+	}
+
+	pub fn draw_ui(&mut self, ui: &imgui::Ui) {
+		use imgui::Condition;
+		imgui::Window::new(ui, im_str!("Projection"))
+			.size([300.0, 160.0], Condition::FirstUseEver)
+			.position([50.0, 630.0], Condition::FirstUseEver)
+			.build(|| self.edit(ui));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn perspective(far: Option<f32>, reverse_z: bool) -> Projection {
+		Projection::Perspective {
+			fov: 90f32.to_radians(),
+			near: 1.0,
+			far,
+			reverse_z,
+		}
+	}
+
+	// The depth of a point at `distance` in front of the camera
+	fn depth(projection: &Projection, distance: f32) -> f32 {
+		let clip = projection.matrix(1.0) * glm::vec4(0.0, 0.0, -distance, 1.0);
+		clip.z / clip.w
+	}
+
+	fn assert_close(a: f32, b: f32) {
+		assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+	}
+
+	#[test]
+	fn finite_planes_are_at_the_ends_of_the_depth_range() {
+		for &reverse_z in &[false, true] {
+			let projection = perspective(Some(100.0), reverse_z);
+			let (near, far) = projection.depth_range();
+			assert_close(depth(&projection, 1.0), near);
+			assert_close(depth(&projection, 100.0), far);
+			assert_eq!(projection.clear_depth(), far);
+		}
+		// The regular one is what glm builds
+		assert_eq!(
+			perspective(Some(100.0), false).matrix(1.5),
+			glm::perspective(1.5, 90f32.to_radians(), 1.0, 100.0)
+		);
+	}
+
+	#[test]
+	fn infinite_far_planes_are_never_reached() {
+		for &reverse_z in &[false, true] {
+			let projection = perspective(None, reverse_z);
+			let (near, far) = projection.depth_range();
+			assert_close(depth(&projection, 1.0), near);
+			assert!((depth(&projection, 1.0e6) - far).abs() < 1e-5);
+			assert!(depth(&projection, 1.0e6) != far);
+		}
+	}
+
+	#[test]
+	fn reversed_depth_gets_smaller_further_away() {
+		let projection = perspective(None, true);
+		let depths: Vec<f32> = [1.0, 2.0, 10.0, 1000.0]
+			.iter()
+			.map(|&distance| depth(&projection, distance))
+			.collect();
+		assert_eq!(depths[1], 0.5);
+		assert!(
+			depths.windows(2).all(|pair| pair[0] > pair[1]),
+			"{:?}",
+			depths
+		);
+		// Turned the usual way round, it's the regular infinite projection
+		assert_eq!(projection.regular(), perspective(None, false));
+		assert_eq!(Projection::orthographic(10.0).depth_range(), (-1.0, 1.0));
 	}
 }
@@ -0,0 +1,7 @@
+// What the renderer did last frame, shown in the profiler
+#[derive(Default, Debug, Clone, Copy)]
+pub struct RenderStats {
+	pub drawn: usize,
+	// Left out for being outside the camera's view
+	pub culled: usize,
+}
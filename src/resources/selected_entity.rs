@@ -0,0 +1,5 @@
+use specs::Entity;
+
+// The entity last clicked on in the viewport, if any
+#[derive(Default)]
+pub struct SelectedEntity(pub Option<Entity>);
@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+
+use crate::resources::asset_store::{AssetStore, Handle};
+
+// The sources of a shader program, compiled once it's first used
+#[derive(Debug)]
+pub struct ShaderSource {
+	pub vertex_path: String,
+	pub fragment_path: String,
+	pub vertex: String,
+	pub fragment: String,
+	// Files pulled in by `#include` from either stage
+	pub includes: Vec<String>,
+}
+
+fn read(path: &str) -> Result<String, String> {
+	fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))
+}
+
+// Paste in the files named by `#include "file"` lines, relative to the file
+// including them. Every file is only included once, which also stops cycles.
+fn expand_includes(path: &str, includes: &mut Vec<String>) -> Result<String, String> {
+	let source = read(path)?;
+	let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+	let mut expanded = String::new();
+	for line in source.lines() {
+		match line.trim().strip_prefix("#include") {
+			Some(name) => {
+				let include = dir
+					.join(name.trim().trim_matches('"'))
+					.to_string_lossy()
+					.into_owned();
+				if !includes.contains(&include) {
+					includes.push(include.clone());
+					expanded.push_str(&expand_includes(&include, includes)?);
+				}
+			}
+			None => {
+				expanded.push_str(line);
+				expanded.push('\n');
+			}
+		}
+	}
+	Ok(expanded)
+}
+
+impl ShaderSource {
+	pub fn load_from_files(vertex_path: &str, fragment_path: &str) -> Result<Self, String> {
+		// Each stage is compiled on its own, so each gets its own copy of an include
+		let mut includes = Vec::new();
+		let vertex = expand_includes(vertex_path, &mut includes)?;
+		let mut fragment_includes = Vec::new();
+		let fragment = expand_includes(fragment_path, &mut fragment_includes)?;
+		for include in fragment_includes {
+			if !includes.contains(&include) {
+				includes.push(include);
+			}
+		}
+		Ok(Self {
+			vertex_path: vertex_path.to_string(),
+			fragment_path: fragment_path.to_string(),
+			vertex,
+			fragment,
+			includes,
+		})
+	}
+
+	// Every file the program is built from
+	pub fn files(&self) -> impl Iterator<Item = &str> {
+		vec![self.vertex_path.as_str(), self.fragment_path.as_str()]
+			.into_iter()
+			.chain(self.includes.iter().map(String::as_str))
+	}
+}
+
+pub type ShaderHandle = Handle<ShaderSource>;
+pub type ShaderStore = AssetStore<ShaderSource>;
+
+impl ShaderStore {
+	pub fn load_from_files(
+		&mut self,
+		vertex_path: &str,
+		fragment_path: &str,
+	) -> Result<ShaderHandle, String> {
+		let key = format!("{}+{}", vertex_path, fragment_path);
+		self.load(&key, |_| {
+			ShaderSource::load_from_files(vertex_path, fragment_path)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lit_shaders_share_their_includes() {
+		for fragment in &["./fragment.fs", "./pbr.fs"] {
+			let source = ShaderSource::load_from_files("./vertex.vs", fragment).unwrap();
+			assert_eq!(
+				source.includes,
+				vec!["./lights.glsl".to_string(), "./normal_map.glsl".to_string()]
+			);
+			assert!(!source.fragment.contains("#include"));
+			assert_eq!(source.fragment.matches("vec3 surface_normal(").count(), 1);
+		}
+	}
+}
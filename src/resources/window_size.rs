@@ -0,0 +1,8 @@
+// The size of the window in the same units as `MouseState::position`
+pub struct WindowSize(pub glm::Vec2);
+
+impl Default for WindowSize {
+	fn default() -> Self {
+		Self(glm::vec2(1.0, 1.0))
+	}
+}
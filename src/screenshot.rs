@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use glium::backend::Facade;
+use glium::uniforms::MagnifySamplerFilter;
+use glium::Surface;
+
+use crate::render_target::Offscreen;
+use crate::resources::capture::Capture;
+
+// Copy whatever has been drawn to a surface so far
+pub fn read_surface<F: Facade, S: Surface>(
+	facade: &F,
+	surface: &S,
+) -> Result<image::RgbaImage, String> {
+	let (width, height) = surface.get_dimensions();
+	let offscreen = Offscreen::new(facade, width, height)?;
+	surface.fill(
+		&offscreen.framebuffer(facade)?,
+		MagnifySamplerFilter::Nearest,
+	);
+	offscreen.read_image()
+}
+
+// Numbered so that tools like ffmpeg pick the frames up in order
+pub fn frame_path(dir: &Path, frame: usize) -> PathBuf {
+	dir.join(format!("frame{:04}.png", frame))
+}
+
+pub fn save(image: &image::RgbaImage, path: &Path) -> Result<(), String> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)
+			.map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+	}
+	image
+		.save(path)
+		.map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+// Save the frame that was just drawn, if `capture` asks for it
+pub fn capture_frame<F: Facade, S: Surface>(
+	facade: &F,
+	surface: &S,
+	capture: &mut Capture,
+) -> Result<(), String> {
+	if !capture.screenshot && capture.recording.is_none() {
+		return Ok(());
+	}
+	let image = read_surface(facade, surface)?;
+
+	if capture.screenshot {
+		capture.screenshot = false;
+		let taken = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|time| time.as_millis())
+			.unwrap_or(0);
+		let path = capture
+			.screenshot_dir
+			.join(format!("screenshot-{}.png", taken));
+		save(&image, &path)?;
+		println!("Saved screenshot to {}", path.display());
+	}
+	if let Some(recording) = &mut capture.recording {
+		let path = frame_path(&recording.dir, recording.frame);
+		recording.frame += 1;
+		save(&image, &path)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use glium::backend::Facade;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::implement_vertex;
+use glium::index::{NoIndices, PrimitiveType};
+use glium::texture::{CubeLayer, DepthCubemap, DepthTexture2d, Texture2d};
+use glium::uniforms::{
+	MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior, SamplerWrapFunction,
+};
+use glium::Surface;
+use specs::prelude::*;
+
+use crate::bounds::{Aabb, Frustum};
+use crate::components::{light::Light, name::NameComponent, shadow::ShadowComponent};
+use crate::gpu_store::GpuMesh;
+use crate::resources::bvh::Bvh;
+use crate::texture_views::TextureViews;
+use crate::uniforms::UniformList;
+
+// Where a light stores the depth of the scene as seen from it.
+// Point lights look in every direction, so they get a cube map.
+pub enum ShadowMap {
+	Depth(DepthTexture2d),
+	Cube(DepthCubemap),
+}
+
+impl ShadowMap {
+	fn new<F: Facade>(facade: &F, cube: bool, resolution: u32) -> Result<Self, String> {
+		let map = if cube {
+			DepthCubemap::empty(facade, resolution).map(ShadowMap::Cube)
+		} else {
+			DepthTexture2d::empty(facade, resolution, resolution).map(ShadowMap::Depth)
+		};
+		map.map_err(|e| format!("Could not create shadow map: {:?}", e))
+	}
+
+	fn matches(&self, cube: bool, resolution: u32) -> bool {
+		match self {
+			ShadowMap::Depth(map) => !cube && map.width() == resolution,
+			ShadowMap::Cube(map) => cube && map.dimensions() == resolution,
+		}
+	}
+}
+
+// The order the faces of a point light's view are in
+const CUBE_LAYERS: [CubeLayer; 6] = [
+	CubeLayer::PositiveX,
+	CubeLayer::NegativeX,
+	CubeLayer::PositiveY,
+	CubeLayer::NegativeY,
+	CubeLayer::PositiveZ,
+	CubeLayer::NegativeZ,
+];
+
+// Any up vector that isn't parallel to `direction`
+fn up_for(direction: &glm::Vec3) -> glm::Vec3 {
+	if direction.y.abs() > 0.99 {
+		glm::vec3(0.0, 0.0, 1.0)
+	} else {
+		glm::vec3(0.0, 1.0, 0.0)
+	}
+}
+
+// How a light sees the scene while rendering its shadow map
+pub enum LightView {
+	// Projection times view of a directional or spot light
+	Single(glm::Mat4),
+	// One matrix per face, in the order of `CUBE_LAYERS`
+	Cube {
+		position: glm::Vec3,
+		faces: Vec<glm::Mat4>,
+		far: f32,
+	},
+}
+
+impl LightView {
+	// Directional lights have no position, their shadows are centered on `center` instead
+	pub fn new(
+		light: &Light,
+		position: &glm::Vec3,
+		shadow: &ShadowComponent,
+		center: &glm::Vec3,
+	) -> Self {
+		match *light {
+			Light::DirectionalLight { direction, .. } => {
+				let direction = direction.normalize();
+				let eye = center - direction * (shadow.far * 0.5);
+				let view = glm::look_at(&eye, center, &up_for(&direction));
+				let extent = shadow.extent;
+				let projection =
+					glm::ortho(-extent, extent, -extent, extent, shadow.near, shadow.far);
+				LightView::Single(projection * view)
+			}
+			Light::SpotLight {
+				direction,
+				outer_cutoff,
+				..
+			} => {
+				let direction = direction.normalize();
+				// Wide enough to fit the outer cone
+				let fov = (outer_cutoff.clamp(-1.0, 1.0).acos() * 2.0)
+					.clamp(1f32.to_radians(), 179f32.to_radians());
+				let view = glm::look_at(position, &(position + direction), &up_for(&direction));
+				LightView::Single(glm::perspective(1.0, fov, shadow.near, shadow.far) * view)
+			}
+			Light::PointLight { .. } => {
+				let projection = glm::perspective(1.0, 90f32.to_radians(), shadow.near, shadow.far);
+				let faces = [
+					(glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0)),
+					(glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0)),
+					(glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)),
+					(glm::vec3(0.0, -1.0, 0.0), glm::vec3(0.0, 0.0, -1.0)),
+					(glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, -1.0, 0.0)),
+					(glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, -1.0, 0.0)),
+				]
+				.iter()
+				.map(|(direction, up)| {
+					projection * glm::look_at(position, &(position + direction), up)
+				})
+				.collect();
+				LightView::Cube {
+					position: *position,
+					faces,
+					far: shadow.far,
+				}
+			}
+		}
+	}
+
+	pub fn is_cube(&self) -> bool {
+		match self {
+			LightView::Single(_) => false,
+			LightView::Cube { .. } => true,
+		}
+	}
+
+	// The ids of the entities that can show up in the shadow map
+	pub fn casters(&self, bvh: &Bvh) -> BitSet {
+		match self {
+			LightView::Single(matrix) => bvh.in_frustum(&Frustum::from_matrix(matrix)),
+			LightView::Cube { position, far, .. } => {
+				let reach = glm::vec3(*far, *far, *far);
+				let range = Aabb {
+					min: position - reach,
+					max: position + reach,
+				};
+				bvh.overlapping(&range)
+					.iter()
+					.map(|entity| entity.id())
+					.collect()
+			}
+		}
+	}
+}
+
+// Shadow maps are compared against depths directly, blending between them would be wrong
+pub fn depth_sampler() -> SamplerBehavior {
+	SamplerBehavior {
+		wrap_function: (
+			SamplerWrapFunction::Clamp,
+			SamplerWrapFunction::Clamp,
+			SamplerWrapFunction::Clamp,
+		),
+		minify_filter: MinifySamplerFilter::Nearest,
+		magnify_filter: MagnifySamplerFilter::Nearest,
+		..Default::default()
+	}
+}
+
+#[derive(Copy, Clone)]
+struct QuadVertex {
+	position: [f32; 2],
+}
+
+implement_vertex!(QuadVertex, position);
+
+// Shadow maps shown in the overlay, the renderer only draws them while `enabled`
+#[derive(Default)]
+pub struct ShadowDebug {
+	pub enabled: bool,
+	pub views: TextureViews,
+}
+
+// The shadow maps of every light that casts shadows, kept between frames
+pub struct ShadowMaps {
+	maps: HashMap<Entity, ShadowMap>,
+	debug_views: HashMap<Entity, Rc<Texture2d>>,
+	// Every shadow sampler needs a texture bound, even when no light uses it
+	pub empty_map: DepthTexture2d,
+	pub empty_cube_map: DepthCubemap,
+	quad: glium::VertexBuffer<QuadVertex>,
+}
+
+impl ShadowMaps {
+	pub fn new<F: Facade>(facade: &F) -> Result<Self, String> {
+		let error = |e| format!("Could not create shadow map: {:?}", e);
+		let quad = glium::VertexBuffer::new(
+			facade,
+			&[
+				QuadVertex {
+					position: [-1.0, -1.0],
+				},
+				QuadVertex {
+					position: [1.0, -1.0],
+				},
+				QuadVertex {
+					position: [-1.0, 1.0],
+				},
+				QuadVertex {
+					position: [1.0, 1.0],
+				},
+			],
+		)
+		.map_err(|e| format!("Could not upload quad: {:?}", e))?;
+		Ok(Self {
+			maps: HashMap::new(),
+			debug_views: HashMap::new(),
+			empty_map: DepthTexture2d::empty(facade, 1, 1).map_err(error)?,
+			empty_cube_map: DepthCubemap::empty(facade, 1).map_err(error)?,
+			quad,
+		})
+	}
+
+	pub fn get(&self, entity: Entity) -> Option<&ShadowMap> {
+		self.maps.get(&entity)
+	}
+
+	// Forget the maps of lights that stopped casting shadows
+	pub fn retain(&mut self, casters: &[Entity]) {
+		self.maps.retain(|entity, _| casters.contains(entity));
+		self.debug_views
+			.retain(|entity, _| casters.contains(entity));
+	}
+
+	// Render the depth of the scene as seen from a light into its shadow map,
+	// which is created again whenever the light's kind or resolution changes.
+	pub fn render<F: Facade>(
+		&mut self,
+		facade: &F,
+		entity: Entity,
+		view: &LightView,
+		resolution: u32,
+		program: &glium::Program,
+		scene: &[(glm::Mat4, &GpuMesh)],
+	) -> Result<(), String> {
+		let cube = view.is_cube();
+		let resolution = resolution.max(1);
+		if !self
+			.maps
+			.get(&entity)
+			.is_some_and(|map| map.matches(cube, resolution))
+		{
+			self.maps
+				.insert(entity, ShadowMap::new(facade, cube, resolution)?);
+		}
+
+		let error = |e| format!("Could not render shadow map: {:?}", e);
+		match (&self.maps[&entity], view) {
+			(ShadowMap::Depth(map), LightView::Single(light_space)) => {
+				let mut target = SimpleFrameBuffer::depth_only(facade, map).map_err(error)?;
+				target.clear_depth(1.0);
+				draw_depth(&mut target, program, scene, light_space, None)
+			}
+			(
+				ShadowMap::Cube(map),
+				LightView::Cube {
+					position,
+					faces,
+					far,
+				},
+			) => {
+				for (layer, light_space) in CUBE_LAYERS.iter().zip(faces) {
+					let image = map.main_level().image(*layer);
+					let mut target = SimpleFrameBuffer::depth_only(facade, image).map_err(error)?;
+					target.clear_depth(1.0);
+					draw_depth(
+						&mut target,
+						program,
+						scene,
+						light_space,
+						Some((position, *far)),
+					)?;
+				}
+				Ok(())
+			}
+			_ => Err("Shadow map doesn't match its light".to_string()),
+		}
+	}
+
+	// Draw a light's shadow map into a texture the overlay can show
+	pub fn debug_view<F: Facade>(
+		&mut self,
+		facade: &F,
+		entity: Entity,
+		program: &glium::Program,
+	) -> Result<Rc<Texture2d>, String> {
+		let map = self
+			.maps
+			.get(&entity)
+			.ok_or_else(|| "Light has no shadow map".to_string())?;
+		// Cube maps are shown as a strip of their six faces
+		let (width, height, cube) = match map {
+			ShadowMap::Depth(_) => (256, 256, 0),
+			ShadowMap::Cube(_) => (768, 128, 1),
+		};
+		let texture = match self.debug_views.get(&entity) {
+			Some(texture) if texture.dimensions() == (width, height) => texture.clone(),
+			_ => {
+				let texture = Rc::new(
+					Texture2d::empty(facade, width, height)
+						.map_err(|e| format!("Could not create texture: {:?}", e))?,
+				);
+				self.debug_views.insert(entity, texture.clone());
+				texture
+			}
+		};
+
+		let mut uniforms = UniformList::new();
+		match map {
+			ShadowMap::Depth(map) => {
+				uniforms.depth_texture("depth_map", map, depth_sampler());
+				uniforms.depth_cubemap("depth_cube", &self.empty_cube_map, depth_sampler());
+			}
+			ShadowMap::Cube(map) => {
+				uniforms.depth_texture("depth_map", &self.empty_map, depth_sampler());
+				uniforms.depth_cubemap("depth_cube", map, depth_sampler());
+			}
+		}
+		uniforms.int("cube", cube);
+
+		let mut target = SimpleFrameBuffer::new(facade, &*texture)
+			.map_err(|e| format!("Could not render shadow map: {:?}", e))?;
+		target
+			.draw(
+				&self.quad,
+				NoIndices(PrimitiveType::TriangleStrip),
+				program,
+				&uniforms,
+				&Default::default(),
+			)
+			.map_err(|e| format!("Could not draw shadow map: {:?}", e))?;
+		Ok(texture)
+	}
+}
+
+// Point lights store the distance to the light rather than the depth
+fn draw_depth<S: Surface>(
+	target: &mut S,
+	program: &glium::Program,
+	scene: &[(glm::Mat4, &GpuMesh)],
+	light_space: &glm::Mat4,
+	point: Option<(&glm::Vec3, f32)>,
+) -> Result<(), String> {
+	let params = glium::DrawParameters {
+		depth: glium::Depth {
+			test: glium::draw_parameters::DepthTest::IfLess,
+			write: true,
+			..Default::default()
+		},
+		..Default::default()
+	};
+	for (model, mesh) in scene {
+		let mut uniforms = UniformList::new();
+		uniforms.mat4("light_space", light_space);
+		uniforms.mat4("model", model);
+		match point {
+			Some((position, far)) => {
+				uniforms.int("linear_depth", 1);
+				uniforms.vec3("light_pos", position);
+				uniforms.float("far_plane", far);
+			}
+			None => uniforms.int("linear_depth", 0),
+		}
+		target
+			.draw(&mesh.vertices, &mesh.indices, program, &uniforms, &params)
+			.map_err(|e| format!("Could not draw shadow: {:?}", e))?;
+	}
+	Ok(())
+}
+
+// Lets shadows be tuned per light, and shows what each light sees
+pub fn draw_ui(
+	ui: &imgui::Ui,
+	world: &World,
+	debug: &mut ShadowDebug,
+	textures: &mut imgui::Textures<Rc<Texture2d>>,
+) {
+	use imgui::Condition;
+
+	debug.views.register(textures);
+
+	let entities = world.entities();
+	let names = world.read_storage::<NameComponent>();
+	let mut shadows = world.write_storage::<ShadowComponent>();
+	imgui::Window::new(ui, im_str!("Shadows"))
+		.size([300.0, 400.0], Condition::FirstUseEver)
+		.position([500.0, 50.0], Condition::FirstUseEver)
+		.build(|| {
+			ui.checkbox(im_str!("Show shadow maps"), &mut debug.enabled);
+			for (entity, shadow, name) in (&entities, &mut shadows, names.maybe()).join() {
+				ui.push_id(entity.id() as i32);
+				ui.separator();
+				match name {
+					Some(name) => ui.text(&name.0),
+					None => ui.text(format!("Light {}", entity.id())),
+				}
+				ui.slider_float(im_str!("Bias"), &mut shadow.bias, 0.0, 0.05)
+					.display_format(im_str!("%.4f"))
+					.build();
+				let mut resolution = shadow.resolution as i32;
+				if ui
+					.slider_int(im_str!("Resolution"), &mut resolution, 64, 4096)
+					.build()
+				{
+					shadow.resolution = resolution as u32;
+				}
+				if debug.enabled {
+					debug.views.image(ui, entity, 1.0);
+				}
+				ui.pop_id();
+			}
+		});
+}
@@ -1,4 +1,32 @@
+pub mod bvh_sys;
 pub mod camera_sys;
+pub mod capture_sys;
 // pub mod input_sys;
 pub mod logger_sys;
-// pub mod render_sys;
+pub mod picking_sys;
+pub mod render_sys;
+pub mod transform_sys;
+
+use specs::prelude::*;
+
+// The systems that run every frame, with or without a window
+pub fn build_dispatcher<'a, 'b>(render_system: render_sys::RenderSystem) -> Dispatcher<'a, 'b> {
+	DispatcherBuilder::new()
+		.with(logger_sys::LoggerSystem, "logger_system", &[])
+		.with(camera_sys::CameraSystem, "camera_system", &[])
+		.with(capture_sys::CaptureSystem, "capture_system", &[])
+		// .with(input_sys::InputSystem, "input_system", &[])
+		.with(
+			transform_sys::TransformSystem::default(),
+			"transform_system",
+			&["logger_system"],
+		)
+		.with(bvh_sys::BvhSystem, "bvh_system", &["transform_system"])
+		.with(
+			picking_sys::PickingSystem,
+			"picking_system",
+			&["camera_system", "bvh_system"],
+		)
+		.with_thread_local(render_system)
+		.build()
+}
@@ -0,0 +1,30 @@
+use std::collections::HashSet;
+
+use specs::prelude::*;
+
+use crate::components::{model::ModelComponent, transformation::GlobalTransformationComponent};
+use crate::resources::{bvh::Bvh, mesh_store::MeshStore};
+
+// Keeps the BVH in line with where entities and their meshes are
+pub struct BvhSystem;
+
+impl<'a> System<'a> for BvhSystem {
+	type SystemData = (
+		Entities<'a>,
+		ReadStorage<'a, GlobalTransformationComponent>,
+		ReadStorage<'a, ModelComponent>,
+		Read<'a, MeshStore>,
+		Write<'a, Bvh>,
+	);
+
+	fn run(&mut self, (entities, trans, models, mesh_store, mut bvh): Self::SystemData) {
+		let mut present = HashSet::new();
+		for (entity, trans, model) in (&entities, &trans, &models).join() {
+			if let Some(mesh) = mesh_store.get(&model.0) {
+				bvh.update(entity, mesh.bounds.transformed(&trans.0));
+				present.insert(entity);
+			}
+		}
+		bvh.retain(|entity| present.contains(&entity));
+	}
+}
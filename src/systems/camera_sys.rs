@@ -1,43 +1,261 @@
-use crate::components::*;
 use crate::resources::*;
 use specs::prelude::*;
 
-use camera::CameraDirection;
+use camera::{Camera, CameraMode};
+use glutin::{MouseButton, VirtualKeyCode};
+use projection::Projection;
+
+// How quickly flying picks up speed and slows down again,
+// equal so that the top speed is the camera's speed.
+const ACCELERATION: f32 = 8.0;
+const DAMPING: f32 = 8.0;
+// Fraction of the distance to the target dollied per line scrolled
+const DOLLY_SPEED: f32 = 0.1;
+// How much narrower the view gets per line scrolled
+const ZOOM_SPEED: f32 = 1.1;
+// Limits of the field of view when zooming, in degrees
+const MIN_FOV: f32 = 5.0;
+const MAX_FOV: f32 = 120.0;
+// Limits of how much fits in an orthographic view when zooming
+const MIN_HEIGHT: f32 = 1.0;
+const MAX_HEIGHT: f32 = 10000.0;
+// How quickly turning and zooming catch up with the mouse,
+// higher is snappier and lower is smoother.
+const SMOOTHING: f32 = 15.0;
 
 pub struct CameraSystem;
 
+// Where WASD asks to go, relative to the camera, normalized
+fn wish_direction(key_state: &key_state::Keystate) -> glm::Vec3 {
+	let mut direction = glm::Vec3::zeros();
+	if key_state.is_key_down(VirtualKeyCode::W) {
+		direction.z += 1.0;
+	}
+	if key_state.is_key_down(VirtualKeyCode::S) {
+		direction.z -= 1.0;
+	}
+	if key_state.is_key_down(VirtualKeyCode::D) {
+		direction.x += 1.0;
+	}
+	if key_state.is_key_down(VirtualKeyCode::A) {
+		direction.x -= 1.0;
+	}
+	if direction != glm::Vec3::zeros() {
+		direction.normalize_mut();
+	}
+	direction
+}
+
+// Turn in place while the right button is held
+fn look(cam: &mut Camera, mouse_state: &mouse_state::MouseState, dt: f32) {
+	if mouse_state.is_button_down(MouseButton::Right) {
+		cam.do_rotate(glm::vec2(
+			mouse_state.delta.x * dt * 10.0,
+			-mouse_state.delta.y * dt * 10.0,
+		));
+	}
+}
+
+// Right drag orbits and middle drag pans
+fn orbit(cam: &mut Camera, mouse_state: &mouse_state::MouseState) {
+	if mouse_state.is_button_down(MouseButton::Right) {
+		cam.do_rotate(glm::vec2(mouse_state.delta.x, -mouse_state.delta.y) * 0.3);
+	}
+	if mouse_state.is_button_down(MouseButton::Middle) {
+		// Keep what's at the target under the cursor, more or less
+		let scale = cam.distance * 0.002;
+		cam.do_pan(glm::vec2(-mouse_state.delta.x, mouse_state.delta.y) * scale);
+	}
+}
+
+// What zooming changes, and how far it may go
+fn zoom_range(projection: &mut Projection) -> (&mut f32, f32, f32) {
+	match projection {
+		Projection::Perspective { fov, .. } => (fov, MIN_FOV.to_radians(), MAX_FOV.to_radians()),
+		Projection::Orthographic { height, .. } => (height, MIN_HEIGHT, MAX_HEIGHT),
+	}
+}
+
+// Narrow or widen the view, easing towards where scrolling asked for
+fn zoom(cam: &mut Camera, projection: &mut Projection, scroll: f32, blend: f32) {
+	let (zoom, min, max) = zoom_range(projection);
+	if scroll != 0.0 {
+		let goal = cam.zoom_goal.unwrap_or(*zoom) * ZOOM_SPEED.powf(-scroll);
+		cam.zoom_goal = Some(goal.max(min).min(max));
+	}
+	if let Some(goal) = cam.zoom_goal {
+		*zoom += (goal - *zoom) * blend;
+		if (goal - *zoom).abs() <= goal * 0.001 {
+			*zoom = goal;
+			cam.zoom_goal = None;
+		}
+	}
+}
+
+fn fly(cam: &mut Camera, wish: glm::Vec3, dt: f32) {
+	let wish = cam.front * wish.z + cam.right * wish.x;
+	cam.velocity += wish * cam.speed * ACCELERATION * dt;
+	cam.velocity *= (-DAMPING * dt).exp();
+	cam.position += cam.velocity * dt;
+}
+
+fn first_person(cam: &mut Camera, wish: glm::Vec3, dt: f32) {
+	// Looking up or down doesn't slow walking down
+	let forward = glm::vec3(cam.front.x, 0.0, cam.front.z).normalize();
+	let right = glm::vec3(cam.right.x, 0.0, cam.right.z).normalize();
+	cam.position += (forward * wish.z + right * wish.x) * cam.speed * dt;
+	cam.position.y = cam.eye_height;
+}
+
 impl<'a> System<'a> for CameraSystem {
 	type SystemData = (
 		Write<'a, camera::Camera>,
 		Read<'a, delta_time::DeltaTime>,
 		Read<'a, key_state::Keystate>,
 		Read<'a, mouse_state::MouseState>,
+		Write<'a, Projection>,
 	);
 
-	fn run(&mut self, (mut cam, delta_time, key_state, mouse_state): Self::SystemData) {
-		cam.speed = if key_state.is_key_down(glutin::VirtualKeyCode::LShift) {
+	fn run(
+		&mut self,
+		(mut cam, delta_time, key_state, mouse_state, mut projection): Self::SystemData,
+	) {
+		for (key, mode) in &[
+			(VirtualKeyCode::Key1, CameraMode::Orbit),
+			(VirtualKeyCode::Key2, CameraMode::Fly),
+			(VirtualKeyCode::Key3, CameraMode::FirstPerson),
+		] {
+			if key_state.was_key_pressed(*key) {
+				cam.set_mode(*mode);
+			}
+		}
+
+		cam.speed = if key_state.is_key_down(VirtualKeyCode::LShift) {
 			100.0
 		} else {
 			1.0
 		};
-		if key_state.is_key_down(glutin::VirtualKeyCode::W) {
-			cam.do_move(CameraDirection::Forward, delta_time.0);
+
+		let dt = delta_time.0;
+		match cam.mode {
+			CameraMode::Orbit => orbit(&mut cam, &mouse_state),
+			_ => look(&mut cam, &mouse_state, dt),
 		}
-		if key_state.is_key_down(glutin::VirtualKeyCode::S) {
-			cam.do_move(CameraDirection::Backward, delta_time.0);
+
+		// Scrolling dollies when orbiting in perspective, and zooms otherwise
+		let scroll = if mouse_state.over_ui {
+			0.0
+		} else {
+			mouse_state.scroll
+		};
+		let dolly = match *projection {
+			Projection::Perspective { .. } => cam.mode == CameraMode::Orbit,
+			Projection::Orthographic { .. } => false,
+		};
+		// The same fraction of the way left is covered whatever the frame rate
+		let blend = 1.0 - (-SMOOTHING * dt).exp();
+		if dolly {
+			cam.do_dolly(scroll * DOLLY_SPEED);
+			zoom(&mut cam, &mut projection, 0.0, blend);
+		} else {
+			zoom(&mut cam, &mut projection, scroll, blend);
 		}
-		if key_state.is_key_down(glutin::VirtualKeyCode::A) {
-			cam.do_move(CameraDirection::Left, delta_time.0);
+		cam.ease(blend);
+
+		let wish = wish_direction(&key_state);
+		match cam.mode {
+			// Keep the camera where orbiting puts it, even when nothing moved
+			CameraMode::Orbit => cam.position = cam.target - cam.front * cam.distance,
+			CameraMode::Fly => fly(&mut cam, wish, dt),
+			CameraMode::FirstPerson => first_person(&mut cam, wish, dt),
 		}
-		if key_state.is_key_down(glutin::VirtualKeyCode::D) {
-			cam.do_move(CameraDirection::Right, delta_time.0);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn camera() -> Camera {
+		let mut camera = Camera {
+			speed: 2.0,
+			..Camera::default()
+		};
+		camera.update_camera_vectors();
+		camera
+	}
+
+	fn fov(projection: &Projection) -> f32 {
+		match *projection {
+			Projection::Perspective { fov, .. } => fov.to_degrees(),
+			Projection::Orthographic { .. } => panic!("Not a perspective projection"),
 		}
+	}
 
-		if mouse_state.is_button_down(glutin::MouseButton::Right) {
-			cam.do_rotate(glm::vec2(
-				mouse_state.delta.x * delta_time.0 * 10.0,
-				-mouse_state.delta.y * delta_time.0 * 10.0,
-			));
+	#[test]
+	fn zooming_eases_towards_the_goal() {
+		let mut camera = camera();
+		let mut projection = Projection::perspective(60f32.to_radians());
+		zoom(&mut camera, &mut projection, 1.0, 0.5);
+		let goal = 60.0 / ZOOM_SPEED;
+		assert!((camera.zoom_goal.unwrap().to_degrees() - goal).abs() < 1e-3);
+		assert!((fov(&projection) - (60.0 + goal) / 2.0).abs() < 1e-3);
+		// Until it's close enough to snap there and stop
+		for _ in 0..20 {
+			zoom(&mut camera, &mut projection, 0.0, 0.5);
 		}
+		assert_eq!(camera.zoom_goal, None);
+		assert!((fov(&projection) - goal).abs() < 1e-3);
+	}
+
+	#[test]
+	fn zooming_stays_within_its_limits() {
+		let mut camera = camera();
+		let mut projection = Projection::perspective(60f32.to_radians());
+		zoom(&mut camera, &mut projection, 100.0, 1.0);
+		assert!((fov(&projection) - MIN_FOV).abs() < 1e-3);
+		zoom(&mut camera, &mut projection, -100.0, 1.0);
+		assert!((fov(&projection) - MAX_FOV).abs() < 1e-3);
+
+		let mut projection = Projection::orthographic(100.0);
+		zoom(&mut camera, &mut projection, -1000.0, 1.0);
+		assert!(matches!(
+			projection,
+			Projection::Orthographic { height, .. } if height == MAX_HEIGHT
+		));
+	}
+
+	#[test]
+	fn flying_speeds_up_and_slows_down() {
+		let mut camera = camera();
+		let dt = 1.0 / 60.0;
+		for _ in 0..120 {
+			fly(&mut camera, glm::vec3(0.0, 0.0, 1.0), dt);
+		}
+		// The top speed is about the camera's speed, in the direction it faces
+		let speed = glm::length(&camera.velocity);
+		assert!(
+			(speed - camera.speed).abs() < camera.speed * 0.1,
+			"{}",
+			speed
+		);
+		assert!(glm::angle(&camera.velocity, &camera.front) < 1e-3);
+
+		let before = camera.position;
+		for _ in 0..120 {
+			fly(&mut camera, glm::Vec3::zeros(), dt);
+		}
+		assert!(glm::length(&camera.velocity) < 1e-3);
+		assert!(glm::distance(&camera.position, &before) > 0.0);
+	}
+
+	#[test]
+	fn walking_ignores_where_the_camera_looks_up_or_down() {
+		let mut camera = camera();
+		camera.pitch = -60.0;
+		camera.update_camera_vectors();
+		first_person(&mut camera, glm::vec3(0.0, 0.0, 1.0), 0.5);
+		// Facing -z at yaw -90
+		assert!(glm::distance(&camera.position, &glm::vec3(0.0, 1.7, -1.0)) < 1e-5);
 	}
 }
@@ -0,0 +1,33 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::resources::*;
+use specs::prelude::*;
+
+use capture::Capture;
+
+// Frames per second of recordings started from the keyboard
+const RECORDING_FPS: f32 = 60.0;
+
+// F12 takes a screenshot, F10 starts and stops recording
+pub struct CaptureSystem;
+
+impl<'a> System<'a> for CaptureSystem {
+	type SystemData = (Write<'a, Capture>, Read<'a, key_state::Keystate>);
+
+	fn run(&mut self, (mut capture, key_state): Self::SystemData) {
+		if key_state.was_key_pressed(glutin::VirtualKeyCode::F12) {
+			capture.take_screenshot();
+		}
+		if key_state.was_key_pressed(glutin::VirtualKeyCode::F10) {
+			if capture.recording.is_some() {
+				capture.stop_recording();
+			} else {
+				let started = SystemTime::now()
+					.duration_since(UNIX_EPOCH)
+					.map(|time| time.as_secs())
+					.unwrap_or(0);
+				capture.start_recording(format!("recordings/{}", started), 1.0 / RECORDING_FPS);
+			}
+		}
+	}
+}
@@ -8,6 +8,7 @@ use crate::resources::{
 	time::CurrentTime,
 };
 
+use nalgebra::UnitQuaternion;
 use specs::prelude::*;
 
 pub struct LoggerSystem;
@@ -21,14 +22,12 @@ impl<'a> System<'a> for LoggerSystem {
 		Read<'a, CurrentTime>,
 	);
 
-	fn run(&mut self, (names, lights, mut trans, _delta_time, current_time): Self::SystemData) {
+	fn run(&mut self, (names, lights, mut trans, delta_time, _current_time): Self::SystemData) {
+		// Circle around above the origin, once every 2 pi seconds
+		let turn = UnitQuaternion::from_axis_angle(&glm::Vec3::y_axis(), -delta_time.0);
 		for (name, _light, mut trans) in (&names, &lights, &mut trans).join() {
 			if name.0 == "Random Light" {
-				trans.set_pos(glm::vec3(
-					(current_time.0.cos() * 100.0) as f32,
-					50.0,
-					(current_time.0.sin() * 100.0) as f32,
-				))
+				trans.rotate_around(&glm::vec3(0.0, 50.0, 0.0), &turn);
 			}
 		}
 	}
@@ -0,0 +1,85 @@
+use specs::prelude::*;
+
+use crate::components::{
+	model::ModelComponent,
+	scene_camera::{CameraComponent, CameraTarget},
+	transformation::GlobalTransformationComponent,
+};
+use crate::picking;
+use crate::resources::{
+	bvh::Bvh, camera::Camera, mesh_store::MeshStore, mouse_state::MouseState,
+	projection::Projection, selected_entity::SelectedEntity, window_size::WindowSize,
+};
+
+#[derive(SystemData)]
+pub struct PickingData<'a> {
+	entities: Entities<'a>,
+	trans: ReadStorage<'a, GlobalTransformationComponent>,
+	models: ReadStorage<'a, ModelComponent>,
+	cameras: ReadStorage<'a, CameraComponent>,
+	mouse_state: Read<'a, MouseState>,
+	window_size: Read<'a, WindowSize>,
+	camera: Read<'a, Camera>,
+	projection: Read<'a, Projection>,
+	mesh_store: Read<'a, MeshStore>,
+	bvh: Read<'a, Bvh>,
+	selected: Write<'a, SelectedEntity>,
+}
+
+// Left clicking in the viewport selects whatever is under the cursor,
+// or nothing if that's empty space. Clicks go through the view they land on.
+pub struct PickingSystem;
+
+impl<'a> System<'a> for PickingSystem {
+	type SystemData = PickingData<'a>;
+
+	fn run(&mut self, mut data: Self::SystemData) {
+		if let Some(entity) = data.selected.0 {
+			if !data.entities.is_alive(entity) {
+				data.selected.0 = None;
+			}
+		}
+		if !data
+			.mouse_state
+			.was_button_pressed(glutin::MouseButton::Left)
+			|| data.mouse_state.over_ui
+		{
+			return;
+		}
+
+		// Camera entities drawing to the screen are on top of the main view,
+		// and those with a higher priority on top of the others.
+		let mut cameras: Vec<_> = (&data.cameras, &data.trans)
+			.join()
+			.filter(|(camera, _)| camera.active && camera.target == CameraTarget::Screen)
+			.collect();
+		cameras.sort_by_key(|(camera, _)| camera.priority);
+		let through_camera = cameras.iter().rev().find_map(|(camera, trans)| {
+			let (position, size) = picking::viewport_position(
+				&data.mouse_state.position,
+				&data.window_size.0,
+				&camera.viewport,
+			)?;
+			Some(picking::screen_ray(
+				&position,
+				&size,
+				&camera.projection,
+				&glm::inverse(&trans.0),
+			))
+		});
+		let ray = match through_camera {
+			Some(ray) => ray,
+			None if data.camera.active => picking::screen_ray(
+				&data.mouse_state.position,
+				&data.window_size.0,
+				&data.projection,
+				&data.camera.get_view_matrix(),
+			),
+			// Nothing was drawn where the click landed
+			None => return,
+		};
+		data.selected.0 =
+			picking::pick(&ray, &data.bvh, &data.trans, &data.models, &data.mesh_store)
+				.map(|(entity, _)| entity);
+	}
+}
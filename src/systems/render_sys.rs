@@ -1,96 +1,1020 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use glium::uniforms::{MagnifySamplerFilter, SamplerBehavior, SamplerWrapFunction};
+use glium::{BlitTarget, Rect, Surface};
+use specs::prelude::*;
+use specs::Join;
+
+use crate::bounds::Frustum;
+use crate::cameras::CameraTargets;
+use crate::clip_control::ClipControl;
 use crate::components::{
-	light::{Light, LightComponent},
+	light::{Light, LightComponent, MAX_LIGHTS},
 	material::MaterialComponent,
 	model::ModelComponent,
-	shader::ShaderComponent,
+	pbr_material::PbrMaterialComponent,
+	scene_camera::{CameraComponent, CameraTarget},
+	shadow::{ShadowComponent, MAX_SHADOW_CUBE_MAPS, MAX_SHADOW_MAPS},
 	texture::GLTextureComponent,
-	transformation::TransformationComponent,
+	transformation::GlobalTransformationComponent,
+};
+use crate::gpu_store::{self, GpuAssets, GpuMesh};
+use crate::render_target::{Offscreen, RenderTarget};
+use crate::resources::{
+	bvh::Bvh,
+	camera::Camera,
+	material_store::{MaterialStore, PbrMaterialStore},
+	mesh_store::MeshStore,
+	projection::Projection,
+	render_stats::RenderStats,
+	shader_store::{ShaderHandle, ShaderStore},
+	texture_map::TextureMap,
 };
+use crate::shadows::{self, LightView, ShadowDebug, ShadowMap, ShadowMaps};
+use crate::texture_views::TextureViews;
+use crate::uniforms::UniformList;
 
-use crate::resources::{camera::Camera, projection::Projection, texture_map::GLTextureMap};
-use specs::prelude::*;
-use specs::Join;
-use std::ffi::CStr;
+// Draws every entity with a model, texture and material into the current frame,
+// as seen by the main camera and then by every active camera entity.
+// glium objects can't leave the main thread, so this runs as a thread local system.
+pub struct RenderSystem {
+	pub context: Rc<glium::backend::Context>,
+	// Where the frame is drawn, handed over by the frame loop
+	pub target: Rc<RefCell<Option<RenderTarget>>>,
+	pub gpu: Rc<RefCell<GpuAssets>>,
+	pub shader: ShaderHandle,
+	pub pbr_shader: ShaderHandle,
+	pub shadow_shader: ShaderHandle,
+	pub shadow_debug_shader: ShaderHandle,
+	pub shadows: ShadowMaps,
+	pub shadow_debug: Rc<RefCell<ShadowDebug>>,
+	pub camera_targets: CameraTargets,
+	// What cameras drawing into a texture saw, for the overlay
+	pub camera_views: Rc<RefCell<TextureViews>>,
+	reversed: ReversedDepth,
+	reported: Reported,
+}
 
-pub struct RenderSystem;
+// Errors that would otherwise be repeated every frame, only reported the first
+// time they come up. Another error for the same thing, after it was reloaded
+// say, is reported again.
+#[derive(Default)]
+struct Reported(HashSet<String>);
+
+impl Reported {
+	fn report(&mut self, error: String) {
+		if !self.0.contains(&error) {
+			eprintln!("{}", error);
+			self.0.insert(error);
+		}
+	}
+}
 
 #[derive(SystemData)]
 pub struct RenderData<'a> {
-	pub trans: ReadStorage<'a, TransformationComponent>,
+	pub entities: Entities<'a>,
+	pub trans: ReadStorage<'a, GlobalTransformationComponent>,
 	pub model: ReadStorage<'a, ModelComponent>,
 	pub texture: ReadStorage<'a, GLTextureComponent>,
-	pub shader: ReadStorage<'a, ShaderComponent>,
-	pub gltexture_map: Read<'a, GLTextureMap>,
-	pub camera: Read<'a, Camera>,
-	pub projection: Read<'a, Projection>,
 	pub material: ReadStorage<'a, MaterialComponent>,
+	pub pbr_materials: ReadStorage<'a, PbrMaterialComponent>,
 	pub lights: ReadStorage<'a, LightComponent>,
+	pub shadows: ReadStorage<'a, ShadowComponent>,
+	pub cameras: ReadStorage<'a, CameraComponent>,
+	pub camera: Read<'a, Camera>,
+	pub projection: Read<'a, Projection>,
+	pub bvh: Read<'a, Bvh>,
+	pub mesh_store: Read<'a, MeshStore>,
+	pub texture_map: Read<'a, TextureMap>,
+	pub material_store: Read<'a, MaterialStore>,
+	pub pbr_material_store: Read<'a, PbrMaterialStore>,
+	pub shader_store: Read<'a, ShaderStore>,
+	pub stats: Write<'a, RenderStats>,
 }
 
-impl<'a> System<'a> for RenderSystem {
-	type SystemData = RenderData<'a>;
+impl RenderSystem {
+	pub fn new(
+		context: Rc<glium::backend::Context>,
+		target: Rc<RefCell<Option<RenderTarget>>>,
+		gpu: Rc<RefCell<GpuAssets>>,
+		shadow_debug: Rc<RefCell<ShadowDebug>>,
+		camera_views: Rc<RefCell<TextureViews>>,
+		clip_control: ClipControl,
+		world: &World,
+	) -> Result<Self, String> {
+		// Held for as long as we render with them
+		let mut shader_store = world.write_resource::<ShaderStore>();
+		Ok(Self {
+			shader: shader_store.load_from_files("./vertex.vs", "./fragment.fs")?,
+			pbr_shader: shader_store.load_from_files("./vertex.vs", "./pbr.fs")?,
+			shadow_shader: shader_store.load_from_files("./shadow.vs", "./shadow.fs")?,
+			shadow_debug_shader: shader_store
+				.load_from_files("./shadow_debug.vs", "./shadow_debug.fs")?,
+			shadows: ShadowMaps::new(&context)?,
+			context,
+			target,
+			gpu,
+			shadow_debug,
+			camera_targets: CameraTargets::default(),
+			camera_views,
+			reversed: ReversedDepth {
+				clip_control,
+				target: None,
+			},
+			reported: Reported::default(),
+		})
+	}
 
-	fn run(&mut self, render_data: Self::SystemData) {
-		unsafe {
-			gl::ClearColor(0.0, 0.0, 0.0, 1.0);
-			gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+	// Make sure everything we're about to draw is on the GPU.
+	// Whatever fails to upload is reported, and only the entities using it are left out.
+	fn upload(&self, gpu: &mut GpuAssets, render_data: &RenderData) {
+		let context = &self.context;
+		for shader in &[
+			&self.shader,
+			&self.pbr_shader,
+			&self.shadow_shader,
+			&self.shadow_debug_shader,
+		] {
+			gpu.programs
+				.upload_or_report(shader, &render_data.shader_store, |source| {
+					gpu_store::compile_program(context, source)
+				});
+		}
+		for (model, texture) in (&render_data.model, &render_data.texture).join() {
+			gpu.meshes
+				.upload_or_report(&model.0, &render_data.mesh_store, |mesh| {
+					gpu_store::upload_mesh(context, mesh)
+				});
+			gpu.textures
+				.upload_or_report(&texture.0, &render_data.texture_map, |texture| {
+					gpu_store::upload_texture(context, texture)
+				});
 		}
+		for material in (&render_data.material).join() {
+			let material = match render_data.material_store.get(&material.0) {
+				Some(material) => material,
+				None => continue,
+			};
+			for (_, map) in material.maps().iter() {
+				if let Some(map) = map {
+					gpu.textures
+						.upload_or_report(map, &render_data.texture_map, |texture| {
+							gpu_store::upload_texture(context, texture)
+						});
+				}
+			}
+		}
+		for (model, material) in (&render_data.model, &render_data.pbr_materials).join() {
+			gpu.meshes
+				.upload_or_report(&model.0, &render_data.mesh_store, |mesh| {
+					gpu_store::upload_mesh(context, mesh)
+				});
+			let material = match render_data.pbr_material_store.get(&material.0) {
+				Some(material) => material,
+				None => continue,
+			};
+			for (_, map) in material.color_maps().iter() {
+				if let Some(map) = map {
+					gpu.srgb_textures
+						.upload_or_report(map, &render_data.texture_map, |texture| {
+							gpu_store::upload_srgb_texture(context, texture)
+						});
+				}
+			}
+			for (_, map) in material.data_maps().iter() {
+				if let Some(map) = map {
+					gpu.textures
+						.upload_or_report(map, &render_data.texture_map, |texture| {
+							gpu_store::upload_texture(context, texture)
+						});
+				}
+			}
+		}
+	}
 
-		let camera_mat = render_data.camera.get_view_matrix();
+	// Draw the scene, starting with the shadow maps
+	fn render<S: Surface>(
+		&mut self,
+		target: &mut S,
+		render_data: &RenderData,
+		stats: &mut RenderStats,
+	) {
+		let projection = self
+			.reversed
+			.supported(&render_data.projection, &mut self.reported);
+		target.clear_color_srgb_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+		let mut gpu = self.gpu.borrow_mut();
+		self.upload(&mut gpu, render_data);
+		let (program, pbr_program, shadow_program) = match (
+			gpu.programs.get(&self.shader),
+			gpu.programs.get(&self.pbr_shader),
+			gpu.programs.get(&self.shadow_shader),
+		) {
+			(Some(program), Some(pbr_program), Some(shadow_program)) => {
+				(program, pbr_program, shadow_program)
+			}
+			_ => return,
+		};
 
-		for (trans, model, shader, texture, material) in (
+		// Everything that can cast a shadow
+		let shadow_scene: Vec<(Entity, glm::Mat4, &GpuMesh)> = (
+			&render_data.entities,
 			&render_data.trans,
 			&render_data.model,
-			&render_data.shader,
-			&render_data.texture,
-			&render_data.material,
 		)
 			.join()
+			.filter_map(|(entity, trans, model)| {
+				gpu.meshes.get(&model.0).map(|mesh| (entity, trans.0, mesh))
+			})
+			.collect();
+
+		// Lights past the maximum the shaders support are left out, and so are
+		// the shadows of lights past the maximum number of shadow maps.
+		// The shadow maps of directional lights are fitted around each view in
+		// turn, those of other lights don't depend on the view.
+		let mut lighting = Lighting::default();
+		for (entity, trans, light, shadow) in (
+			&render_data.entities,
+			&render_data.trans,
+			&render_data.lights,
+			render_data.shadows.maybe(),
+		)
+			.join()
+			.take(MAX_LIGHTS)
 		{
-			unsafe {
-				if let Some(handle) = render_data.gltexture_map.get_texture(texture.0) {
-					shader.use_program();
-					shader.set_mat4(c_str!("model"), &trans.0);
-					shader.set_mat4(c_str!("projection"), &render_data.projection.0);
-
-					for (trans, light) in (&render_data.trans, &render_data.lights).join() {
-						let pos = trans.get_pos();
-
-						match light.0 {
-							Light::PointLight {
-								ambient,
-								diffuse,
-								specular,
-								constant,
-								linear,
-								quadratic,
-								..
-							} => {
-								shader.set_vector3(c_str!("point_light.position"), &pos);
-								shader.set_vector3(c_str!("point_light.ambient"), &ambient);
-								shader.set_vector3(c_str!("point_light.diffuse"), &diffuse);
-								shader.set_vector3(c_str!("point_light.specular"), &specular);
-								shader.set_float(c_str!("point_light.constant"), constant);
-								shader.set_float(c_str!("point_light.linear"), linear);
-								shader.set_float(c_str!("point_light.quadratic"), quadratic);
+			let mut uniform = LightUniform::new(trans, &light.0);
+			match (shadow, &light.0) {
+				(Some(shadow), Light::DirectionalLight { .. }) => {
+					if lighting.map_casters.len() < MAX_SHADOW_MAPS {
+						lighting.directional.push(DirectionalShadow {
+							entity,
+							index: lighting.lights.len(),
+							slot: lighting.map_casters.len(),
+							light: &light.0,
+							shadow,
+						});
+						lighting.map_casters.push(entity);
+					}
+				}
+				(Some(shadow), _) => {
+					let view = LightView::new(&light.0, &trans.get_pos(), shadow, &trans.get_pos());
+					let (casters, max) = if view.is_cube() {
+						(&mut lighting.cube_map_casters, MAX_SHADOW_CUBE_MAPS)
+					} else {
+						(&mut lighting.map_casters, MAX_SHADOW_MAPS)
+					};
+					if casters.len() < max {
+						match render_shadow_map(
+							&mut self.shadows,
+							&self.context,
+							entity,
+							&view,
+							shadow,
+							shadow_program,
+							&shadow_scene,
+							&render_data.bvh,
+						) {
+							Ok(()) => {
+								uniform.cast_shadow(casters.len(), &view, shadow);
+								casters.push(entity);
+							}
+							Err(e) => self
+								.reported
+								.report(format!("Could not render shadows of {:?}: {}", entity, e)),
+						}
+					}
+				}
+				(None, _) => {}
+			}
+			lighting.lights.push(uniform);
+		}
+		let casters: Vec<Entity> = lighting
+			.map_casters
+			.iter()
+			.chain(lighting.cube_map_casters.iter())
+			.copied()
+			.collect();
+		self.shadows.retain(&casters);
+
+		// The main camera covers the whole target, which was cleared already.
+		// It can be turned off, leaving the target to camera entities.
+		let (width, height) = target.get_dimensions();
+		if render_data.camera.active {
+			let main_view = View {
+				view: render_data.camera.get_view_matrix(),
+				projection: projection.matrix(width as f32 / height.max(1) as f32),
+				reverse_z: projection.reverse_z(),
+				position: render_data.camera.position,
+				viewport: None,
+			};
+			let scene = Scene {
+				gpu: &gpu,
+				render_data,
+				program,
+				pbr_program,
+				uniforms: lighting.uniforms(
+					&mut self.shadows,
+					&self.context,
+					shadow_program,
+					&shadow_scene,
+					&render_data.bvh,
+					&main_view.position,
+				),
+			};
+			if main_view.reverse_z {
+				let rect = Rect {
+					left: 0,
+					bottom: 0,
+					width,
+					height,
+				};
+				let black = Some((0.0, 0.0, 0.0, 1.0));
+				if let Err(e) = self.reversed.draw(
+					&self.context,
+					target,
+					rect,
+					black,
+					&scene,
+					&main_view,
+					stats,
+					&mut self.reported,
+				) {
+					self.reported
+						.report(format!("Could not draw the main camera: {}", e));
+				}
+			} else {
+				scene.draw(target, &main_view, stats, &mut self.reported);
+			}
+		}
+
+		let mut cameras: Vec<_> = (
+			&render_data.entities,
+			&render_data.cameras,
+			&render_data.trans,
+		)
+			.join()
+			.filter(|(_, camera, _)| camera.active)
+			.collect();
+		cameras.sort_by_key(|(_, camera, _)| camera.priority);
+
+		let mut views = self.camera_views.borrow_mut();
+		views.clear();
+		let mut textured = Vec::new();
+		for (entity, camera, trans) in cameras {
+			let clear_color = camera
+				.clear_color
+				.map(|color| (color.x, color.y, color.z, color.w));
+			let projection = self
+				.reversed
+				.supported(&camera.projection, &mut self.reported);
+			let uniforms = lighting.uniforms(
+				&mut self.shadows,
+				&self.context,
+				shadow_program,
+				&shadow_scene,
+				&render_data.bvh,
+				&trans.get_pos(),
+			);
+			let scene = Scene {
+				gpu: &gpu,
+				render_data,
+				program,
+				pbr_program,
+				uniforms,
+			};
+			match camera.target {
+				CameraTarget::Screen => {
+					let rect = viewport_rect(&camera.viewport, width, height);
+					let aspect = rect.width as f32 / rect.height.max(1) as f32;
+					let view = View::from_camera(&projection, trans, aspect, Some(rect));
+					if view.reverse_z {
+						if let Err(e) = self.reversed.draw(
+							&self.context,
+							target,
+							rect,
+							clear_color,
+							&scene,
+							&view,
+							stats,
+							&mut self.reported,
+						) {
+							self.reported
+								.report(format!("Could not draw camera {:?}: {}", entity, e));
+						}
+					} else {
+						target.clear(Some(&rect), clear_color, true, Some(1.0), None);
+						scene.draw(target, &view, stats, &mut self.reported);
+					}
+				}
+				CameraTarget::Texture { width, height } => {
+					textured.push(entity);
+					let offscreen =
+						match self
+							.camera_targets
+							.get(&self.context, entity, width, height)
+						{
+							Ok(offscreen) => offscreen,
+							Err(e) => {
+								self.reported.report(format!(
+									"Could not create target of camera {:?}: {}",
+									entity, e
+								));
+								continue;
 							}
+						};
+					let mut framebuffer = match offscreen.framebuffer(&self.context) {
+						Ok(framebuffer) => framebuffer,
+						Err(e) => {
+							self.reported
+								.report(format!("Could not draw camera {:?}: {}", entity, e));
+							continue;
 						}
+					};
+					framebuffer.clear(
+						None,
+						Some(clear_color.unwrap_or((0.0, 0.0, 0.0, 1.0))),
+						true,
+						Some(projection.clear_depth()),
+						None,
+					);
+					let aspect = width as f32 / height.max(1) as f32;
+					let view = View::from_camera(&projection, trans, aspect, None);
+					// Camera textures have a floating point depth buffer already
+					let reported = &mut self.reported;
+					let mut draw = || scene.draw(&mut framebuffer, &view, stats, reported);
+					if view.reverse_z {
+						self.reversed.clip_control.zero_to_one(&self.context, draw);
+					} else {
+						draw();
 					}
+					views.push(entity, offscreen.color.clone());
+				}
+			}
+		}
 
-					shader.set_vector3(c_str!("material.ambient"), &material.ambient);
-					shader.set_vector3(c_str!("material.diffuse"), &material.diffuse);
-					shader.set_vector3(c_str!("material.specular"), &material.specular);
-					shader.set_float(c_str!("material.shininess"), material.shininess);
+		for error in lighting.errors.drain(..) {
+			self.reported.report(error);
+		}
+
+		// Shadow maps of directional lights show how they were fitted for the last view
+		let mut debug = self.shadow_debug.borrow_mut();
+		debug.views.clear();
+		if let (true, Some(debug_program)) =
+			(debug.enabled, gpu.programs.get(&self.shadow_debug_shader))
+		{
+			for entity in casters {
+				match self
+					.shadows
+					.debug_view(&self.context, entity, debug_program)
+				{
+					Ok(texture) => debug.views.push(entity, texture),
+					Err(e) => self
+						.reported
+						.report(format!("Could not show shadow map of {:?}: {}", entity, e)),
+				}
+			}
+		}
+		self.camera_targets.retain(&textured);
+	}
+}
+
+// Render a light's shadow map, with only what the light can see in it
+#[allow(clippy::too_many_arguments)]
+fn render_shadow_map(
+	shadows: &mut ShadowMaps,
+	context: &Rc<glium::backend::Context>,
+	entity: Entity,
+	view: &LightView,
+	shadow: &ShadowComponent,
+	program: &glium::Program,
+	scene: &[(Entity, glm::Mat4, &GpuMesh)],
+	bvh: &Bvh,
+) -> Result<(), String> {
+	let in_view = view.casters(bvh);
+	let scene: Vec<(glm::Mat4, &GpuMesh)> = scene
+		.iter()
+		.filter(|(entity, _, _)| in_view.contains(entity.id()))
+		.map(|&(_, model, mesh)| (model, mesh))
+		.collect();
+	shadows.render(context, entity, view, shadow.resolution, program, &scene)
+}
 
-					shader.set_mat4(c_str!("camera"), &camera_mat);
-					shader.set_vector3(c_str!("camera_pos"), &render_data.camera.position);
+// Reversed depth takes a floating point depth buffer and glClipControl to be
+// of any use. The window has no such depth buffer, so views with it that go on
+// the window are drawn into one of the same size first, then copied over.
+struct ReversedDepth {
+	clip_control: ClipControl,
+	target: Option<Offscreen>,
+}
+
+impl ReversedDepth {
+	// The projection to draw with, which without glClipControl
+	// has its depth the usual way round
+	fn supported(&self, projection: &Projection, reported: &mut Reported) -> Projection {
+		if projection.reverse_z() && !self.clip_control.is_supported() {
+			reported.report(
+				"Reverse Z needs OpenGL 4.5, drawing with regular depth instead".to_string(),
+			);
+			return projection.regular();
+		}
+		*projection
+	}
+
+	// Draw the view into `rect` of the target, cleared to `clear_color`
+	// first or on top of what's there already when there's none.
+	#[allow(clippy::too_many_arguments)]
+	fn draw<S: Surface>(
+		&mut self,
+		context: &Rc<glium::backend::Context>,
+		target: &mut S,
+		rect: Rect,
+		clear_color: Option<(f32, f32, f32, f32)>,
+		scene: &Scene,
+		view: &View,
+		stats: &mut RenderStats,
+		reported: &mut Reported,
+	) -> Result<(), String> {
+		let (width, height) = target.get_dimensions();
+		let offscreen = match self.target.take() {
+			Some(offscreen) if offscreen.dimensions() == (width, height) => offscreen,
+			_ => Offscreen::new(context, width, height)?,
+		};
+		let offscreen = self.target.get_or_insert(offscreen);
+		let mut framebuffer = offscreen.framebuffer(context)?;
+
+		let blit = BlitTarget {
+			left: rect.left,
+			bottom: rect.bottom,
+			width: rect.width as i32,
+			height: rect.height as i32,
+		};
+		if clear_color.is_none() {
+			target.blit_color(&rect, &framebuffer, &blit, MagnifySamplerFilter::Nearest);
+		}
+		framebuffer.clear(Some(&rect), clear_color, true, Some(0.0), None);
+		self.clip_control.zero_to_one(context, || {
+			scene.draw(&mut framebuffer, view, stats, reported)
+		});
+		framebuffer.blit_color(&rect, target, &blit, MagnifySamplerFilter::Nearest);
+		Ok(())
+	}
+}
 
-					gl::BindTexture(gl::TEXTURE_2D, handle);
+// A directional light casting shadows, whose map follows the view being drawn
+struct DirectionalShadow<'a> {
+	entity: Entity,
+	// Where the light is in `Lighting::lights`
+	index: usize,
+	slot: usize,
+	light: &'a Light,
+	shadow: &'a ShadowComponent,
+}
+
+// The lights of a frame and which of them have which shadow maps
+#[derive(Default)]
+struct Lighting<'a> {
+	lights: Vec<LightUniform>,
+	directional: Vec<DirectionalShadow<'a>>,
+	map_casters: Vec<Entity>,
+	cube_map_casters: Vec<Entity>,
+	// Where the directional shadow maps were last fitted around
+	fitted: Option<glm::Vec3>,
+	// What went wrong fitting them, to be reported once the frame is drawn
+	errors: Vec<String>,
+}
+
+impl<'a> Lighting<'a> {
+	// Everything the lit shaders share for a view at `center`, whichever material.
+	// Directional shadow maps are only rendered again when the view has moved.
+	fn uniforms<'s>(
+		&mut self,
+		shadows: &'s mut ShadowMaps,
+		context: &Rc<glium::backend::Context>,
+		program: &glium::Program,
+		scene: &[(Entity, glm::Mat4, &GpuMesh)],
+		bvh: &Bvh,
+		center: &glm::Vec3,
+	) -> UniformList<'s> {
+		if self.fitted != Some(*center) {
+			self.fitted = Some(*center);
+			for directional in &self.directional {
+				let uniform = &mut self.lights[directional.index];
+				let view = LightView::new(
+					directional.light,
+					&uniform.position,
+					directional.shadow,
+					center,
+				);
+				match render_shadow_map(
+					shadows,
+					context,
+					directional.entity,
+					&view,
+					directional.shadow,
+					program,
+					scene,
+					bvh,
+				) {
+					Ok(()) => uniform.cast_shadow(directional.slot, &view, directional.shadow),
+					Err(e) => {
+						self.errors.push(format!(
+							"Could not render shadows of {:?}: {}",
+							directional.entity, e
+						));
+						uniform.shadow = -1;
+					}
+				}
+			}
+		}
+
+		let shadows: &'s ShadowMaps = shadows;
+		let mut uniforms = UniformList::new();
+		for (i, light) in self.lights.iter().enumerate() {
+			light.add_to(&mut uniforms, i);
+		}
+		uniforms.int("light_count", self.lights.len() as i32);
+		// Unused slots still need a texture of the right kind
+		for slot in 0..MAX_SHADOW_MAPS {
+			let map = match self
+				.map_casters
+				.get(slot)
+				.and_then(|entity| shadows.get(*entity))
+			{
+				Some(ShadowMap::Depth(map)) => map,
+				_ => &shadows.empty_map,
+			};
+			uniforms.depth_texture(
+				format!("shadow_maps[{}]", slot),
+				map,
+				shadows::depth_sampler(),
+			);
+		}
+		for slot in 0..MAX_SHADOW_CUBE_MAPS {
+			let map = match self
+				.cube_map_casters
+				.get(slot)
+				.and_then(|entity| shadows.get(*entity))
+			{
+				Some(ShadowMap::Cube(map)) => map,
+				_ => &shadows.empty_cube_map,
+			};
+			uniforms.depth_cubemap(
+				format!("shadow_cube_maps[{}]", slot),
+				map,
+				shadows::depth_sampler(),
+			);
+		}
+		uniforms
+	}
+}
+
+// How a camera sees the scene
+struct View {
+	view: glm::Mat4,
+	projection: glm::Mat4,
+	// Nearer things have a higher depth instead of a lower one
+	reverse_z: bool,
+	position: glm::Vec3,
+	// The part of the target drawn into, all of it when there's none
+	viewport: Option<Rect>,
+}
+
+impl View {
+	fn from_camera(
+		projection: &Projection,
+		trans: &GlobalTransformationComponent,
+		aspect: f32,
+		viewport: Option<Rect>,
+	) -> Self {
+		Self {
+			view: glm::inverse(&trans.0),
+			projection: projection.matrix(aspect),
+			reverse_z: projection.reverse_z(),
+			position: trans.get_pos(),
+			viewport,
+		}
+	}
+}
+
+// A camera's viewport in pixels, which is given in fractions of the target
+fn viewport_rect(viewport: &glm::Vec4, width: u32, height: u32) -> Rect {
+	let (width, height) = (width as f32, height as f32);
+	Rect {
+		left: (viewport.x * width) as u32,
+		bottom: (viewport.y * height) as u32,
+		width: (viewport.z * width) as u32,
+		height: (viewport.w * height) as u32,
+	}
+}
+
+// What every camera draws the scene with
+struct Scene<'a, 'd> {
+	gpu: &'a GpuAssets,
+	render_data: &'a RenderData<'d>,
+	program: &'a glium::Program,
+	pbr_program: &'a glium::Program,
+	// Lights and shadows
+	uniforms: UniformList<'a>,
+}
+
+impl<'a, 'd> Scene<'a, 'd> {
+	fn draw<S: Surface>(
+		&self,
+		target: &mut S,
+		view: &View,
+		stats: &mut RenderStats,
+		reported: &mut Reported,
+	) {
+		let (gpu, render_data, program, pbr_program) =
+			(self.gpu, self.render_data, self.program, self.pbr_program);
+		let mut view_uniforms = self.uniforms.clone();
+		view_uniforms.mat4("camera", &view.view);
+		view_uniforms.mat4("projection", &view.projection);
+		view_uniforms.vec3("camera_pos", &view.position);
+		let visible = render_data
+			.bvh
+			.in_frustum(&Frustum::from_matrix(&(view.projection * view.view)));
+
+		let sampler = SamplerBehavior {
+			wrap_function: (
+				SamplerWrapFunction::Repeat,
+				SamplerWrapFunction::Repeat,
+				SamplerWrapFunction::Repeat,
+			),
+			..Default::default()
+		};
+		let params = glium::DrawParameters {
+			depth: glium::Depth {
+				test: if view.reverse_z {
+					glium::draw_parameters::DepthTest::IfMore
+				} else {
+					glium::draw_parameters::DepthTest::IfLess
+				},
+				write: true,
+				..Default::default()
+			},
+			viewport: view.viewport,
+			..Default::default()
+		};
+
+		// Entities with a PBR material are drawn with that instead
+		for (entity, trans, model, texture, material, ()) in (
+			&render_data.entities,
+			&render_data.trans,
+			&render_data.model,
+			&render_data.texture,
+			&render_data.material,
+			!&render_data.pbr_materials,
+		)
+			.join()
+		{
+			let (mesh, texture, material) = match (
+				gpu.meshes.get(&model.0),
+				gpu.textures.get(&texture.0),
+				render_data.material_store.get(&material.0),
+			) {
+				(Some(mesh), Some(texture), Some(material)) => (mesh, texture, material),
+				_ => continue,
+			};
+			if !visible.contains(entity.id()) {
+				stats.culled += 1;
+				continue;
+			}
+
+			let mut uniforms = view_uniforms.clone();
+			uniforms.mat4("model", &trans.0);
+			uniforms.vec3("material.ambient", &material.ambient);
+			uniforms.vec3("material.diffuse", &material.diffuse);
+			uniforms.vec3("material.specular", &material.specular);
+			uniforms.float("material.shininess", material.shininess);
+			uniforms.texture("our_texture", texture, sampler);
+			for (name, map) in material.maps().iter() {
+				match map.and_then(|map| gpu.textures.get(map)) {
+					Some(texture) => {
+						uniforms.texture(*name, texture, sampler);
+						uniforms.int(format!("has_{}", name), 1);
+					}
+					None => uniforms.int(format!("has_{}", name), 0),
+				}
+			}
+
+			match target.draw(&mesh.vertices, &mesh.indices, program, &uniforms, &params) {
+				Ok(()) => stats.drawn += 1,
+				Err(e) => reported.report(format!("Could not draw {:?}: {:?}", model.0, e)),
+			}
+		}
+
+		for (entity, trans, model, material) in (
+			&render_data.entities,
+			&render_data.trans,
+			&render_data.model,
+			&render_data.pbr_materials,
+		)
+			.join()
+		{
+			let (mesh, material) = match (
+				gpu.meshes.get(&model.0),
+				render_data.pbr_material_store.get(&material.0),
+			) {
+				(Some(mesh), Some(material)) => (mesh, material),
+				_ => continue,
+			};
+			if !visible.contains(entity.id()) {
+				stats.culled += 1;
+				continue;
+			}
+
+			let mut uniforms = view_uniforms.clone();
+			uniforms.mat4("model", &trans.0);
+			uniforms.vec4("material.base_color", &material.base_color);
+			uniforms.float("material.metallic", material.metallic);
+			uniforms.float("material.roughness", material.roughness);
+			uniforms.float("material.normal_scale", material.normal_scale);
+			uniforms.float("material.occlusion_strength", material.occlusion_strength);
+			uniforms.vec3("material.emissive", &material.emissive);
+			// Maps that are missing or not uploaded yet are left out
+			for (name, map) in material.color_maps().iter() {
+				match map.and_then(|map| gpu.srgb_textures.get(map)) {
+					Some(texture) => {
+						uniforms.srgb_texture(*name, texture, sampler);
+						uniforms.int(format!("has_{}", name), 1);
+					}
+					None => uniforms.int(format!("has_{}", name), 0),
+				}
+			}
+			for (name, map) in material.data_maps().iter() {
+				match map.and_then(|map| gpu.textures.get(map)) {
+					Some(texture) => {
+						uniforms.texture(*name, texture, sampler);
+						uniforms.int(format!("has_{}", name), 1);
+					}
+					None => uniforms.int(format!("has_{}", name), 0),
+				}
+			}
+
+			match target.draw(
+				&mesh.vertices,
+				&mesh.indices,
+				pbr_program,
+				&uniforms,
+				&params,
+			) {
+				Ok(()) => stats.drawn += 1,
+				Err(e) => reported.report(format!("Could not draw {:?}: {:?}", model.0, e)),
+			}
+		}
+	}
+}
+
+// The values of a `Light` struct in fragment.fs
+struct LightUniform {
+	kind: i32,
+	position: glm::Vec3,
+	direction: glm::Vec3,
+	ambient: glm::Vec3,
+	diffuse: glm::Vec3,
+	specular: glm::Vec3,
+	constant: f32,
+	linear: f32,
+	quadratic: f32,
+	cutoff: f32,
+	outer_cutoff: f32,
+	// Which of the shadow maps of its kind the light uses, -1 for none
+	shadow: i32,
+	light_space: glm::Mat4,
+	far_plane: f32,
+	shadow_bias: f32,
+}
+
+// Light kinds as defined in fragment.fs
+const DIRECTIONAL_LIGHT: i32 = 0;
+const POINT_LIGHT: i32 = 1;
+const SPOT_LIGHT: i32 = 2;
+
+impl LightUniform {
+	fn new(trans: &GlobalTransformationComponent, light: &Light) -> Self {
+		let defaults = Self {
+			kind: DIRECTIONAL_LIGHT,
+			position: trans.get_pos(),
+			direction: glm::vec3(0.0, -1.0, 0.0),
+			ambient: glm::Vec3::zeros(),
+			diffuse: glm::Vec3::zeros(),
+			specular: glm::Vec3::zeros(),
+			constant: 1.0,
+			linear: 0.0,
+			quadratic: 0.0,
+			cutoff: -1.0,
+			outer_cutoff: -1.0,
+			shadow: -1,
+			light_space: glm::Mat4::identity(),
+			far_plane: 1.0,
+			shadow_bias: 0.0,
+		};
+		match *light {
+			Light::DirectionalLight {
+				direction,
+				ambient,
+				diffuse,
+				specular,
+			} => Self {
+				kind: DIRECTIONAL_LIGHT,
+				direction: direction.normalize(),
+				ambient,
+				diffuse,
+				specular,
+				..defaults
+			},
+			Light::PointLight {
+				ambient,
+				diffuse,
+				specular,
+				constant,
+				linear,
+				quadratic,
+			} => Self {
+				kind: POINT_LIGHT,
+				ambient,
+				diffuse,
+				specular,
+				constant,
+				linear,
+				quadratic,
+				..defaults
+			},
+			Light::SpotLight {
+				direction,
+				cutoff,
+				outer_cutoff,
+				ambient,
+				diffuse,
+				specular,
+				constant,
+				linear,
+				quadratic,
+			} => Self {
+				kind: SPOT_LIGHT,
+				direction: direction.normalize(),
+				ambient,
+				diffuse,
+				specular,
+				constant,
+				linear,
+				quadratic,
+				cutoff,
+				outer_cutoff,
+				..defaults
+			},
+		}
+	}
+
+	fn cast_shadow(&mut self, slot: usize, view: &LightView, shadow: &ShadowComponent) {
+		self.shadow = slot as i32;
+		if let LightView::Single(light_space) = view {
+			self.light_space = *light_space;
+		}
+		self.far_plane = shadow.far;
+		self.shadow_bias = shadow.bias;
+	}
+
+	fn add_to(&self, uniforms: &mut UniformList, index: usize) {
+		let field = |name: &str| format!("lights[{}].{}", index, name);
+		uniforms.int(field("kind"), self.kind);
+		uniforms.vec3(field("position"), &self.position);
+		uniforms.vec3(field("direction"), &self.direction);
+		uniforms.vec3(field("ambient"), &self.ambient);
+		uniforms.vec3(field("diffuse"), &self.diffuse);
+		uniforms.vec3(field("specular"), &self.specular);
+		uniforms.float(field("constant"), self.constant);
+		uniforms.float(field("linear"), self.linear);
+		uniforms.float(field("quadratic"), self.quadratic);
+		uniforms.float(field("cutoff"), self.cutoff);
+		uniforms.float(field("outer_cutoff"), self.outer_cutoff);
+		uniforms.int(field("shadow"), self.shadow);
+		uniforms.mat4(field("light_space"), &self.light_space);
+		uniforms.float(field("far_plane"), self.far_plane);
+		uniforms.float(field("shadow_bias"), self.shadow_bias);
+	}
+}
+
+impl<'a> System<'a> for RenderSystem {
+	type SystemData = RenderData<'a>;
 
-					gl::BindVertexArray(model.vao);
-					gl::DrawArrays(gl::TRIANGLES, 0, model.length as i32);
+	fn run(&mut self, mut render_data: Self::SystemData) {
+		let target = self.target.clone();
+		let mut target = target.borrow_mut();
+		let mut stats = RenderStats::default();
+		match target.as_mut() {
+			Some(RenderTarget::Frame(frame)) => self.render(frame, &render_data, &mut stats),
+			Some(RenderTarget::Offscreen(offscreen)) => {
+				match offscreen.framebuffer(&self.context) {
+					Ok(mut framebuffer) => self.render(&mut framebuffer, &render_data, &mut stats),
+					Err(e) => self.reported.report(format!("Could not draw: {}", e)),
 				}
 			}
+			None => {}
 		}
+		*render_data.stats = stats;
 	}
 }
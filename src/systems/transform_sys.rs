@@ -0,0 +1,263 @@
+use specs::prelude::*;
+
+use crate::components::{
+	parent::ParentComponent,
+	transformation::{GlobalTransformationComponent, TransformationComponent},
+};
+
+// Works out the world transformation of every entity from its own and
+// those of its ancestors, walking down from the roots so that parents
+// are always done before their children.
+#[derive(Default)]
+pub struct TransformSystem {
+	// The children of every entity by id, and the entities left to visit
+	// along with their parent's world transformation.
+	// Kept between frames so their memory gets reused.
+	children: Vec<Vec<Entity>>,
+	stack: Vec<(Entity, glm::Mat4)>,
+	visited: BitSet,
+}
+
+impl TransformSystem {
+	// Visit everything on the stack and below, updating world transformations in place
+	fn propagate(
+		&mut self,
+		locals: &mut WriteStorage<TransformationComponent>,
+		globals: &mut WriteStorage<GlobalTransformationComponent>,
+	) {
+		while let Some((entity, base)) = self.stack.pop() {
+			// An entity that was detached from a cycle is still listed under its old parent
+			if self.visited.add(entity.id()) {
+				continue;
+			}
+			let global = match locals.get_mut(entity) {
+				Some(local) => base * local.matrix(),
+				None => continue,
+			};
+			match globals.get_mut(entity) {
+				Some(existing) => existing.0 = global,
+				None => {
+					if let Err(e) = globals.insert(entity, GlobalTransformationComponent(global)) {
+						eprintln!("Could not set world transformation: {:?}", e);
+					}
+				}
+			}
+			if let Some(children) = self.children.get(entity.id() as usize) {
+				self.stack
+					.extend(children.iter().map(|&child| (child, global)));
+			}
+		}
+	}
+}
+
+impl<'a> System<'a> for TransformSystem {
+	type SystemData = (
+		Entities<'a>,
+		WriteStorage<'a, TransformationComponent>,
+		WriteStorage<'a, GlobalTransformationComponent>,
+		WriteStorage<'a, ParentComponent>,
+	);
+
+	fn run(&mut self, (entities, mut locals, mut globals, mut parents): Self::SystemData) {
+		// Children of deleted entities become roots, keeping their last
+		// world transformation so they don't jump.
+		let orphans: Vec<Entity> = (&entities, &parents)
+			.join()
+			.filter(|(_, parent)| !entities.is_alive(parent.0))
+			.map(|(entity, _)| entity)
+			.collect();
+		for orphan in orphans {
+			parents.remove(orphan);
+			if let (Some(local), Some(global)) = (locals.get_mut(orphan), globals.get(orphan)) {
+				local.set_matrix(&global.0);
+			}
+		}
+
+		// Entities without a transformation have no place in the world either
+		let stale: Vec<Entity> = (&entities, &globals, !&locals)
+			.join()
+			.map(|(entity, _, _)| entity)
+			.collect();
+		for entity in stale {
+			globals.remove(entity);
+		}
+
+		for children in &mut self.children {
+			children.clear();
+		}
+		self.visited.clear();
+		for (entity, _, parent) in (&entities, &locals, parents.maybe()).join() {
+			// Parents without a transformation don't move their children
+			match parent.filter(|parent| locals.contains(parent.0)) {
+				Some(parent) => {
+					let index = parent.0.id() as usize;
+					if self.children.len() <= index {
+						self.children.resize_with(index + 1, Vec::new);
+					}
+					self.children[index].push(entity);
+				}
+				None => self.stack.push((entity, glm::Mat4::identity())),
+			}
+		}
+		self.propagate(&mut locals, &mut globals);
+
+		// Whatever wasn't reached from a root has a cycle among its ancestors.
+		// Cutting the cycle where it closes makes that entity a root, so
+		// every cycle is only reported once.
+		let unreached: Vec<Entity> = (&entities, &locals, !&self.visited)
+			.join()
+			.map(|(entity, _, _)| entity)
+			.collect();
+		for entity in unreached {
+			if self.visited.contains(entity.id()) {
+				continue;
+			}
+			let mut chain = Vec::new();
+			let mut current = Some(entity);
+			while let Some(ancestor) = current {
+				if chain.contains(&ancestor) {
+					eprintln!(
+						"Entity {:?} is its own ancestor, detaching it from its parent",
+						ancestor
+					);
+					parents.remove(ancestor);
+					self.stack.push((ancestor, glm::Mat4::identity()));
+					break;
+				}
+				chain.push(ancestor);
+				current = parents.get(ancestor).map(|parent| parent.0);
+			}
+			self.propagate(&mut locals, &mut globals);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn world() -> World {
+		let mut world = World::new();
+		world.register::<TransformationComponent>();
+		world.register::<GlobalTransformationComponent>();
+		world.register::<ParentComponent>();
+		world
+	}
+
+	fn global(world: &World, entity: Entity) -> glm::Mat4 {
+		world
+			.read_storage::<GlobalTransformationComponent>()
+			.get(entity)
+			.unwrap()
+			.0
+	}
+
+	#[test]
+	fn children_follow_their_parents() {
+		let mut world = world();
+		let root = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(1.0, 0.0, 0.0)))
+			.build();
+		let child = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(0.0, 2.0, 0.0)))
+			.with(ParentComponent(root))
+			.build();
+		let grandchild = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(0.0, 0.0, 3.0)))
+			.with(ParentComponent(child))
+			.build();
+
+		let mut system = TransformSystem::default();
+		system.run_now(&world);
+		assert_eq!(
+			global(&world, grandchild),
+			glm::translation(&glm::vec3(1.0, 2.0, 3.0))
+		);
+
+		// Later frames pick up changes in place
+		world
+			.write_storage::<TransformationComponent>()
+			.get_mut(root)
+			.unwrap()
+			.set_pos(glm::vec3(5.0, 0.0, 0.0));
+		system.run_now(&world);
+		assert_eq!(
+			global(&world, grandchild),
+			glm::translation(&glm::vec3(5.0, 2.0, 3.0))
+		);
+	}
+
+	#[test]
+	fn orphans_stay_where_they_were() {
+		let mut world = world();
+		let root = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(1.0, 0.0, 0.0)))
+			.build();
+		let child = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(0.0, 2.0, 0.0)))
+			.with(ParentComponent(root))
+			.build();
+
+		let mut system = TransformSystem::default();
+		system.run_now(&world);
+		world.delete_entity(root).unwrap();
+		world.maintain();
+		system.run_now(&world);
+		assert!(world.read_storage::<ParentComponent>().get(child).is_none());
+		assert_eq!(
+			global(&world, child),
+			glm::translation(&glm::vec3(1.0, 2.0, 0.0))
+		);
+	}
+
+	#[test]
+	fn cycles_are_cut_once() {
+		let mut world = world();
+		let first = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(1.0, 0.0, 0.0)))
+			.build();
+		let second = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(0.0, 2.0, 0.0)))
+			.with(ParentComponent(first))
+			.build();
+		let below = world
+			.create_entity()
+			.with(TransformationComponent::from_pos(glm::vec3(0.0, 0.0, 3.0)))
+			.with(ParentComponent(second))
+			.build();
+		world
+			.write_storage::<ParentComponent>()
+			.insert(first, ParentComponent(second))
+			.unwrap();
+
+		let mut system = TransformSystem::default();
+		system.run_now(&world);
+		// One link of the cycle is gone, and everything has a world transformation
+		let parents = world.read_storage::<ParentComponent>();
+		assert_eq!(
+			[first, second]
+				.iter()
+				.filter(|&&entity| parents.get(entity).is_none())
+				.count(),
+			1
+		);
+		drop(parents);
+		for &entity in &[first, second, below] {
+			assert!(world
+				.read_storage::<GlobalTransformationComponent>()
+				.get(entity)
+				.is_some());
+		}
+		assert_eq!(
+			global(&world, below).column(3).xyz(),
+			glm::vec3(1.0, 2.0, 3.0)
+		);
+	}
+}
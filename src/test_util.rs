@@ -0,0 +1,39 @@
+// Helpers shared by the tests of several modules
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A directory in the temporary directory, unique to this test run and named
+// after the test. It's removed along with everything in it when dropped,
+// including whatever the code under test wrote next to the files.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+	pub fn new(name: &str) -> TempDir {
+		let path = std::env::temp_dir().join(format!("appetizer-{}-{}", std::process::id(), name));
+		// Left over from an earlier run that was killed
+		let _ = fs::remove_dir_all(&path);
+		fs::create_dir_all(&path).unwrap();
+		TempDir(path)
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.0
+	}
+
+	pub fn join(&self, file: &str) -> PathBuf {
+		self.0.join(file)
+	}
+
+	pub fn write(&self, file: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+		let path = self.join(file);
+		fs::write(&path, contents).unwrap();
+		path
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use glium::texture::Texture2d;
+use specs::Entity;
+
+// Textures the renderer made for some entities this frame, to be shown in the overlay
+#[derive(Default)]
+pub struct TextureViews {
+	views: Vec<(Entity, Rc<Texture2d>)>,
+	registered: HashMap<Entity, imgui::TextureId>,
+}
+
+impl TextureViews {
+	pub fn clear(&mut self) {
+		self.views.clear();
+	}
+
+	pub fn push(&mut self, entity: Entity, texture: Rc<Texture2d>) {
+		self.views.push((entity, texture));
+	}
+
+	// Hand this frame's views to the imgui renderer, reusing their ids
+	pub fn register(&mut self, textures: &mut imgui::Textures<Rc<Texture2d>>) {
+		let views: Vec<Entity> = self.views.iter().map(|(entity, _)| *entity).collect();
+		let stale: Vec<Entity> = self
+			.registered
+			.keys()
+			.filter(|entity| !views.contains(entity))
+			.copied()
+			.collect();
+		for entity in stale {
+			if let Some(id) = self.registered.remove(&entity) {
+				textures.remove(id);
+			}
+		}
+		for (entity, texture) in &self.views {
+			match self.registered.get(entity) {
+				Some(id) => {
+					textures.replace(*id, texture.clone());
+				}
+				None => {
+					self.registered
+						.insert(*entity, textures.insert(texture.clone()));
+				}
+			}
+		}
+	}
+
+	// Show the entity's view, if it has one, scaled by `scale`
+	pub fn image(&self, ui: &imgui::Ui, entity: Entity, scale: f32) {
+		let view = self.views.iter().find(|(view, _)| *view == entity);
+		if let (Some((_, texture)), Some(id)) = (view, self.registered.get(&entity)) {
+			let (width, height) = texture.dimensions();
+			// Textures are upside down as far as imgui is concerned
+			imgui::Image::new(ui, *id, [width as f32 * scale, height as f32 * scale])
+				.uv0([0.0, 1.0])
+				.uv1([1.0, 0.0])
+				.build();
+		}
+	}
+}
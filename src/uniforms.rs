@@ -0,0 +1,83 @@
+use glium::texture::{DepthCubemap, DepthTexture2d, SrgbTexture2d, Texture2d};
+use glium::uniforms::{SamplerBehavior, UniformValue, Uniforms};
+
+// Uniforms whose names are only known at runtime, such as the fields of
+// light structs, which the `uniform!` macro can't express.
+#[derive(Default, Clone)]
+pub struct UniformList<'t> {
+	values: Vec<(String, UniformValue<'t>)>,
+}
+
+impl<'t> UniformList<'t> {
+	pub fn new() -> Self {
+		Self { values: Vec::new() }
+	}
+
+	pub fn add<N: Into<String>>(&mut self, name: N, value: UniformValue<'t>) {
+		self.values.push((name.into(), value));
+	}
+
+	pub fn mat4<N: Into<String>>(&mut self, name: N, value: &glm::Mat4) {
+		self.add(name, UniformValue::Mat4(*value.as_ref()));
+	}
+
+	pub fn vec3<N: Into<String>>(&mut self, name: N, value: &glm::Vec3) {
+		self.add(name, UniformValue::Vec3(*value.as_ref()));
+	}
+
+	pub fn vec4<N: Into<String>>(&mut self, name: N, value: &glm::Vec4) {
+		self.add(name, UniformValue::Vec4(*value.as_ref()));
+	}
+
+	pub fn int<N: Into<String>>(&mut self, name: N, value: i32) {
+		self.add(name, UniformValue::SignedInt(value));
+	}
+
+	pub fn float<N: Into<String>>(&mut self, name: N, value: f32) {
+		self.add(name, UniformValue::Float(value));
+	}
+
+	pub fn texture<N: Into<String>>(
+		&mut self,
+		name: N,
+		texture: &'t Texture2d,
+		sampler: SamplerBehavior,
+	) {
+		self.add(name, UniformValue::Texture2d(texture, Some(sampler)));
+	}
+
+	pub fn srgb_texture<N: Into<String>>(
+		&mut self,
+		name: N,
+		texture: &'t SrgbTexture2d,
+		sampler: SamplerBehavior,
+	) {
+		self.add(name, UniformValue::SrgbTexture2d(texture, Some(sampler)));
+	}
+
+	pub fn depth_texture<N: Into<String>>(
+		&mut self,
+		name: N,
+		texture: &'t DepthTexture2d,
+		sampler: SamplerBehavior,
+	) {
+		self.add(name, UniformValue::DepthTexture2d(texture, Some(sampler)));
+	}
+
+	pub fn depth_cubemap<N: Into<String>>(
+		&mut self,
+		name: N,
+		texture: &'t DepthCubemap,
+		sampler: SamplerBehavior,
+	) {
+		self.add(name, UniformValue::DepthCubemap(texture, Some(sampler)));
+	}
+}
+
+impl<'t> Uniforms for UniformList<'t> {
+	fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut visit: F) {
+		for (name, value) in &self.values {
+			visit(name, *value);
+		}
+	}
+}
@@ -1,45 +1,141 @@
 use specs::prelude::*;
 
 use crate::components::*;
+use crate::gltf_loader;
 use crate::obj_parser;
 use crate::resources::*;
 
-fn register_components(mut world: &mut World) {
+fn register_components(world: &mut World) {
 	world.register::<transformation::TransformationComponent>();
+	world.register::<transformation::GlobalTransformationComponent>();
+	world.register::<parent::ParentComponent>();
 	world.register::<model::ModelComponent>();
 	world.register::<name::NameComponent>();
 	world.register::<texture::GLTextureComponent>();
 	// world.register::<shader::ShaderComponent>();
 	world.register::<material::MaterialComponent>();
+	world.register::<pbr_material::PbrMaterialComponent>();
 	world.register::<light::LightComponent>();
+	world.register::<shadow::ShadowComponent>();
+	world.register::<scene_camera::CameraComponent>();
 }
 
-fn insert_resources(mut world: &mut World) {
+fn insert_resources(world: &mut World) {
 	world.insert(delta_time::DeltaTime(0.0));
 	world.insert(key_state::Keystate::default());
 	world.insert(camera::Camera::default());
+	world.insert(capture::Capture::default());
 	world.insert(texture_map::TextureMap::new());
-	world.insert(texture_map::GLTextureMap::new());
+	world.insert(mesh_store::MeshStore::new());
+	world.insert(bvh::Bvh::default());
+	world.insert(material_store::MaterialStore::new());
+	world.insert(material_store::PbrMaterialStore::new());
+	world.insert(shader_store::ShaderStore::new());
 	world.insert(projection::Projection::default());
+	world.insert(render_stats::RenderStats::default());
 	world.insert(mouse_state::MouseState::default());
+	world.insert(window_size::WindowSize::default());
+	world.insert(selected_entity::SelectedEntity::default());
 	world.insert(time::CurrentTime::default());
 }
 
-pub fn create_world() -> Result<World, String> {
+// Assets are keyed by their place in the OBJ file,
+// so spawning the same file again shares them.
+fn submesh_key(path: &str, index: usize, submesh: &obj_parser::SubMesh) -> String {
+	match &submesh.material {
+		Some(material) => format!("{}#{}", path, material),
+		None => format!("{}#{}", path, index),
+	}
+}
+
+// Spawn an entity for every material used by an OBJ model.
+// Submeshes without a diffuse map are drawn with `fallback_texture`.
+fn spawn_obj(
+	world: &mut World,
+	path: &str,
+	trans: &transformation::TransformationComponent,
+	name: &str,
+	fallback_texture: &texture_map::TextureHandle,
+) -> Result<Vec<Entity>, String> {
+	let mode = *world.read_resource::<obj_parser::ParseMode>();
+	let model =
+		obj_parser::load(path.to_string(), mode).map_err(|e| format!("Parser error: {}", e))?;
+	for warning in &model.warnings {
+		eprintln!("Warning: {}", warning);
+	}
+
+	let mut entities = Vec::new();
+	for (i, submesh) in model.submeshes.iter().enumerate() {
+		let key = submesh_key(path, i, submesh);
+		let (material, texture) = {
+			let mut texture_map = world.write_resource::<texture_map::TextureMap>();
+			let mut material_store = world.write_resource::<material_store::MaterialStore>();
+
+			let material = material_store.load(&key, |_| match model.material(submesh) {
+				Some(mtl) => mtl.to_material(&mut texture_map, mode),
+				None => Ok(material_store::Material::default()),
+			})?;
+			let diffuse_map = material_store
+				.get(&material)
+				.and_then(|material| material.diffuse_map.clone());
+			let texture = diffuse_map.unwrap_or_else(|| fallback_texture.clone());
+			(material, texture)
+		};
+
+		let mesh = world
+			.write_resource::<mesh_store::MeshStore>()
+			.load(&key, |_| Ok::<_, String>(submesh.object.get_mesh()))?;
+
+		let entity_name = match (&submesh.material, model.submeshes.len()) {
+			(Some(material), n) if n > 1 => format!("{} ({})", name, material),
+			_ => name.to_string(),
+		};
+
+		entities.push(
+			world
+				.create_entity()
+				.with(trans.clone())
+				.with(material::MaterialComponent(material))
+				.with(model::ModelComponent(mesh))
+				.with(texture::GLTextureComponent(texture))
+				.with(name::NameComponent(entity_name))
+				.build(),
+		);
+	}
+	Ok(entities)
+}
+
+// Load an OBJ model again and swap the new geometry in behind the
+// existing mesh handles, returning the meshes that changed.
+pub fn reload_obj(
+	mesh_store: &mut mesh_store::MeshStore,
+	path: &str,
+	mode: obj_parser::ParseMode,
+) -> Result<Vec<asset_store::HandleId>, String> {
+	let model =
+		obj_parser::load(path.to_string(), mode).map_err(|e| format!("Parser error: {}", e))?;
+	let mut changed = Vec::new();
+	for (i, submesh) in model.submeshes.iter().enumerate() {
+		if let Some(handle) = mesh_store.find(&submesh_key(path, i, submesh)) {
+			mesh_store.replace(&handle, submesh.object.get_mesh());
+			changed.push(handle.id());
+		}
+	}
+	Ok(changed)
+}
+
+pub fn create_world(scenes: &[String], mode: obj_parser::ParseMode) -> Result<World, String> {
 	// Initialized everything
 	let mut world: World = World::new();
 
 	register_components(&mut world);
 	insert_resources(&mut world);
+	// How OBJ and MTL files are parsed, reloads included
+	world.insert(mode);
 
 	// let shader = shader::ShaderComponent::new("vertex.vs", "fragment.fs")
 	// 	.map_err(|e| format!("Shader error: {:?}", e))?;
 
-	let teapot = obj_parser::parse("objs/teapot.obj".to_string())
-		.map_err(|e| format!("Parser error: {:?}", e))?;
-	let cube = obj_parser::parse("objs/cube.obj".to_string())
-		.map_err(|e| format!("Parser error: {:?}", e))?;
-
 	{
 		let mut camera = world.write_resource::<camera::Camera>();
 		camera.update_camera_vectors();
@@ -48,65 +144,121 @@ pub fn create_world() -> Result<World, String> {
 		let mut texture_map = world.write_resource::<texture_map::TextureMap>();
 		texture_map.load_from_file("assets/textures/white.png".to_string())?
 	};
-	let white_gltexture_handle = {
-		let texture_map = world.read_resource::<texture_map::TextureMap>();
-		let mut gltexture_map = world.write_resource::<texture_map::GLTextureMap>();
-
-		gltexture_map.load_from_map(&texture_map, white_texture_handle)?
-	};
-	let wall_texture_handle = {
-		let mut texture_map = world.write_resource::<texture_map::TextureMap>();
-		texture_map.load_from_file("assets/textures/wall.jpg".to_string())?
-	};
-	let wall_gltexture_handle = {
-		let texture_map = world.read_resource::<texture_map::TextureMap>();
-		let mut gltexture_map = world.write_resource::<texture_map::GLTextureMap>();
+	spawn_obj(
+		&mut world,
+		"objs/teapot.obj",
+		&transformation::TransformationComponent::default(),
+		"Alpha",
+		&white_texture_handle,
+	)?;
 
-		gltexture_map.load_from_map(&texture_map, wall_texture_handle)?
-	};
+	let light_entities = spawn_obj(
+		&mut world,
+		"objs/cube.obj",
+		&transformation::TransformationComponent::from_pos(glm::vec3(100.0, 50.0, 0.0)),
+		"Random Light",
+		&white_texture_handle,
+	)?;
+	{
+		let mut lights = world.write_storage::<light::LightComponent>();
+		for entity in light_entities {
+			lights
+				.insert(
+					entity,
+					light::LightComponent(light::Light::PointLight {
+						ambient: glm::vec3(1.0, 1.0, 1.0),
+						diffuse: glm::vec3(1.0, 1.0, 1.0),
+						specular: glm::vec3(1.0, 1.0, 1.0),
+						// Reaches about 300 units
+						constant: 1.0,
+						linear: 0.014,
+						quadratic: 0.0007,
+					}),
+				)
+				.map_err(|e| format!("Could not attach light: {:?}", e))?;
+		}
+	}
 
 	world
 		.create_entity()
-		.with(transformation::TransformationComponent(glm::mat4(
-			1.0, 0.0, 0.0, 0.0, //
-			0.0, 1.0, 0.0, 0.0, //
-			0.0, 0.0, 1.0, 0.0, //
-			0.0, 0.0, 0.0, 1.0, //
-		)))
-		.with(material::MaterialComponent {
-			ambient: glm::vec3(0.1, 0.1, 0.1),
+		.with(transformation::TransformationComponent::from_pos(
+			glm::Vec3::zeros(),
+		))
+		.with(light::LightComponent(light::Light::DirectionalLight {
+			direction: glm::vec3(-0.2, -1.0, -0.3),
+			ambient: glm::vec3(0.2, 0.2, 0.2),
 			diffuse: glm::vec3(0.5, 0.5, 0.5),
-			specular: glm::vec3(0.8, 0.8, 0.8),
-			shininess: 32.0,
-		})
-		.with(teapot.get_component())
-		.with(texture::GLTextureComponent(wall_gltexture_handle))
-		.with(name::NameComponent("Alpha".to_string()))
+			specular: glm::vec3(0.5, 0.5, 0.5),
+		}))
+		.with(shadow::ShadowComponent::default())
+		.with(name::NameComponent("Sun".to_string()))
 		.build();
 
 	world
 		.create_entity()
 		.with(transformation::TransformationComponent::from_pos(
-			glm::vec3(0.0, 1000.0, 0.0),
+			glm::vec3(0.0, 40.0, 0.0),
 		))
-		.with(material::MaterialComponent {
-			ambient: glm::vec3(1.0, 0.5, 0.5),
-			diffuse: glm::vec3(0.0, 0.0, 0.0),
-			specular: glm::vec3(0.0, 0.0, 0.0),
-			shininess: 32.0,
-		})
-		.with(cube.get_component())
-		.with(texture::GLTextureComponent(white_gltexture_handle))
-		.with(light::LightComponent(light::Light::PointLight {
-			ambient: glm::vec3(1.0, 1.0, 1.0),
+		.with(light::LightComponent(light::Light::SpotLight {
+			direction: glm::vec3(0.0, -1.0, 0.0),
+			cutoff: 12.5f32.to_radians().cos(),
+			outer_cutoff: 17.5f32.to_radians().cos(),
+			ambient: glm::vec3(0.0, 0.0, 0.0),
 			diffuse: glm::vec3(1.0, 1.0, 1.0),
 			specular: glm::vec3(1.0, 1.0, 1.0),
 			constant: 1.0,
-			linear: 0.09,
-			quadratic: 0.032,
+			linear: 0.014,
+			quadratic: 0.0007,
 		}))
-		.with(name::NameComponent("Random Light".to_string()))
+		.with(shadow::ShadowComponent::default())
+		.with(name::NameComponent("Spot".to_string()))
 		.build();
 
+	// Extra cameras, switched on from the "Cameras" overlay
+	let mut top = transformation::TransformationComponent::from_pos(glm::vec3(0.0, 200.0, 0.0));
+	top.look_at(&glm::Vec3::zeros(), &glm::vec3(0.0, 0.0, -1.0));
+	world
+		.create_entity()
+		.with(top)
+		.with(scene_camera::CameraComponent {
+			projection: projection::Projection::orthographic(120.0),
+			viewport: glm::vec4(0.7, 0.7, 0.28, 0.28),
+			priority: 1,
+			clear_color: Some(glm::vec4(0.1, 0.1, 0.15, 1.0)),
+			active: false,
+			..Default::default()
+		})
+		.with(name::NameComponent("Top view".to_string()))
+		.build();
+	let mut side = transformation::TransformationComponent::from_pos(glm::vec3(150.0, 60.0, 0.0));
+	side.look_at(&glm::Vec3::zeros(), &glm::vec3(0.0, 1.0, 0.0));
+	world
+		.create_entity()
+		.with(side)
+		.with(scene_camera::CameraComponent {
+			active: false,
+			target: scene_camera::CameraTarget::Texture {
+				width: 512,
+				height: 288,
+			},
+			..Default::default()
+		})
+		.with(name::NameComponent("Side view".to_string()))
+		.build();
+
+	for scene in scenes {
+		if scene.ends_with(".obj") {
+			spawn_obj(
+				&mut world,
+				scene,
+				&transformation::TransformationComponent::default(),
+				scene,
+				&white_texture_handle,
+			)?;
+		} else {
+			gltf_loader::import(&mut world, scene)?;
+		}
+	}
+
 	Ok(world)
 }
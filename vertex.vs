@@ -2,10 +2,12 @@
 layout (location = 0) in vec3 position;
 layout (location = 1) in vec3 normal;
 layout (location = 2) in vec2 uv;
+layout (location = 3) in vec4 tangent;
 out vec4 FragColor;
 out vec2 TexCoord;
 out vec3 FragPos;
 out vec3 Normal;
+out vec4 Tangent;
 
 uniform mat4 camera;
 
@@ -14,7 +16,9 @@ uniform mat4 model;
 
 void main() {
 	TexCoord = uv;
-	Normal = normal;
+	Normal = mat3(transpose(inverse(model))) * normal;
+	// Tangents lie along the surface, so they transform like positions do
+	Tangent = vec4(mat3(model) * tangent.xyz, tangent.w);
 	FragPos = vec3(model * vec4(position, 1.0));
 
 	gl_Position = projection * camera * model * vec4(position, 1.0);